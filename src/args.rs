@@ -1,9 +1,20 @@
+use crate::score::{Formula, parse_formula};
 use clap::Parser;
-use clap::ValueEnum;
+use clap::builder::ArgPredicate;
+use clap::{ArgGroup, Subcommand, ValueEnum};
 use clap_complete::Shell;
+use ipnetwork::IpNetwork;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// Checkpoint file used by `--resume`.
+const DEFAULT_CHECKPOINT_FILE: &str = "./warp-checkpoint.jsonl";
 
 #[derive(Parser)]
 #[command(version, about, long_about = None)]
+#[command(group(ArgGroup::new("every_port").args(["all_ports", "probe_all_ports"])))]
 pub(crate) struct Args {
     #[arg(short = 't', long, default_value_t = 100)]
     pub(crate) threads: usize,
@@ -11,20 +22,1397 @@ pub(crate) struct Args {
     #[arg(short = 'n', long, default_value_t = 100)]
     pub(crate) addresses: usize,
 
-    #[arg(short = 'c', long, default_value_t = 3)]
+    #[arg(
+        short = 'c',
+        long,
+        default_value_t = 3,
+        default_value_ifs([
+            ("fast_mode", ArgPredicate::Equals("true".into()), "1"),
+            ("thorough_mode", ArgPredicate::Equals("true".into()), "15"),
+        ])
+    )]
     pub(crate) attempts: u32,
 
+    /// Probe every address once and print working ones as they are found
+    #[arg(long, conflicts_with = "attempts")]
+    pub(crate) fast_mode: bool,
+
+    /// Probe every address 15 times, leave out outliers, rank by the median
+    /// and report every latency statistic with a 95% confidence interval
+    #[arg(long, conflicts_with_all = ["attempts", "fast_mode"])]
+    pub(crate) thorough_mode: bool,
+
+    /// Estimate a good --attempts with a pilot scan of 5 IPs before scanning
+    ///
+    /// The pilot IPs count toward --addresses.
+    #[arg(long)]
+    pub(crate) recommend_attempts: bool,
+
+    /// Latency accuracy --recommend-attempts aims for
+    #[arg(long, value_name = "MS", default_value_t = 5.0, requires = "recommend_attempts",
+          value_parser = parse_margin)]
+    pub(crate) margin_of_error_ms: f64,
+
+    /// Successful probes needed for an address to count as working
+    #[arg(long, value_name = "N", default_value_t = 1, value_parser = clap::value_parser!(u32).range(1..))]
+    pub(crate) min_sample: u32,
+
+    /// Keep probing addresses whose latency varies more than this
+    ///
+    /// Once --attempts probes are done, addresses whose coefficient of
+    /// variation exceeds the threshold get up to --max-extra-attempts more.
+    #[arg(long, value_name = "CV", num_args = 0..=1, default_missing_value = "0.5")]
+    pub(crate) cv_threshold: Option<f64>,
+
+    /// Most extra probes per address with --cv-threshold
+    #[arg(long, default_value_t = 3)]
+    pub(crate) max_extra_attempts: u32,
+
+    /// Probe each address for this many milliseconds instead of --attempts times
+    ///
+    /// Fast addresses get more probes: with 100, one answering in 10 ms is
+    /// probed about 10 times, one answering in 100 ms once.
+    #[arg(long, value_name = "MS", conflicts_with = "cv_threshold",
+          value_parser = clap::value_parser!(u64).range(1..))]
+    pub(crate) attempts_per_ms: Option<u64>,
+
+    /// Most probes per address with --attempts-per-ms
+    #[arg(long, value_name = "N", default_value_t = 10, requires = "attempts_per_ms",
+          value_parser = clap::value_parser!(u32).range(1..))]
+    pub(crate) max_attempts: u32,
+
+    /// Probe the N best results again, with max(3 * --attempts, 10) probes
+    /// each, and report their latency from that instead. Results more than
+    /// --verify-threshold-pct slower the second time are flagged unstable.
+    /// The N are then ranked again as --score, --score-formula or --sort-by say.
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u64).range(1..))]
+    pub(crate) verify_top_n: Option<u64>,
+
+    /// How much slower, in percent, a result may get during --verify-top-n
+    /// before it is flagged unstable
+    #[arg(
+        long,
+        value_name = "PCT",
+        default_value_t = 20.0,
+        requires = "verify_top_n"
+    )]
+    pub(crate) verify_threshold_pct: f64,
+
+    /// Bootstrap a confidence interval of every latency, in percent, e.g. 95
+    ///
+    /// Needs at least 2 successful probes per address. Shown in verbose text
+    /// output and as `ci_low`/`ci_high` in JSON.
+    #[arg(
+        long,
+        value_name = "PCT",
+        value_parser = parse_confidence,
+        default_value_if("thorough_mode", ArgPredicate::Equals("true".into()), "95")
+    )]
+    pub(crate) confidence_interval: Option<f64>,
+
+    /// Flag addresses whose probes form two latency clusters more than 20 ms
+    /// apart, a sign of ECMP routing over different paths
+    #[arg(long)]
+    pub(crate) ecmp_detect: bool,
+
+    /// Leave probes outside Tukey's fences out of the latency and jitter
+    ///
+    /// Needs at least 4 successful probes per address to have an effect.
+    #[arg(
+        long,
+        default_value_if("thorough_mode", ArgPredicate::Equals("true".into()), "true")
+    )]
+    pub(crate) remove_outliers: bool,
+
+    /// Number of best results to show and export, 1 with --numeric
+    #[arg(
+        long,
+        default_value_t = 5,
+        default_value_if("numeric", ArgPredicate::IsPresent, "1")
+    )]
+    pub(crate) top: usize,
+
+    /// Unit latencies are measured and reported in
+    ///
+    /// us resolves the sub-millisecond round trips of a LAN, which ms floors
+    /// to 0. Latency options such as --max-latency stay in milliseconds.
+    #[arg(long, value_enum, default_value_t = LatencyUnit::Ms)]
+    pub(crate) latency_unit: LatencyUnit,
+
+    /// Ignore working addresses slower than this many milliseconds
+    #[arg(long, value_name = "MS")]
+    pub(crate) max_latency: Option<u128>,
+
+    /// Drop working addresses within this many milliseconds of a faster one
+    /// that was kept, so the top results are distinct latency tiers
+    #[arg(long, value_name = "MS")]
+    pub(crate) dedup_threshold_ms: Option<u128>,
+
+    /// Keep at most this many of the fastest working addresses per /24 (or /48)
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u64).range(1..))]
+    pub(crate) limit_per_subnet: Option<u64>,
+
+    /// Keep only the N fastest working addresses in memory during the scan,
+    /// 0 keeps all
+    ///
+    /// Slower ones are dropped as faster ones arrive, so filters, statistics
+    /// and --top only see the N kept. The working address count still
+    /// includes the dropped ones.
+    #[arg(long, value_name = "N", default_value_t = 0)]
+    pub(crate) result_limit: usize,
+
+    /// Stop scanning once this many working addresses are found
+    ///
+    /// With --max-latency only addresses below it count.
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u64).range(1..))]
+    pub(crate) stop_after: Option<u64>,
+
+    /// Skip the rest of a /24 (or /48) once N addresses in a row in it failed
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u32).range(1..))]
+    pub(crate) skip_subnet_after: Option<u32>,
+
+    /// Skip the rest of a /24 (or /48) once N working addresses in it are found
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u32).range(1..))]
+    pub(crate) stop_after_n_per_subnet: Option<u32>,
+
+    /// Stop scanning once every /24 (or /48) being scanned has a working address
+    ///
+    /// With --max-latency only addresses below it count. --stop-after still
+    /// stops the scan earlier if reached first.
+    #[arg(long)]
+    pub(crate) stop_after_first_per_subnet: bool,
+
+    /// Probe the addresses of at most N /24s (or /48s) at a time
+    ///
+    /// For telling subnet-level throttling apart from slow addresses, with 1
+    /// every subnet is probed on its own.
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u32).range(1..))]
+    pub(crate) concurrent_subnets: Option<u32>,
+
+    /// Exit with an error right away if the very first probe fails
+    ///
+    /// For CI checks that WARP is reachable at all, with --addresses 1 or
+    /// --whitelist-file. A timeout counts as a failure too.
+    #[arg(long, conflicts_with = "rounds")]
+    pub(crate) fail_fast: bool,
+
+    /// Probe addresses whose every probe failed to send once more after the
+    /// scan
+    ///
+    /// Send errors like ENETUNREACH during a routing flap are usually gone a
+    /// moment later, unlike timeouts. Addresses that fail to send again stay
+    /// system errors.
+    #[arg(long)]
+    pub(crate) retest_failed: bool,
+
+    /// Stop at the first working address, with --max-latency the first below it
+    ///
+    /// Unlike --stop-after 1, in-flight probes are abandoned right away.
+    #[arg(long, conflicts_with = "stop_after")]
+    pub(crate) first: bool,
+
+    /// How long in-flight probes may still finish after --stop-after or
+    /// --stop-after-first-per-subnet is reached
+    #[arg(long, value_name = "MS", default_value_t = 500)]
+    pub(crate) stop_after_grace_ms: u64,
+
+    /// Wait at most twice the first probe's round trip for later probes to
+    /// the same address, instead of the full 1 s timeout
+    ///
+    /// The first probe always gets the full timeout, and no adaptive timeout
+    /// is shorter than 50 ms. Not applied to the --use-mmsg prober.
+    #[arg(long)]
+    pub(crate) adaptive_timeout: bool,
+
+    /// Delay in milliseconds between consecutive attempts to the same address
+    ///
+    /// Each address then takes up to `attempts × (timeout + delay)` to test.
+    #[arg(long, default_value_t = 0)]
+    pub(crate) inter_attempt_delay: u64,
+
+    /// Wait at least this many milliseconds between any two probes sent
+    ///
+    /// Unlike --inter-attempt-delay this spaces out the probes of all threads,
+    /// so at most one is sent per interval.
+    #[arg(long, value_name = "MS")]
+    pub(crate) send_interval: Option<u64>,
+
+    /// Accept responses of at least this many bytes, instead of exactly the 92
+    /// of a handshake response
+    #[arg(long, value_name = "BYTES", requires = "response_size_max",
+          value_parser = clap::value_parser!(u8).range(1..))]
+    pub(crate) response_size_min: Option<u8>,
+
+    /// Accept responses of at most this many bytes, up to 255
+    #[arg(long, value_name = "BYTES", requires = "response_size_min",
+          value_parser = clap::value_parser!(u8).range(1..))]
+    pub(crate) response_size_max: Option<u8>,
+
+    /// Accept responses coming from another address than the probed one
+    ///
+    /// Needed behind asymmetric NAT. Probes then wait for the full timeout
+    /// on closed ports instead of failing on ICMP port unreachable. Responses
+    /// are always matched by source with `--use-mmsg`.
+    #[arg(long)]
+    pub(crate) allow_src_mismatch: bool,
+
+    /// Send probes from a fixed local UDP port
+    ///
+    /// Each concurrent worker binds its own port, starting at this one and
+    /// counting up to `port + threads - 1`, or up to
+    /// `port + threads * port-concurrency - 1` with --probe-all-ports. Use
+    /// `--threads 1` when only this exact port is allowed through the
+    /// firewall. --verify-top-n, --ping-interval, --recommend-attempts and
+    /// --test-connectivity probe from this port too, one address at a time.
+    #[arg(long)]
+    pub(crate) source_port: Option<u16>,
+
+    /// Receive buffer size of every probe socket in bytes, 0 keeps the OS default
+    ///
+    /// Raise it when scanning with many threads drops responses.
+    #[arg(long, value_name = "BYTES", default_value_t = 0)]
+    pub(crate) recv_buf_size: usize,
+
+    /// Set SO_MARK on every probe socket for policy routing, e.g. `0x100`
+    ///
+    /// Linux only, needs CAP_NET_ADMIN. Ignored on other systems.
+    #[arg(long, value_name = "MARK", value_parser = parse_mark)]
+    pub(crate) socket_mark: Option<u32>,
+
+    /// Set SO_PRIORITY on every probe socket for QoS, from 0 to 6
+    ///
+    /// Linux only. Ignored on other systems.
+    #[arg(long, value_name = "PRIORITY", value_parser = clap::value_parser!(u32).range(0..=6))]
+    pub(crate) socket_priority: Option<u32>,
+
+    /// Send every probe through this network interface, e.g. `eth0`
+    ///
+    /// Linux only, needs CAP_NET_RAW. Ignored on other systems.
+    #[arg(long, value_name = "IFNAME")]
+    pub(crate) bind_device: Option<String>,
+
+    /// Probe link-local IPv6 addresses (fe80::/10) through this network
+    /// interface, e.g. `eth0`
+    ///
+    /// Sets the scope ID of every such address to the index of the interface.
+    /// Only resolved on Linux.
+    #[arg(long, value_name = "IFNAME")]
+    pub(crate) ipv6_scope: Option<String>,
+
+    /// Send buffer size of every probe socket in bytes, 0 keeps the OS default
+    #[arg(long, value_name = "BYTES", default_value_t = 0)]
+    pub(crate) send_buf_size: usize,
+
+    /// Probe over one shared socket per IP version, draining responses in
+    /// batches with recvmmsg
+    #[cfg(all(feature = "mmsg", target_os = "linux"))]
+    #[arg(long)]
+    pub(crate) use_mmsg: bool,
+
+    /// How addresses are sampled from the IPv4 ranges
+    #[arg(
+        long,
+        value_enum,
+        default_value_t = SampleStrategy::Weighted,
+        default_value_if("uniform_subnets", ArgPredicate::Equals("true".into()), "uniform")
+    )]
+    pub(crate) sample_strategy: SampleStrategy,
+
+    /// Sample the same number of addresses from every subnet, same as
+    /// --sample-strategy uniform
+    #[arg(long, conflicts_with = "sample_strategy")]
+    pub(crate) uniform_subnets: bool,
+
+    /// Sample at most N addresses from any single IPv4 range
+    ///
+    /// What a capped range would have gotten goes to the others instead. If
+    /// the ranges cannot make up --addresses this way, fewer are tested.
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u32).range(1..))]
+    pub(crate) max_addresses_per_cidr: Option<u32>,
+
+    /// Rank working addresses by this expression of their `latency` and
+    /// `jitter` in ms and `loss` in percent instead, lowest first
+    ///
+    /// e.g. `"latency * 0.6 + loss * 200 + jitter * 0.4"`. Overrides --sort-by.
+    #[arg(long, value_name = "EXPR", value_parser = parse_formula)]
+    pub(crate) score_formula: Option<Formula>,
+
+    /// Rank working addresses by a weighted sum of their latency, loss and
+    /// jitter, lowest first
+    ///
+    /// `latency_ms * --weight-latency + loss_pct * --weight-loss + jitter_ms *
+    /// --weight-jitter`. Overrides --sort-by.
+    #[arg(long, conflicts_with = "score_formula")]
+    pub(crate) score: bool,
+
+    /// Weight of the latency in ms in --score
+    #[arg(long, value_name = "W", default_value_t = 1.0, requires = "score")]
+    pub(crate) weight_latency: f64,
+
+    /// Weight of the loss in percent in --score
+    #[arg(long, value_name = "W", default_value_t = 200.0, requires = "score")]
+    pub(crate) weight_loss: f64,
+
+    /// Weight of the jitter in ms in --score
+    #[arg(long, value_name = "W", default_value_t = 0.5, requires = "score")]
+    pub(crate) weight_jitter: f64,
+
+    /// Rank working addresses by this
+    #[arg(
+        long,
+        value_enum,
+        default_value_t = SortBy::Latency,
+        default_value_if("jitter_only", ArgPredicate::Equals("true".into()), "jitter")
+    )]
+    pub(crate) sort_by: SortBy,
+
+    /// Rank by jitter and show it first, for VoIP and gaming
+    ///
+    /// Implies --sort-by jitter. Needs at least 2 --attempts.
+    #[arg(long, conflicts_with = "fast_mode")]
+    pub(crate) jitter_only: bool,
+
+    /// Ignore working addresses with more jitter than this many milliseconds
+    #[arg(long, value_name = "MS")]
+    pub(crate) max_jitter: Option<f64>,
+
+    /// Order the sampled addresses are tested in
+    #[arg(long, value_enum, default_value_t = Order::Random)]
+    pub(crate) order: Order,
+
+    /// Seed the address sampling, so the same arguments test the same addresses.
+    /// Every round and --watch cycle still draws its own sample
+    #[arg(long)]
+    pub(crate) seed: Option<u64>,
+
+    /// Test these ports instead of the known WARP ones, e.g. `500-600,2408`
+    ///
+    /// Ranges include both ends. At most 1000 ports can be given.
+    // The full path keeps clap from taking the list as one value per port
+    #[arg(long, value_name = "PORTS", value_parser = parse_ports)]
+    pub(crate) ports: Option<::std::vec::Vec<u16>>,
+
+    /// Never test this port, can be repeated
+    #[arg(long, value_name = "PORT")]
+    pub(crate) exclude_port: Vec<u16>,
+
+    /// Test every IP on the first port instead of a random one
+    #[arg(long, conflicts_with_all = ["port_weights", "all_ports", "probe_all_ports"])]
+    pub(crate) no_randomize: bool,
+
+    /// Bias the random port choice, e.g. `500:3,2408:5`
+    ///
+    /// Ports left out keep weight 1, weight 0 excludes a port.
+    #[arg(long, value_name = "PORT:WEIGHT", value_delimiter = ',', value_parser = parse_port_weight)]
+    pub(crate) port_weights: Vec<(u16, u32)>,
+
+    /// Probe every sampled IP on every port instead of a random one
+    #[arg(long)]
+    pub(crate) all_ports: bool,
+
+    /// Like --all-ports, but rank every IP by its fastest port only
+    ///
+    /// --threads IPs are probed at a time, each on up to --port-concurrency
+    /// ports at once.
+    #[arg(long, conflicts_with = "all_ports")]
+    pub(crate) probe_all_ports: bool,
+
+    /// Ports of one IP probed at once with --probe-all-ports
+    #[arg(long, value_name = "N", default_value_t = 4, requires = "probe_all_ports",
+          value_parser = clap::value_parser!(u32).range(1..))]
+    pub(crate) port_concurrency: u32,
+
+    /// With --all-ports or --probe-all-ports, keep only IPs where at least
+    /// this many ports responded
+    #[arg(long, value_name = "N", requires = "every_port")]
+    pub(crate) min_ports_alive: Option<usize>,
+
+    /// Skip checking that UDP traffic reaches 1.1.1.1 before scanning, and
+    /// the --test-connectivity check with it
+    #[arg(long)]
+    pub(crate) skip_preflight: bool,
+
+    /// Warn if the outgoing interface's MTU is below the 1280 bytes WireGuard
+    /// needs
+    #[arg(long)]
+    pub(crate) mtu_check: bool,
+
+    /// Also check that 162.159.192.1:2408 answers a handshake before scanning,
+    /// within the fixed 1 s probe timeout
+    #[arg(long)]
+    pub(crate) test_connectivity: bool,
+
+    /// Skip only the --test-connectivity check, still checking 1.1.1.1
+    #[arg(long)]
+    pub(crate) skip_preflight_check: bool,
+
+    /// Scan this range instead of the built-in ones, can be repeated
+    ///
+    /// Takes a CIDR, a single IP or a hostname, which is resolved to the
+    /// addresses it points to. IPv6 ranges replace the built-in ones that
+    /// --mode ipv6 embeds the IPv4 addresses in.
+    #[arg(long, value_name = "CIDR|HOST", conflicts_with = "fetch_cidrs")]
+    pub(crate) cidr: Vec<String>,
+
+    /// Replace the built-in IPv4 ranges with those listed in this file
+    ///
+    /// One CIDR per line, `#` starts a comment. --fetch-cidrs falls back to
+    /// them instead of the built-in ones.
+    #[arg(long, value_name = "PATH", conflicts_with = "cidr")]
+    pub(crate) ipv4_ranges_file: Option<PathBuf>,
+
+    /// Replace the built-in IPv6 ranges with those listed in this file
+    ///
+    /// One CIDR per line, `#` starts a comment. --mode ipv6 and dual embed the
+    /// sampled IPv4 addresses in them, IPv6 ranges from --cidr take
+    /// precedence.
+    #[arg(long, value_name = "PATH")]
+    pub(crate) ipv6_ranges_file: Option<PathBuf>,
+
+    /// Scan this block as well as the built-in ranges, can be repeated
+    ///
+    /// Unlike --cidr the built-in ranges, or those from --cidr and
+    /// --fetch-cidrs, are kept. Addresses are drawn evenly from all of them,
+    /// so larger blocks get more. --exclude-subnet still applies.
+    #[arg(long, value_name = "CIDR")]
+    pub(crate) extra_cidr: Vec<IpNetwork>,
+
+    /// Fetch the current WARP ranges from Cloudflare's published IP list
+    ///
+    /// The list is cached in ~/.cache/warp-speedtest for a day. If it cannot be
+    /// fetched, the cached or built-in ranges are used instead.
+    #[arg(long)]
+    pub(crate) fetch_cidrs: bool,
+
+    /// Never test addresses listed in this file
+    ///
+    /// One IP or CIDR per line, `#` starts a comment.
+    #[arg(long, value_name = "PATH")]
+    pub(crate) blacklist_file: Option<PathBuf>,
+
+    /// Never test addresses in this block, can be repeated
+    #[arg(long, value_name = "CIDR")]
+    pub(crate) exclude_subnet: Vec<IpNetwork>,
+
+    /// Test only the addresses listed in this file instead of random ones
+    ///
+    /// One IP, ip:port or CIDR per line, `#` starts a comment. Entries without
+    /// a port are tested on a random one. At most --addresses are tested.
+    #[arg(long, value_name = "PATH")]
+    pub(crate) whitelist_file: Option<PathBuf>,
+
+    /// Re-test the addresses saved by a previous --output-file
+    ///
+    /// Reads `.json`, `.csv` and `.tsv` files in the format they were saved
+    /// in, CSV with the --export-csv-sep separator, and anything else as one
+    /// ip:port per line. At most --addresses are tested.
+    #[arg(long, value_name = "PATH", conflicts_with = "whitelist_file")]
+    pub(crate) input_file: Option<PathBuf>,
+
+    /// Test every address of --input-file or --whitelist-file in file order
+    ///
+    /// Ignores --addresses and implies --all-results, to health-check known
+    /// endpoints. CIDR ranges in the list are rejected.
+    #[arg(long)]
+    pub(crate) fixed_ips: bool,
+
     /// IP version
     #[arg(long, value_enum, default_value_t = SpeedTestMode::Ipv4)]
     pub(crate) mode: SpeedTestMode,
 
+    /// With --mode dual, compare every IPv4 address to its IPv6 twin
+    #[arg(long)]
+    pub(crate) compare_v4_v6: bool,
+
+    /// Number of independent scan rounds, each with a fresh address sample.
+    /// An IP probed on different ports across rounds counts as one address,
+    /// except with --all-ports
+    #[arg(long, default_value_t = 1, value_parser = clap::value_parser!(u32).range(1..))]
+    pub(crate) rounds: u32,
+
+    /// Only report addresses seen in at least this many rounds
+    #[arg(long, default_value_t = 1)]
+    pub(crate) min_rounds_present: usize,
+
+    /// Weight of each new round in the smoothed latency across rounds
+    ///
+    /// `1.0` ranks by the last round an address was seen in, lower values
+    /// keep more of the earlier rounds.
+    #[arg(long, value_name = "ALPHA", default_value_t = 0.3, value_parser = parse_alpha)]
+    pub(crate) ewma_alpha: f64,
+
+    /// Rank the aggregated results by stability across rounds instead of
+    /// latency
+    #[arg(long)]
+    pub(crate) sort_by_stability: bool,
+
+    /// Print the results of every round, not just the aggregate
+    #[arg(long)]
+    pub(crate) show_round_results: bool,
+
+    /// Repeat the scan every this many seconds until Ctrl-C
+    #[arg(long, value_name = "SECS")]
+    pub(crate) watch: Option<u64>,
+
+    /// After the scan, ping the top results every this many seconds until Ctrl-C
+    ///
+    /// Endpoints that miss 3 pings in a row are reported unreachable.
+    #[arg(long, value_name = "SECS", conflicts_with = "watch",
+          value_parser = clap::value_parser!(u64).range(1..))]
+    pub(crate) ping_interval: Option<u64>,
+
+    /// With --ping-interval, only report a new fastest endpoint whose latency
+    /// differs from the previous one by more than this many milliseconds
+    #[arg(
+        long,
+        value_name = "MS",
+        default_value_t = 0,
+        requires = "ping_interval"
+    )]
+    pub(crate) flap_threshold_ms: u128,
+
+    /// Stop watching after this many scans
+    #[arg(long, requires = "watch")]
+    pub(crate) watch_count: Option<u32>,
+
+    /// Record progress to this JSON Lines file and skip addresses it already has
+    ///
+    /// The file keeps the seed of the sample, so a resumed scan draws the same
+    /// addresses as the interrupted one whatever --seed says.
+    #[arg(long, value_name = "PATH", conflicts_with_all = ["rounds", "watch"])]
+    pub(crate) checkpoint_file: Option<PathBuf>,
+
+    /// Shorthand for `--checkpoint-file ./warp-checkpoint.jsonl`
+    #[arg(long, conflicts_with_all = ["checkpoint_file", "rounds", "watch"])]
+    pub(crate) resume: bool,
+
+    /// Keep the checkpoint file after the scan completes
+    #[arg(long)]
+    pub(crate) keep_checkpoint: bool,
+
+    /// Only print the results, warnings and errors
+    ///
+    /// Hides the progress bar and informational messages. Text results are
+    /// printed to stdout as one `addr - latency` line each. Implied by
+    /// --count-only.
+    #[arg(
+        short = 'q',
+        long,
+        default_value_if("count_only", ArgPredicate::Equals("true".into()), "true")
+    )]
+    pub(crate) quiet: bool,
+
+    /// Never use colors, even on a terminal
+    ///
+    /// Setting the NO_COLOR environment variable does the same.
+    #[arg(long, global = true)]
+    pub(crate) no_color: bool,
+
+    /// Custom indicatif template for the progress bar
+    #[arg(long, value_name = "TEMPLATE")]
+    pub(crate) pb_template: Option<String>,
+
+    /// Print a status line to stderr every SECS seconds during the scan
+    ///
+    /// Also with --quiet, for logs where a progress bar does not render.
+    #[arg(long, value_name = "SECS", value_parser = clap::value_parser!(u64).range(1..))]
+    pub(crate) status_interval: Option<u64>,
+
+    /// Write the scan progress to this file as JSON Lines, for dashboards
+    ///
+    /// A line every second, or every --status-interval. The file is truncated
+    /// when the scan starts.
+    #[arg(long, value_name = "PATH")]
+    pub(crate) progress_to_file: Option<PathBuf>,
+
+    /// Write every single probe as an `addr,port,attempt,latency_ms,status`
+    /// CSV row to this file
+    ///
+    /// Failed probes have an empty latency. Truncated when the scan starts.
+    #[arg(long, value_name = "PATH")]
+    pub(crate) latency_log_file: Option<PathBuf>,
+
+    /// How the scan progress is shown
+    #[arg(long, value_enum, default_value_t = PbStyle::Bar)]
+    pub(crate) pb_style: PbStyle,
+
+    /// Also copy the `ip:port` of the best result to the clipboard
+    ///
+    /// On Linux the clipboard is only kept after exiting if a clipboard
+    /// manager is running.
+    #[cfg(feature = "clipboard")]
+    #[arg(long)]
+    pub(crate) clipboard: bool,
+
+    /// Show a desktop notification when the scan completes, unless --quiet
+    #[cfg(feature = "notify")]
+    #[arg(long)]
+    pub(crate) notify: bool,
+
+    /// Show an interactive dashboard while scanning
+    #[cfg(feature = "tui")]
+    #[arg(long)]
+    pub(crate) tui: bool,
+
+    /// Count the network hops to the top IPv4 results with increasing TTLs
+    ///
+    /// Needs root or CAP_NET_RAW to read the ICMP time-exceeded errors.
+    #[arg(long)]
+    pub(crate) hop_count: bool,
+
+    /// Probe the path MTU to the top results with handshakes padded to 1200,
+    /// 1300, 1400, 1450 and 1480 bytes
+    ///
+    /// Results below the 1280 bytes WireGuard needs are flagged. The Don't
+    /// Fragment bit is only set on Linux.
+    #[arg(long)]
+    pub(crate) pmtud: bool,
+
+    /// Also ping the top results, to tell WARP overhead from a slow path
+    ///
+    /// Needs net.ipv4.ping_group_range to include the user, or root.
+    #[arg(long)]
+    pub(crate) ping_compare: bool,
+
+    /// Look up the PTR name of the top results, unless --quiet
+    #[arg(long)]
+    pub(crate) rdns: bool,
+
+    /// Add the AS announcing every result to the JSON output
+    ///
+    /// Uses the iptoasn.com database, cached in ~/.cache/warp-speedtest for a
+    /// day. Results outside Cloudflare's AS13335 are warned about.
+    #[cfg(feature = "asn")]
+    #[arg(long)]
+    pub(crate) asn: bool,
+
+    /// Add the country of every result from this MaxMind GeoLite2 database
+    #[cfg(feature = "geoip")]
+    #[arg(long, value_name = "MMDB")]
+    pub(crate) geoip: Option<PathBuf>,
+
+    /// Only keep results in this country, as an ISO code, can be repeated
+    #[cfg(feature = "geoip")]
+    #[arg(long, value_name = "CC", requires = "geoip")]
+    pub(crate) country_filter: Vec<String>,
+
+    /// Result output format
+    #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+    pub(crate) output_format: OutputFormat,
+
+    /// Print only one `addr:port latency_ms` line per top result to stdout
+    ///
+    /// Takes precedence over --output-format. IPv6 addresses are printed
+    /// without brackets.
+    #[arg(long)]
+    pub(crate) compact: bool,
+
+    /// Separator between the address and latency with --compact, `\t` for a tab
+    #[arg(long, value_name = "SEP", default_value = " ", requires = "compact")]
+    pub(crate) compact_sep: String,
+
+    /// Field separator of CSV results, `tab` or `\t` for a tab. Fields holding
+    /// it, e.g. every address with `:`, are quoted
+    #[arg(long, value_name = "CHAR", default_value = ",", value_parser = parse_csv_sep)]
+    pub(crate) export_csv_sep: char,
+
+    /// Print only the IP of every top result to stdout, like `[2606:4700::1]`
+    /// for IPv6
+    ///
+    /// Exits with code 1 if no address works.
+    #[arg(long, conflicts_with = "compact")]
+    pub(crate) numeric: bool,
+
+    /// Print only the number of working addresses to stdout once the scan is
+    /// done
+    ///
+    /// Implies --quiet. With --alert-threshold, exits with code 1 if no
+    /// address works.
+    #[arg(long, conflicts_with_all = ["numeric", "compact"])]
+    pub(crate) count_only: bool,
+
+    /// End every --compact or --numeric line with a null byte, for `xargs -0`
+    ///
+    /// Implies --compact without --numeric.
+    #[arg(long)]
+    pub(crate) print0: bool,
+
+    /// Measurement name of --output-format influx lines
+    #[arg(long, value_name = "NAME", default_value = "warp_endpoint")]
+    pub(crate) influx_measurement: String,
+
+    /// Extra tag added to every --output-format influx line, repeatable
+    #[arg(long, value_name = "KEY=VALUE", value_parser = parse_key_value)]
+    pub(crate) influx_tag: Vec<(String, String)>,
+
+    /// Extra field added to JSON and CSV results, repeatable, e.g.
+    /// `--tag region=us-west`
+    ///
+    /// JSON results get it in a `tags` object, CSV results as an extra
+    /// column.
+    /// Also passed to --on-complete as WARP_TAG_<KEY>.
+    #[arg(long, value_name = "KEY=VALUE", value_parser = parse_key_value)]
+    pub(crate) tag: Vec<(String, String)>,
+
+    /// Save the results to this file instead of printing them
+    ///
+    /// Text results are still logged, and also saved as one `addr - latency`
+    /// line per address.
+    #[arg(long, value_name = "PATH")]
+    pub(crate) output_file: Option<PathBuf>,
+
+    /// Indent JSON results by 2 spaces instead of writing them on one line
+    #[arg(long)]
+    pub(crate) json_pretty: bool,
+
+    /// Print the header of CSV results and exit, to start a file that runs
+    /// with --append add rows to
+    ///
+    /// Tab separated with --output-format tsv.
+    #[arg(long)]
+    pub(crate) csv_header_only: bool,
+
+    /// Append to --output-file instead of replacing it
+    ///
+    /// CSV files keep a single header, text runs are separated by a
+    /// `=== Run at <time> ===` line.
+    #[arg(long, requires = "output_file")]
+    pub(crate) append: bool,
+
+    /// Also save the results in this format to this file, can be repeated
+    ///
+    /// e.g. `--output json:results.json --output csv:results.csv`. Text
+    /// files get one `addr - latency` line per address.
+    #[arg(long = "output", value_name = "FORMAT:PATH", value_parser = parse_output)]
+    pub(crate) outputs: Vec<(OutputFormat, PathBuf)>,
+
+    /// Show a latency histogram of all working addresses
+    #[arg(long)]
+    pub(crate) histogram: bool,
+
+    /// Width of each histogram bucket in milliseconds
+    ///
+    /// Without this or --histogram-buckets, the histogram has 10 buckets
+    /// from 0 to the probe timeout.
+    #[arg(long, value_parser = clap::value_parser!(u64).range(1..))]
+    pub(crate) histogram_bucket_ms: Option<u64>,
+
+    /// Split the histogram into N buckets of equal width between the lowest
+    /// and the highest latency
+    #[arg(long, value_name = "N", conflicts_with = "histogram_bucket_ms",
+          value_parser = clap::value_parser!(u32).range(1..))]
+    pub(crate) histogram_buckets: Option<u32>,
+
+    /// Also report every address that did not respond, with the reason
+    #[arg(long)]
+    pub(crate) all_results: bool,
+
+    /// Show latency statistics per subnet
+    #[arg(long)]
+    pub(crate) verbose_subnets: bool,
+
+    /// Exit with an error if no address of some subnet responded
+    #[arg(long)]
+    pub(crate) fail_on_unreachable: bool,
+
+    /// Exit with code 2 if the fastest address is slower than this many
+    /// milliseconds, or 1 if none works
+    ///
+    /// Unlike --max-latency, no address is filtered out.
+    #[arg(long, value_name = "MS")]
+    pub(crate) alert_threshold: Option<u128>,
+
+    /// Write the top results as WireGuard `[Peer]` blocks to this file
+    #[arg(long, value_name = "PATH")]
+    pub(crate) export_wireguard: Option<PathBuf>,
+
+    /// Write a systemd-networkd WireGuard device with the best result as its
+    /// peer to this file, e.g. `/etc/systemd/network/20-warp.netdev`
+    #[arg(long, value_name = "PATH")]
+    pub(crate) export_networkd: Option<PathBuf>,
+
+    /// Name of the WireGuard interface in --export-networkd
+    #[arg(
+        long,
+        value_name = "IFNAME",
+        default_value = "wg0",
+        requires = "export_networkd"
+    )]
+    pub(crate) networkd_interface: String,
+
+    /// Write a complete WireGuard configuration with the best result as its
+    /// peer to this file, readable only by its owner
+    ///
+    /// The keys and addresses come from --warp-registration, without it they
+    /// are left as placeholders to fill in.
+    #[arg(long, value_name = "PATH")]
+    pub(crate) export_warp_conf: Option<PathBuf>,
+
+    /// Write a resolv.conf with the IP of the best result as the nameserver to
+    /// this file
+    #[arg(long, value_name = "PATH")]
+    pub(crate) export_resolv_conf: Option<PathBuf>,
+
+    /// Add a `search` line for this domain to --export-resolv-conf
+    #[arg(long, value_name = "DOMAIN", requires = "export_resolv_conf")]
+    pub(crate) resolv_search: Option<String>,
+
+    /// WARP public key used in exported WireGuard peers
+    #[arg(long, value_name = "KEY")]
+    pub(crate) wireguard_pubkey: Option<String>,
+
+    /// Take the WARP public key from this warp-registration.json, e.g. from wgcf
+    ///
+    /// The key is printed with the results and used in --export-wireguard.
+    #[arg(long, value_name = "PATH", conflicts_with = "wireguard_pubkey")]
+    pub(crate) warp_registration: Option<PathBuf>,
+
+    /// Write every round's working addresses to this CSV file
+    ///
+    /// One `round,addr,latency_ms,loss_pct` row per round and address,
+    /// unlike --output-file which gets the aggregate.
+    #[arg(long, value_name = "PATH")]
+    pub(crate) timeseries_csv: Option<PathBuf>,
+
+    /// Append to the --timeseries-csv file instead of replacing it
+    #[arg(long, requires = "timeseries_csv")]
+    pub(crate) append_ts_csv: bool,
+
+    /// Send a trace span for every probe to this OTLP/HTTP collector
+    #[cfg(feature = "otel")]
+    #[arg(long, value_name = "URL")]
+    pub(crate) otel_endpoint: Option<String>,
+
+    /// How long to wait for the remaining spans to be sent on exit
+    #[cfg(feature = "otel")]
+    #[arg(long, value_name = "MS", default_value_t = 5000)]
+    pub(crate) otel_flush_timeout: u64,
+
+    /// POST the results as JSON to this URL once the scan is done
+    ///
+    /// The body is the same as with --output-format json. Failed requests
+    /// are only logged.
+    #[arg(long, value_name = "URL")]
+    pub(crate) webhook: Option<String>,
+
+    /// Extra header sent to the --webhook, e.g. "Authorization: Bearer ..."
+    #[arg(long, value_name = "NAME: VALUE", requires = "webhook", value_parser = parse_header)]
+    pub(crate) webhook_auth_header: Option<(String, String)>,
+
+    /// Write a one-line JSON summary of the scan to this file, whatever the
+    /// --output-format
+    ///
+    /// Has the alive and total counts, the best address and latency, the scan
+    /// duration and a timestamp. The file is replaced atomically.
+    #[arg(long, value_name = "PATH")]
+    pub(crate) summary_json: Option<PathBuf>,
+
+    /// Write Prometheus textfile-collector metrics to this file
+    #[arg(long, value_name = "PATH")]
+    pub(crate) metrics_file: Option<PathBuf>,
+
+    /// Send the best latency, the alive count and the scan duration as StatsD
+    /// gauges to this agent, e.g. localhost:8125
+    #[arg(long, value_name = "HOST:PORT")]
+    pub(crate) statsd_addr: Option<String>,
+
+    /// Prefix of the --statsd-addr metric names
+    #[arg(
+        long,
+        value_name = "PREFIX",
+        default_value = "warp_speedtest",
+        requires = "statsd_addr"
+    )]
+    pub(crate) statsd_prefix: String,
+
+    /// Write a self-contained HTML report with a latency chart to this file
+    #[arg(long, value_name = "PATH")]
+    pub(crate) report_html: Option<PathBuf>,
+
+    /// Write a Markdown table of the top results to this file
+    #[arg(long, value_name = "PATH")]
+    pub(crate) report_md: Option<PathBuf>,
+
+    /// Print a `warp-cli set-custom-endpoint` command for every top result
+    #[arg(long)]
+    pub(crate) export_warp_cli: bool,
+
+    /// Run `warp-cli set-custom-endpoint` with the best result
+    #[arg(long)]
+    pub(crate) exec_warp_cli: bool,
+
+    /// Run this shell command once the results are out
+    ///
+    /// The best result is passed in WARP_BEST_ADDR and WARP_BEST_LATENCY_MS,
+    /// the counts in WARP_ALIVE_COUNT and WARP_TOTAL_TESTED.
+    #[arg(long, value_name = "CMD")]
+    pub(crate) on_complete: Option<String>,
+
+    /// Print the probe count, worst-case duration and sample memory of the
+    /// scan, then exit without probing
+    #[arg(long)]
+    pub(crate) dry_run_stats: bool,
+
+    /// Log messages of this level and above
+    ///
+    /// Defaults to info, or warn with --quiet. An explicit RUST_LOG takes
+    /// precedence. debug also shows results as they come in instead of the
+    /// progress bar.
+    #[arg(long, value_enum, value_name = "LEVEL")]
+    pub(crate) log_level: Option<LogLevel>,
+
+    /// Log output format
+    #[arg(long, value_enum, default_value_t = LogFormat::Text)]
+    pub(crate) log_format: LogFormat,
+
     /// Generate shell completions
     #[arg(long, value_enum)]
     pub(crate) completion: Option<Shell>,
+
+    /// Print the JSON Schema of the --output-format json report, with the
+    /// latency keys of --latency-unit, and exit
+    #[arg(long)]
+    pub(crate) results_json_schema: bool,
+
+    #[command(subcommand)]
+    pub(crate) command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+pub(crate) enum Command {
+    /// Compare the latencies of two saved JSON results
+    Diff(DiffArgs),
+    /// Scan, then cross-check the latencies of the fastest results
+    Benchmark(BenchmarkArgs),
+    /// Write the fastest results of a saved JSON report as an /etc/hosts block
+    ExportHosts(ExportHostsArgs),
+    /// Show the results of past scans
+    #[cfg(feature = "history")]
+    History(HistoryArgs),
+}
+
+#[derive(clap::Args)]
+pub(crate) struct DiffArgs {
+    /// The earlier results
+    pub(crate) a: PathBuf,
+
+    /// The later results
+    pub(crate) b: PathBuf,
+
+    /// Highlight addresses whose latency changed by more than this, and exit
+    /// with an error if there are any
+    #[arg(long, value_name = "MS")]
+    pub(crate) threshold_ms: Option<f64>,
+}
+
+#[derive(clap::Args)]
+pub(crate) struct BenchmarkArgs {
+    /// Time the results with an nmap UDP scan, nmap must be in $PATH
+    #[arg(long, required = true)]
+    pub(crate) compare_nmap: bool,
+
+    /// Number of results to cross-check
+    #[arg(long, default_value_t = 5)]
+    pub(crate) top: usize,
+}
+
+#[derive(clap::Args)]
+pub(crate) struct ExportHostsArgs {
+    /// The saved JSON results
+    pub(crate) results: PathBuf,
+
+    /// Number of results to write
+    #[arg(long, default_value_t = 5)]
+    pub(crate) top: usize,
+
+    /// Write the block to this file instead of stdout
+    #[arg(short, long, value_name = "PATH")]
+    pub(crate) output: Option<PathBuf>,
+
+    /// Replace the block in the hosts file, keeping a .bak copy
+    #[arg(long)]
+    pub(crate) update_hosts: bool,
+
+    /// The hosts file to update
+    #[arg(
+        long,
+        value_name = "PATH",
+        default_value = "/etc/hosts",
+        requires = "update_hosts"
+    )]
+    pub(crate) hosts_file: PathBuf,
+}
+
+#[cfg(feature = "history")]
+#[derive(clap::Args)]
+pub(crate) struct HistoryArgs {
+    /// Only show results from this day on, e.g. 2024-01-01
+    #[arg(long, value_name = "DATE")]
+    pub(crate) since: Option<jiff::civil::Date>,
+
+    /// Only show results of this address
+    #[arg(long, value_name = "IP:PORT")]
+    pub(crate) addr: Option<std::net::SocketAddr>,
+
+    /// Show the mean latency and its trend per address instead
+    #[arg(long)]
+    pub(crate) stats: bool,
+}
+
+/// Most ports `--ports` expands to.
+const MAX_PORTS: usize = 1000;
+
+/// Parse `--ports`, a comma separated mix of ports and `low-high` ranges.
+fn parse_ports(value: &str) -> Result<Vec<u16>, String> {
+    let parse = |port: &str| {
+        port.trim()
+            .parse::<u16>()
+            .map_err(|e| format!("invalid port {port:?}: {e}"))
+    };
+    let mut ports = Vec::new();
+    for part in value.split(',') {
+        match part.split_once('-') {
+            Some((low, high)) => {
+                let (low, high) = (parse(low)?, parse(high)?);
+                if low > high {
+                    return Err(format!(
+                        "invalid port range {part:?}, {low} is above {high}"
+                    ));
+                }
+                ports.extend(low..=high);
+            }
+            None => ports.push(parse(part)?),
+        }
+        if ports.len() > MAX_PORTS {
+            return Err(format!("at most {MAX_PORTS} ports can be tested"));
+        }
+    }
+    Ok(ports)
+}
+
+/// Parse one `port:weight` pair of `--port-weights`.
+fn parse_port_weight(value: &str) -> Result<(u16, u32), String> {
+    let (port, weight) = value
+        .split_once(':')
+        .ok_or_else(|| format!("expected PORT:WEIGHT, got {value:?}"))?;
+    let port = port
+        .parse()
+        .map_err(|e| format!("invalid port {port:?}: {e}"))?;
+    let weight = weight
+        .parse()
+        .map_err(|e| format!("invalid weight {weight:?}: {e}"))?;
+    Ok((port, weight))
+}
+
+/// Parse a `--socket-mark`, in hex with a `0x` prefix or in decimal.
+fn parse_mark(value: &str) -> Result<u32, String> {
+    match value
+        .strip_prefix("0x")
+        .or_else(|| value.strip_prefix("0X"))
+    {
+        Some(hex) => u32::from_str_radix(hex, 16),
+        None => value.parse(),
+    }
+    .map_err(|e| format!("invalid mark {value:?}: {e}"))
+}
+
+/// Parse one `key=value` pair of `--influx-tag` or `--tag`.
+fn parse_key_value(value: &str) -> Result<(String, String), String> {
+    match value.split_once('=') {
+        Some((key, value)) if !key.is_empty() && !value.is_empty() => {
+            Ok((key.to_string(), value.to_string()))
+        }
+        _ => Err(format!("expected KEY=VALUE, got {value:?}")),
+    }
+}
+
+/// Parse a single character `--export-csv-sep`.
+fn parse_csv_sep(value: &str) -> Result<char, String> {
+    if matches!(value, "tab" | "\\t") {
+        return Ok('\t');
+    }
+    let mut chars = value.chars();
+    match (chars.next(), chars.next()) {
+        (Some(sep), None) => Ok(sep),
+        _ => Err(format!("expected a single character, got {value:?}")),
+    }
+}
+
+/// Parse one `format:path` pair of `--output`.
+fn parse_output(value: &str) -> Result<(OutputFormat, PathBuf), String> {
+    let (format, path) = value
+        .split_once(':')
+        .filter(|(_, path)| !path.is_empty())
+        .ok_or_else(|| format!("expected FORMAT:PATH, got {value:?}"))?;
+    let format = OutputFormat::from_str(format, true)
+        .map_err(|_| format!("unknown output format {format:?}"))?;
+    Ok((format, PathBuf::from(path)))
+}
+
+/// Parse a `Name: value` HTTP header.
+fn parse_header(value: &str) -> Result<(String, String), String> {
+    match value.split_once(':') {
+        Some((name, value)) if !name.trim().is_empty() => {
+            Ok((name.trim().to_string(), value.trim().to_string()))
+        }
+        _ => Err(format!("expected NAME: VALUE, got {value:?}")),
+    }
+}
+
+/// Parse the `--confidence-interval` percentage.
+fn parse_confidence(value: &str) -> Result<f64, String> {
+    let pct: f64 = value.parse().map_err(|e| format!("{e}"))?;
+    if pct > 0.0 && pct < 100.0 {
+        Ok(pct)
+    } else {
+        Err(format!("{pct} is not between 0 and 100"))
+    }
+}
+
+/// Parse `--margin-of-error-ms`, which must be positive.
+fn parse_margin(value: &str) -> Result<f64, String> {
+    let margin: f64 = value.parse().map_err(|e| format!("{e}"))?;
+    if margin > 0.0 {
+        Ok(margin)
+    } else {
+        Err(format!("{margin} is not positive"))
+    }
+}
+
+/// Parse the `--ewma-alpha` weight.
+fn parse_alpha(value: &str) -> Result<f64, String> {
+    let alpha: f64 = value.parse().map_err(|e| format!("{e}"))?;
+    if alpha > 0.0 && alpha <= 1.0 {
+        Ok(alpha)
+    } else {
+        Err(format!("{alpha} is not in (0, 1]"))
+    }
+}
+
+impl Args {
+    /// Whether every sampled IP is probed on every port.
+    pub(crate) fn every_port(&self) -> bool {
+        self.all_ports || self.probe_all_ports
+    }
+
+    /// Most probes in flight at once, each needing its own `--source-port`.
+    pub(crate) fn concurrent_probers(&self) -> usize {
+        if self.probe_all_ports {
+            self.threads.saturating_mul(self.port_concurrency as usize)
+        } else {
+            self.threads
+        }
+    }
+
+    /// The checkpoint file to use, if any.
+    pub(crate) fn checkpoint_path(&self) -> Option<PathBuf> {
+        if self.resume {
+            Some(PathBuf::from(DEFAULT_CHECKPOINT_FILE))
+        } else {
+            self.checkpoint_file.clone()
+        }
+    }
 }
 
 #[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
 pub(crate) enum SpeedTestMode {
     Ipv4,
     Ipv6,
+    /// Every sampled IPv4 address together with its IPv6 twin
+    Dual,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+pub(crate) enum Order {
+    /// In sampling order
+    Random,
+    /// In ascending address order
+    Sequential,
+    /// One address of every subnet before the second of any, for an early
+    /// spread with --stop-after
+    SubnetFirst,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+pub(crate) enum SampleStrategy {
+    /// The same number of addresses from every range, regardless of its size
+    Uniform,
+    /// At random from all ranges, so larger ones get more addresses
+    Weighted,
+    /// The first addresses of the ranges in CIDR order
+    Sequential,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+pub(crate) enum PbStyle {
+    /// A progress bar
+    Bar,
+    /// A spinner with the probe count, for terminals that draw bars poorly
+    Spinner,
+    /// Nothing
+    None,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+pub(crate) enum SortBy {
+    /// Average latency, or the median with --thorough-mode
+    Latency,
+    /// Mean difference between consecutive probes, steadiest first
+    PingVariance,
+    /// Standard deviation of the probes, steadiest first
+    Jitter,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+pub(crate) enum LogLevel {
+    Trace,
+    Debug,
+    Info,
+    Warn,
+    Error,
+}
+
+impl From<LogLevel> for log::LevelFilter {
+    fn from(level: LogLevel) -> Self {
+        match level {
+            LogLevel::Trace => log::LevelFilter::Trace,
+            LogLevel::Debug => log::LevelFilter::Debug,
+            LogLevel::Info => log::LevelFilter::Info,
+            LogLevel::Warn => log::LevelFilter::Warn,
+            LogLevel::Error => log::LevelFilter::Error,
+        }
+    }
+}
+
+#[derive(
+    Copy,
+    Clone,
+    Debug,
+    Default,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    ValueEnum,
+    Serialize,
+    Deserialize,
+    JsonSchema,
+)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum LatencyUnit {
+    /// Milliseconds
+    #[default]
+    Ms,
+    /// Microseconds
+    Us,
+}
+
+impl LatencyUnit {
+    /// `elapsed` in this unit, rounded down.
+    pub(crate) fn of(self, elapsed: Duration) -> u128 {
+        match self {
+            LatencyUnit::Ms => elapsed.as_millis(),
+            LatencyUnit::Us => elapsed.as_micros(),
+        }
+    }
+
+    /// How long `latency` in this unit is.
+    pub(crate) fn duration(self, latency: u128) -> Duration {
+        let latency = latency.try_into().unwrap_or(u64::MAX);
+        match self {
+            LatencyUnit::Ms => Duration::from_millis(latency),
+            LatencyUnit::Us => Duration::from_micros(latency),
+        }
+    }
+
+    /// How many of this unit make a millisecond.
+    pub(crate) fn per_ms(self) -> u128 {
+        match self {
+            LatencyUnit::Ms => 1,
+            LatencyUnit::Us => 1000,
+        }
+    }
+
+    /// `ms` milliseconds in this unit, for the latency options, which are
+    /// always in milliseconds.
+    pub(crate) fn of_ms(self, ms: u128) -> u128 {
+        ms.saturating_mul(self.per_ms())
+    }
+
+    /// Like [`LatencyUnit::of_ms`], for fractional options.
+    pub(crate) fn of_ms_f64(self, ms: f64) -> f64 {
+        ms * self.per_ms() as f64
+    }
+
+    /// `latency` in this unit as fractional milliseconds.
+    pub(crate) fn to_ms(self, latency: f64) -> f64 {
+        latency / self.per_ms() as f64
+    }
+
+    /// Symbol printed after latencies.
+    pub(crate) fn label(self) -> &'static str {
+        match self {
+            LatencyUnit::Ms => "ms",
+            LatencyUnit::Us => "µs",
+        }
+    }
+
+    /// Column or field name for a latency `name` in this unit, such as
+    /// `latency_us`.
+    pub(crate) fn column(self, name: &str) -> String {
+        match self {
+            LatencyUnit::Ms => format!("{name}_ms"),
+            LatencyUnit::Us => format!("{name}_us"),
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+pub(crate) enum LogFormat {
+    Text,
+    Json,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+pub(crate) enum OutputFormat {
+    Text,
+    Json,
+    Csv,
+    /// Like csv, separated by tabs whatever --export-csv-sep says
+    Tsv,
+    /// InfluxDB line protocol, one line per top address
+    Influx,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_ports_expands_ranges() {
+        assert_eq!(parse_ports("500-502,2408"), Ok(vec![500, 501, 502, 2408]));
+        assert_eq!(parse_ports(" 2408 "), Ok(vec![2408]));
+    }
+
+    #[test]
+    fn parse_ports_rejects_bad_ranges() {
+        assert!(parse_ports("600-500").is_err());
+        assert!(parse_ports("500-").is_err());
+        assert!(parse_ports("1-65535").is_err());
+    }
+
+    #[test]
+    fn skip_preflight_check_keeps_the_udp_check() {
+        let cli = Args::try_parse_from(["warp", "--test-connectivity", "--skip-preflight-check"])
+            .unwrap();
+        assert!(cli.skip_preflight_check && !cli.skip_preflight);
+    }
+
+    #[test]
+    fn latency_unit_converts_millisecond_options() {
+        assert_eq!(LatencyUnit::Ms.of_ms(250), 250);
+        assert_eq!(LatencyUnit::Us.of_ms(250), 250_000);
+        assert_eq!(LatencyUnit::Us.to_ms(1500.0), 1.5);
+        assert_eq!(LatencyUnit::Us.column("jitter"), "jitter_us");
+    }
 }
@@ -0,0 +1,147 @@
+use crate::TestResult;
+use crate::cidrs;
+use anyhow::{Context, Result, bail};
+use flate2::read::GzDecoder;
+use log::{debug, info, warn};
+use schemars::JsonSchema;
+use serde::Serialize;
+use std::fs;
+use std::io::Read;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+/// iptoasn.com's IPv4 and IPv6 database, one range per line.
+const DATABASE_URL: &str = "https://iptoasn.com/data/ip2asn-combined.tsv.gz";
+
+/// How long a downloaded database is reused before downloading again.
+const CACHE_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Timeout of the whole HTTP request, the database is a few megabytes.
+const FETCH_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Cloudflare's AS, every WARP endpoint is expected to be announced by it.
+const CLOUDFLARE_ASN: u32 = 13335;
+
+#[derive(Clone, Debug, Serialize, JsonSchema)]
+pub(crate) struct AsnInfo {
+    pub(crate) asn: u32,
+    pub(crate) org: String,
+}
+
+/// Address ranges and the AS announcing them, sorted by start address.
+pub(crate) struct Database {
+    ranges: Vec<(IpAddr, IpAddr, AsnInfo)>,
+}
+
+impl Database {
+    /// Parse the tab-separated `start end asn country org` lines of the
+    /// database. Unrouted ranges, with AS 0, are left out.
+    fn parse(contents: &str) -> Database {
+        let mut ranges: Vec<(IpAddr, IpAddr, AsnInfo)> = contents
+            .lines()
+            .filter_map(|line| {
+                let mut fields = line.split('\t');
+                let start = fields.next()?.parse().ok()?;
+                let end = fields.next()?.parse().ok()?;
+                let asn = fields.next()?.parse().ok().filter(|&asn| asn != 0)?;
+                let org = fields.nth(1)?.to_string();
+                Some((start, end, AsnInfo { asn, org }))
+            })
+            .collect();
+        ranges.sort_by_key(|(start, _, _)| *start);
+        Database { ranges }
+    }
+
+    /// The AS announcing `ip`, if any.
+    pub(crate) fn lookup(&self, ip: IpAddr) -> Option<&AsnInfo> {
+        let i = self.ranges.partition_point(|(start, _, _)| *start <= ip);
+        let (start, end, info) = self.ranges.get(i.checked_sub(1)?)?;
+        (start.is_ipv4() == ip.is_ipv4() && ip <= *end).then_some(info)
+    }
+}
+
+fn cache_path() -> Option<PathBuf> {
+    cidrs::cache_dir().map(|dir| dir.join("ip2asn.tsv"))
+}
+
+/// Download and decompress the database.
+async fn fetch() -> Result<String> {
+    let client = reqwest::Client::builder()
+        .timeout(FETCH_TIMEOUT)
+        .user_agent(concat!(
+            env!("CARGO_PKG_NAME"),
+            "/",
+            env!("CARGO_PKG_VERSION")
+        ))
+        .build()?;
+    let body = client
+        .get(DATABASE_URL)
+        .send()
+        .await?
+        .error_for_status()?
+        .bytes()
+        .await?;
+
+    let mut contents = String::new();
+    GzDecoder::new(&body[..])
+        .read_to_string(&mut contents)
+        .context("Failed to decompress the ASN database")?;
+    if contents.is_empty() {
+        bail!("{DATABASE_URL} returned an empty database");
+    }
+    Ok(contents)
+}
+
+/// The ASN database, from a cache younger than a day or freshly downloaded.
+/// Falls back to a stale cache if downloading fails.
+pub(crate) async fn load() -> Result<Database> {
+    let path = cache_path().context("Could not determine the cache directory")?;
+    let age = fs::metadata(&path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+        .and_then(|modified| SystemTime::now().duration_since(modified).ok());
+    if age.is_some_and(|age| age < CACHE_TTL) {
+        debug!("Using cached ASN database {}", path.display());
+        return read(&path);
+    }
+
+    match fetch().await {
+        Ok(contents) => {
+            info!("Downloaded the ASN database from {DATABASE_URL}");
+            if let Some(dir) = path.parent()
+                && let Err(e) = fs::create_dir_all(dir).and_then(|()| fs::write(&path, &contents))
+            {
+                warn!("Failed to cache the ASN database: {e}");
+            }
+            Ok(Database::parse(&contents))
+        }
+        Err(e) if age.is_some() => {
+            warn!("Failed to download the ASN database, using the cached one: {e:#}");
+            read(&path)
+        }
+        Err(e) => Err(e.context("Failed to download the ASN database")),
+    }
+}
+
+fn read(path: &Path) -> Result<Database> {
+    let contents =
+        fs::read_to_string(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    Ok(Database::parse(&contents))
+}
+
+/// Fill in the AS of every result, warning about those not announced by
+/// Cloudflare.
+pub(crate) fn annotate(database: &Database, results: &mut [TestResult]) {
+    for result in results {
+        result.asn = database.lookup(result.addr.ip()).cloned();
+        match &result.asn {
+            Some(info) if info.asn != CLOUDFLARE_ASN => warn!(
+                "{} is announced by AS{} ({}), not Cloudflare",
+                result.addr, info.asn, info.org
+            ),
+            Some(_) => {}
+            None => warn!("{} is not in the ASN database", result.addr),
+        }
+    }
+}
@@ -0,0 +1,88 @@
+use crate::args::BenchmarkArgs;
+use crate::{AddressPool, Args, generate_addrs, sample_rng, scan};
+use anyhow::{Context, Result, bail};
+use console::style;
+use log::{debug, info};
+use std::net::SocketAddr;
+use std::process::Command;
+
+/// The latency nmap reports in `Host is up (0.012s latency).`, in ms.
+fn parse_nmap_latency(output: &str) -> Option<f64> {
+    let rest = &output[output.find("Host is up (")? + "Host is up (".len()..];
+    let seconds: f64 = rest[..rest.find("s latency")?].parse().ok()?;
+    Some(seconds * 1000.0)
+}
+
+/// Time `addr` with an nmap UDP scan of its port.
+fn nmap_latency(addr: &SocketAddr) -> Result<Option<f64>> {
+    let mut command = Command::new("nmap");
+    if addr.is_ipv6() {
+        command.arg("-6");
+    }
+    let output = command
+        .args([
+            "-sU",
+            "-p",
+            &addr.port().to_string(),
+            "--host-timeout",
+            "2s",
+        ])
+        .arg(addr.ip().to_string())
+        .output()
+        .context("Failed to run nmap, is it installed?")?;
+    if !output.status.success() {
+        bail!("nmap exited with {} for {addr}", output.status);
+    }
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    debug!("nmap output for {addr}:\n{stdout}");
+    Ok(parse_nmap_latency(&stdout))
+}
+
+/// Scan, then time the fastest results with nmap and print both side by
+/// side. Pairs that nmap orders the other way round are inversions.
+pub(crate) async fn run(cli: &Args, args: &BenchmarkArgs, pool: &AddressPool) -> Result<()> {
+    let results = scan(
+        cli,
+        generate_addrs(cli, pool, &mut sample_rng(cli.seed)),
+        None,
+    )
+    .await?;
+    let top: Vec<(SocketAddr, u128)> = results
+        .alive
+        .iter()
+        .take(args.top)
+        .map(|res| (res.addr, res.latency))
+        .collect();
+    if top.is_empty() {
+        bail!("No working IPs found to benchmark");
+    }
+
+    info!("Timing the top {} IPs with nmap", top.len());
+    let nmap = top
+        .iter()
+        .map(|(addr, _)| nmap_latency(addr))
+        .collect::<Result<Vec<_>>>()?;
+
+    // The scan was faster to `i` than to `j`, nmap the other way round
+    let inverted = |i: usize, j: usize| {
+        top[i].1 < top[j].1 && matches!((nmap[i], nmap[j]), (Some(a), Some(b)) if a > b)
+    };
+    let inversions = (0..top.len())
+        .flat_map(|i| (i + 1..top.len()).map(move |j| (i, j)))
+        .filter(|&(i, j)| inverted(i, j))
+        .count();
+
+    println!("{:<45} {:>10} {:>10}", "Address", "Scan (ms)", "nmap (ms)");
+    for (i, ((addr, latency), nmap_ms)) in top.iter().zip(&nmap).enumerate() {
+        let nmap_col = nmap_ms.map_or_else(|| "-".to_string(), |ms| format!("{ms:.1}"));
+        let row = format!("{addr:<45} {latency:>10} {nmap_col:>10}");
+        if (i + 1..top.len()).any(|j| inverted(i, j)) {
+            println!("{row} {:>10}", style("INVERTED").yellow());
+        } else {
+            println!("{row}");
+        }
+    }
+    println!("{inversions} ordering inversions");
+    Ok(())
+}
@@ -0,0 +1,243 @@
+use crate::args::LatencyUnit;
+use crate::{DeadAddr, ScanResults, Status, TestResult};
+use anyhow::{Context, Result, bail};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+
+/// Number of completed addresses buffered before appending them to the file.
+const FLUSH_EVERY: usize = 100;
+
+/// The first line of a checkpoint, describing the sample it records.
+#[derive(Debug, Serialize, Deserialize)]
+struct Header {
+    /// Seed the addresses were sampled with
+    seed: u64,
+    /// Unit of the latencies of the records
+    #[serde(default)]
+    latency_unit: LatencyUnit,
+}
+
+/// One tested address, stored as a single JSON line.
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct Record {
+    addr: SocketAddr,
+    /// In the `latency_unit` of the header
+    #[serde(alias = "latency_ms")]
+    latency: Option<u128>,
+    status: Status,
+    #[serde(default)]
+    samples: Vec<u128>,
+    #[serde(default)]
+    attempts: u32,
+}
+
+impl From<&TestResult> for Record {
+    fn from(result: &TestResult) -> Self {
+        Record {
+            addr: result.addr,
+            latency: Some(result.latency),
+            status: Status::Alive,
+            samples: result.samples.clone(),
+            attempts: result.attempts,
+        }
+    }
+}
+
+impl From<&DeadAddr> for Record {
+    fn from(dead: &DeadAddr) -> Self {
+        Record {
+            addr: dead.addr,
+            latency: None,
+            status: dead.status,
+            samples: Vec::new(),
+            attempts: 0,
+        }
+    }
+}
+
+/// Append-only JSON Lines log of tested addresses for resuming scans.
+pub(crate) struct Checkpoint {
+    path: PathBuf,
+    file: File,
+    seed: u64,
+    saved: Vec<Record>,
+    pending: Vec<Record>,
+}
+
+impl Checkpoint {
+    /// Open `path` for appending, loading the seed and the records of a
+    /// previous run. A new checkpoint records `seed` and latencies in `unit`
+    /// instead. Resuming one recorded in another unit is an error.
+    ///
+    /// Lines that fail to parse, e.g. one cut short by an interrupted write,
+    /// are skipped with a warning.
+    pub(crate) fn open(path: &Path, seed: u64, unit: LatencyUnit) -> Result<Self> {
+        let mut header = None;
+        let mut saved = Vec::new();
+        if path.exists() {
+            let reader = BufReader::new(
+                File::open(path).with_context(|| format!("Failed to open {}", path.display()))?,
+            );
+            for (number, line) in reader.lines().enumerate() {
+                let line = line?;
+                if line.trim().is_empty() {
+                    continue;
+                }
+                if number == 0
+                    && let Ok(parsed) = serde_json::from_str::<Header>(&line)
+                {
+                    header = Some(parsed);
+                    continue;
+                }
+                match serde_json::from_str(&line) {
+                    Ok(record) => saved.push(record),
+                    Err(e) => warn!("Skipping line {} of {}: {e}", number + 1, path.display()),
+                }
+            }
+            info!(
+                "Resuming from {} with {} tested addresses",
+                path.display(),
+                saved.len()
+            );
+        }
+
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .with_context(|| format!("Failed to open {}", path.display()))?;
+        let seed = match header {
+            Some(header) if header.latency_unit != unit => bail!(
+                "{} records latencies in {}, resume it with --latency-unit {0}",
+                path.display(),
+                header.latency_unit.label()
+            ),
+            Some(header) => header.seed,
+            None if saved.is_empty() => {
+                let header = serde_json::to_string(&Header {
+                    seed,
+                    latency_unit: unit,
+                })?;
+                writeln!(file, "{header}")
+                    .with_context(|| format!("Failed to write {}", path.display()))?;
+                seed
+            }
+            None => {
+                warn!(
+                    "{} has no seed, the resumed scan samples new addresses",
+                    path.display()
+                );
+                seed
+            }
+        };
+
+        Ok(Checkpoint {
+            path: path.to_path_buf(),
+            file,
+            seed,
+            saved,
+            pending: Vec::with_capacity(FLUSH_EVERY),
+        })
+    }
+
+    /// Seed of the sample this checkpoint records.
+    pub(crate) fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    /// Drop every address from `addrs` that was already tested.
+    pub(crate) fn skip_tested(&self, addrs: &mut Vec<SocketAddr>) {
+        let tested: HashSet<SocketAddr> = self.saved.iter().map(|record| record.addr).collect();
+        addrs.retain(|addr| !tested.contains(addr));
+    }
+
+    /// Queue a completed address, appending to the file every `FLUSH_EVERY`.
+    pub(crate) fn record(&mut self, record: Record) -> Result<()> {
+        self.pending.push(record);
+        if self.pending.len() >= FLUSH_EVERY {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        let mut lines = String::new();
+        for record in self.pending.drain(..) {
+            lines.push_str(&serde_json::to_string(&record)?);
+            lines.push('\n');
+        }
+        self.file
+            .write_all(lines.as_bytes())
+            .with_context(|| format!("Failed to write {}", self.path.display()))
+    }
+
+    /// Add the results saved by previous runs to `results` and `tested`,
+    /// after the ones of this run.
+    pub(crate) fn merge_into(&self, results: &mut ScanResults, tested: &mut Vec<SocketAddr>) {
+        for record in &self.saved {
+            tested.push(record.addr);
+            match record.latency {
+                Some(latency) => {
+                    let samples = if record.samples.is_empty() {
+                        vec![latency]
+                    } else {
+                        record.samples.clone()
+                    };
+                    results.alive.push(TestResult::from_samples(
+                        record.addr,
+                        samples,
+                        record.attempts,
+                    ));
+                }
+                None => results.dead.push(DeadAddr {
+                    addr: record.addr,
+                    latency_ms: None,
+                    status: record.status,
+                }),
+            }
+        }
+    }
+
+    /// Flush the remaining records, then delete the file unless `keep` is set.
+    pub(crate) fn finish(mut self, keep: bool) -> Result<()> {
+        self.flush()?;
+        if !keep {
+            fs::remove_file(&self.path)
+                .with_context(|| format!("Failed to remove {}", self.path.display()))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resume_keeps_the_seed_and_unit_and_skips_tested() {
+        let dir = std::env::temp_dir().join(format!("warp-checkpoint-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("checkpoint.jsonl");
+        let _ = fs::remove_file(&path);
+        let tested: SocketAddr = "10.0.0.1:2408".parse().unwrap();
+        let untested: SocketAddr = "10.0.0.2:2408".parse().unwrap();
+
+        let mut checkpoint = Checkpoint::open(&path, 7, LatencyUnit::Us).unwrap();
+        let result = TestResult::from_samples(tested, vec![10], 1);
+        checkpoint.record(Record::from(&result)).unwrap();
+        checkpoint.finish(true).unwrap();
+
+        assert!(Checkpoint::open(&path, 8, LatencyUnit::Ms).is_err());
+        let checkpoint = Checkpoint::open(&path, 8, LatencyUnit::Us).unwrap();
+        assert_eq!(checkpoint.seed(), 7);
+        let mut addrs = vec![tested, untested];
+        checkpoint.skip_tested(&mut addrs);
+        assert_eq!(addrs, [untested]);
+        checkpoint.finish(false).unwrap();
+    }
+}
@@ -0,0 +1,215 @@
+use anyhow::{Context, Result, bail};
+use ipnetwork::{IpNetwork, Ipv4Network, Ipv6Network};
+use log::{debug, info, warn};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// Cloudflare's published list of IPv4 prefixes.
+const IPS_V4_URL: &str = "https://www.cloudflare.com/ips-v4";
+
+/// How long a fetched list is reused before fetching again.
+const CACHE_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Timeout of the whole HTTP request.
+const FETCH_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Timeout of resolving a `--cidr` hostname.
+const RESOLVE_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Serialize, Deserialize)]
+struct Cache {
+    /// Unix time the list was fetched
+    fetched_at: i64,
+    /// The published prefixes, matched against the known ranges of each run.
+    /// Older caches stored the matched ranges, which match themselves.
+    #[serde(alias = "cidrs")]
+    published: Vec<Ipv4Network>,
+}
+
+/// `$XDG_CACHE_HOME/warp-speedtest`, falling back to `~/.cache/warp-speedtest`.
+pub(crate) fn cache_dir() -> Option<PathBuf> {
+    let base = std::env::var_os("XDG_CACHE_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".cache")))?;
+    Some(base.join("warp-speedtest"))
+}
+
+fn cache_path() -> Option<PathBuf> {
+    cache_dir().map(|dir| dir.join("cidrs.json"))
+}
+
+fn read_cache() -> Option<Cache> {
+    let path = cache_path()?;
+    let contents = fs::read_to_string(&path).ok()?;
+    serde_json::from_str(&contents)
+        .inspect_err(|e| warn!("Ignoring invalid cache {}: {e}", path.display()))
+        .ok()
+}
+
+fn write_cache(published: &[Ipv4Network]) -> Result<()> {
+    let path = cache_path().context("Could not determine the cache directory")?;
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let cache = Cache {
+        fetched_at: jiff::Timestamp::now().as_second(),
+        published: published.to_vec(),
+    };
+    fs::write(&path, serde_json::to_string(&cache)?)
+        .with_context(|| format!("Failed to write {}", path.display()))
+}
+
+/// Download the current prefix list.
+async fn fetch_published() -> Result<Vec<Ipv4Network>> {
+    let client = reqwest::Client::builder()
+        .timeout(FETCH_TIMEOUT)
+        .user_agent(concat!(
+            env!("CARGO_PKG_NAME"),
+            "/",
+            env!("CARGO_PKG_VERSION")
+        ))
+        .build()?;
+    let body = client
+        .get(IPS_V4_URL)
+        .send()
+        .await?
+        .error_for_status()?
+        .text()
+        .await?;
+
+    let published: Vec<Ipv4Network> = body
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .filter_map(|line| {
+            line.parse()
+                .inspect_err(|e| debug!("Skipping {line:?} from {IPS_V4_URL}: {e}"))
+                .ok()
+        })
+        .collect();
+
+    if published.is_empty() {
+        bail!("{IPS_V4_URL} returned no prefixes");
+    }
+    Ok(published)
+}
+
+/// The WARP ranges still covered by Cloudflare's published prefixes.
+///
+/// The published list only has large aggregates, so the WARP-specific ranges
+/// are the entries of `known` that fall inside one of them.
+fn warp_ranges(published: &[Ipv4Network], known: &[Ipv4Network]) -> Vec<Ipv4Network> {
+    known
+        .iter()
+        .filter(|range| {
+            published
+                .iter()
+                .any(|prefix| prefix.contains(range.network()) && prefix.prefix() <= range.prefix())
+        })
+        .copied()
+        .collect()
+}
+
+/// The entries of `known` still published, from a cache younger than a day
+/// or freshly fetched. Falls back to a stale cache, then to `known`, if
+/// fetching fails.
+///
+/// The cache keeps the published prefixes rather than the matched ranges, so
+/// a changed `known` list is matched again instead of reusing the old one.
+pub(crate) async fn fetch_v4(known: &[Ipv4Network]) -> Vec<Ipv4Network> {
+    let cached = read_cache()
+        .map(|cache| (cache.fetched_at, warp_ranges(&cache.published, known)))
+        .filter(|(_, ranges)| !ranges.is_empty());
+    if let Some((fetched_at, ranges)) = &cached
+        && jiff::Timestamp::now().as_second() - fetched_at < CACHE_TTL.as_secs() as i64
+    {
+        debug!("Using cached WARP ranges from {fetched_at}");
+        return ranges.clone();
+    }
+
+    let fetched = fetch_published().await.and_then(|published| {
+        let ranges = warp_ranges(&published, known);
+        if ranges.is_empty() {
+            bail!("none of the known WARP ranges are published anymore");
+        }
+        Ok((published, ranges))
+    });
+
+    match fetched {
+        Ok((published, ranges)) => {
+            info!("Fetched {} WARP ranges from {IPS_V4_URL}", ranges.len());
+            if let Err(e) = write_cache(&published) {
+                warn!("Failed to cache WARP ranges: {e:#}");
+            }
+            ranges
+        }
+        Err(e) => match cached {
+            Some((_, ranges)) => {
+                warn!("Failed to fetch WARP ranges, using the cached list: {e:#}");
+                ranges
+            }
+            None => {
+                warn!("Failed to fetch WARP ranges, using the built-in list: {e:#}");
+                known.to_vec()
+            }
+        },
+    }
+}
+
+/// Whether `value` looks like a DNS name rather than a mistyped CIDR.
+fn is_hostname(value: &str) -> bool {
+    value.len() <= 253
+        && value.contains(|c: char| c.is_ascii_alphabetic())
+        && value.split('.').all(|label| {
+            !label.is_empty()
+                && label.len() <= 63
+                && !label.starts_with('-')
+                && !label.ends_with('-')
+                && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+        })
+}
+
+/// Every address `host` resolves to, as single-address networks.
+async fn resolve(host: &str) -> Result<Vec<IpNetwork>> {
+    let addrs = tokio::time::timeout(RESOLVE_TIMEOUT, tokio::net::lookup_host((host, 0)))
+        .await
+        .with_context(|| format!("Timed out resolving {host}"))?
+        .with_context(|| format!("Failed to resolve {host}"))?;
+
+    let networks: Vec<IpNetwork> = addrs.map(|addr| IpNetwork::from(addr.ip())).collect();
+    if networks.is_empty() {
+        bail!("{host} did not resolve to any address");
+    }
+    debug!("Resolved {host} to {networks:?}");
+    Ok(networks)
+}
+
+/// The `--cidr` ranges split by family. Values that are not a CIDR or IP but
+/// a hostname are resolved to the addresses it points to.
+pub(crate) async fn parse_ranges(
+    values: &[String],
+) -> Result<(Vec<Ipv4Network>, Vec<Ipv6Network>)> {
+    let mut v4 = Vec::new();
+    let mut v6 = Vec::new();
+    for value in values {
+        let networks = match value.parse::<IpNetwork>() {
+            Ok(network) => vec![network],
+            Err(_) if is_hostname(value) => resolve(value).await?,
+            Err(e) => bail!("Invalid --cidr {value:?}: {e}"),
+        };
+        for network in networks {
+            match network {
+                IpNetwork::V4(network) => v4.push(network),
+                IpNetwork::V6(network) => v6.push(network),
+            }
+        }
+    }
+
+    if v4.is_empty() {
+        bail!("--cidr needs at least one IPv4 range, IPv6 addresses are derived from them");
+    }
+    Ok((v4, v6))
+}
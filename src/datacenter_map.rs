@@ -0,0 +1,21 @@
+use crate::subnet;
+use phf::phf_map;
+use std::net::IpAddr;
+
+/// Shown for addresses outside every known subnet.
+pub(crate) const UNKNOWN: &str = "unknown";
+
+/// IATA code of the Cloudflare PoP serving each /24 (IPv4) or /48 (IPv6),
+/// keyed like [`subnet::subnet_of`] prints it.
+///
+/// The WARP ranges are anycast, so which PoP answers usually depends on
+/// where the client is rather than on the address. Only subnets seen pinned
+/// to a single PoP belong here.
+static POPS: phf::Map<&'static str, &'static str> = phf_map! {};
+
+/// The PoP serving `ip`, [`UNKNOWN`] if its subnet is not mapped.
+pub(crate) fn pop_of(ip: IpAddr) -> &'static str {
+    POPS.get(subnet::subnet_of(ip).to_string().as_str())
+        .copied()
+        .unwrap_or(UNKNOWN)
+}
@@ -0,0 +1,91 @@
+use crate::args::DiffArgs;
+use anyhow::{Context, Result, bail};
+use console::style;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::fs;
+use std::net::SocketAddr;
+use std::path::Path;
+
+/// The working addresses of a saved JSON report with their latency in
+/// milliseconds, in the order they were saved.
+pub(crate) fn load(path: &Path) -> Result<Vec<(SocketAddr, f64)>> {
+    let contents =
+        fs::read_to_string(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    let report: Value = serde_json::from_str(&contents)
+        .with_context(|| format!("Failed to parse {}", path.display()))?;
+    // Reports saved with `--latency-unit us` say so, older ones are in ms
+    let (key, per_ms) = match report["latency_unit"].as_str() {
+        Some("us") => ("latency_us", 1000.0),
+        _ => ("latency_ms", 1.0),
+    };
+
+    report["results"]
+        .as_array()
+        .with_context(|| format!("{} has no results", path.display()))?
+        .iter()
+        .map(|result| {
+            let addr = result["addr"].as_str().unwrap_or_default();
+            let addr = addr
+                .parse()
+                .with_context(|| format!("Invalid address {addr:?} in {}", path.display()))?;
+            // Before the keys were named by unit, µs were under `latency_ms`
+            let latency = result
+                .get(key)
+                .or_else(|| result.get("latency_ms"))
+                .and_then(Value::as_f64)
+                .with_context(|| format!("{addr} has no latency in {}", path.display()))?;
+            Ok((addr, latency / per_ms))
+        })
+        .collect()
+}
+
+/// Print every address of both files with its latency in each and the change
+/// between them.
+pub(crate) fn run(args: &DiffArgs) -> Result<()> {
+    let a = load(&args.a)?;
+    let b = load(&args.b)?;
+    let in_a: HashMap<SocketAddr, f64> = a.iter().copied().collect();
+    let in_b: HashMap<SocketAddr, f64> = b.iter().copied().collect();
+
+    println!(
+        "{:<45} {:>10} {:>10} {:>10}",
+        "Address", "A (ms)", "B (ms)", "Delta"
+    );
+
+    let mut exceeded = 0;
+    for (addr, latency_a) in &a {
+        let Some(latency_b) = in_b.get(addr) else {
+            println!(
+                "{addr:<45} {latency_a:>10.1} {:>10} {:>10}",
+                "-", "ONLY_IN_A"
+            );
+            continue;
+        };
+
+        let delta = latency_b - latency_a;
+        let row = format!("{addr:<45} {latency_a:>10.1} {latency_b:>10.1} {delta:>+10.1}");
+        if args
+            .threshold_ms
+            .is_some_and(|threshold| delta.abs() > threshold)
+        {
+            exceeded += 1;
+            println!("{}", style(row).red());
+        } else {
+            println!("{row}");
+        }
+    }
+    for (addr, latency_b) in b.iter().filter(|(addr, _)| !in_a.contains_key(addr)) {
+        println!(
+            "{addr:<45} {:>10} {latency_b:>10.1} {:>10}",
+            "-", "ONLY_IN_B"
+        );
+    }
+
+    if let Some(threshold) = args.threshold_ms
+        && exceeded > 0
+    {
+        bail!("{exceeded} addresses changed by more than {threshold} ms");
+    }
+    Ok(())
+}
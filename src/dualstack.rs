@@ -0,0 +1,78 @@
+use crate::TestResult;
+use crate::args::LatencyUnit;
+use schemars::JsonSchema;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+
+/// Latency of an IPv4 endpoint next to its IPv6 twin.
+#[derive(Debug, Serialize, JsonSchema)]
+pub(crate) struct Comparison {
+    pub(crate) v4: SocketAddr,
+    pub(crate) v6: SocketAddr,
+    pub(crate) v4_latency_ms: u128,
+    pub(crate) v6_latency_ms: u128,
+    /// IPv6 latency minus IPv4 latency
+    pub(crate) delta_ms: i128,
+    pub(crate) winner: Winner,
+}
+
+#[derive(Debug, Serialize, JsonSchema, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum Winner {
+    V4,
+    V6,
+    Tie,
+}
+
+impl Comparison {
+    /// Human-readable winner with latencies in `unit`, e.g.
+    /// `v4 faster by 15 ms`.
+    pub(crate) fn verdict(&self, unit: LatencyUnit) -> String {
+        let unit = unit.label();
+        match self.winner {
+            Winner::V4 => format!("v4 faster by {} {unit}", self.delta_ms),
+            Winner::V6 => format!("v6 faster by {} {unit}", -self.delta_ms),
+            Winner::Tie => "tie".to_string(),
+        }
+    }
+}
+
+/// The low 32 bits shared by an IPv4 address and its IPv6 twin.
+fn suffix(ip: IpAddr) -> u32 {
+    match ip {
+        IpAddr::V4(ip) => ip.into(),
+        IpAddr::V6(ip) => u128::from(ip) as u32,
+    }
+}
+
+/// Pair every working IPv4 result with the working IPv6 result of its twin,
+/// ordered like `alive`.
+pub(crate) fn compare(alive: &[TestResult]) -> Vec<Comparison> {
+    let v6: HashMap<(u32, u16), &TestResult> = alive
+        .iter()
+        .filter(|result| result.addr.is_ipv6())
+        .map(|result| ((suffix(result.addr.ip()), result.addr.port()), result))
+        .collect();
+
+    alive
+        .iter()
+        .filter(|result| result.addr.is_ipv4())
+        .filter_map(|v4| {
+            let v6 = v6.get(&(suffix(v4.addr.ip()), v4.addr.port()))?;
+            let delta_ms = v6.latency as i128 - v4.latency as i128;
+            Some(Comparison {
+                v4: v4.addr,
+                v6: v6.addr,
+                v4_latency_ms: v4.latency,
+                v6_latency_ms: v6.latency,
+                delta_ms,
+                winner: match delta_ms {
+                    0 => Winner::Tie,
+                    d if d > 0 => Winner::V4,
+                    _ => Winner::V6,
+                },
+            })
+        })
+        .collect()
+}
@@ -0,0 +1,375 @@
+use crate::args::LatencyUnit;
+use crate::output;
+use crate::{Ranking, TestResult};
+use anyhow::{Context, Result, bail};
+use serde::Serialize;
+use std::fmt::Write as _;
+use std::fs::{self, OpenOptions};
+use std::io::Write as _;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::process::Command;
+
+/// Resolvers of the WARP client, used in `--export-warp-conf`.
+const WARP_DNS: [&str; 4] = [
+    "1.1.1.1",
+    "1.0.0.1",
+    "2606:4700:4700::1111",
+    "2606:4700:4700::1001",
+];
+
+/// Replace `path` with `contents` via a temporary file and a rename, so
+/// readers never see a partially written file.
+pub(crate) fn write_atomic(path: &Path, contents: &str) -> Result<()> {
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+
+    fs::write(&tmp, contents).with_context(|| format!("Failed to write {}", path.display()))?;
+    fs::rename(&tmp, path).with_context(|| format!("Failed to write {}", path.display()))
+}
+
+/// Put the `ip:port` of `best` on the system clipboard.
+#[cfg(feature = "clipboard")]
+pub(crate) fn copy_to_clipboard(best: &TestResult) -> Result<()> {
+    arboard::Clipboard::new()
+        .and_then(|mut clipboard| clipboard.set_text(best.addr.to_string()))
+        .context("Failed to copy to the clipboard")
+}
+
+/// Show a desktop notification summarizing the scan, with latencies in
+/// `unit`.
+#[cfg(feature = "notify")]
+pub(crate) fn notify(
+    best: Option<&TestResult>,
+    alive: usize,
+    tested: usize,
+    unit: LatencyUnit,
+) -> Result<()> {
+    let body = match best {
+        Some(best) => format!(
+            "Best endpoint: {} at {}{}. Found {alive}/{tested} alive.",
+            best.addr,
+            best.latency,
+            unit.label()
+        ),
+        None => "No working WARP endpoints found.".to_string(),
+    };
+    notify_rust::Notification::new()
+        .summary("WARP Speedtest Complete")
+        .body(&body)
+        .show()
+        .context("Failed to show a notification")?;
+    Ok(())
+}
+
+/// Write one row per round and working address of that round to `path`,
+/// with latencies in `unit`.
+///
+/// With `append` the rows are added to an existing file, and the header is
+/// only written if the file is new or empty.
+pub(crate) fn write_timeseries(
+    path: &Path,
+    rounds: &[Vec<TestResult>],
+    append: bool,
+    unit: LatencyUnit,
+) -> Result<()> {
+    let mut file = OpenOptions::new()
+        .create(true)
+        .write(true)
+        .append(append)
+        .truncate(!append)
+        .open(path)
+        .with_context(|| format!("Failed to open {}", path.display()))?;
+
+    let mut csv = String::new();
+    if file.metadata()?.len() == 0 {
+        writeln!(csv, "round,addr,{},loss_pct", unit.column("latency"))?;
+    }
+    for (round, results) in rounds.iter().enumerate() {
+        for result in results {
+            writeln!(
+                csv,
+                "{},{},{},{:.1}",
+                round + 1,
+                result.addr,
+                result.latency,
+                result.loss_pct
+            )?;
+        }
+    }
+
+    file.write_all(csv.as_bytes())
+        .with_context(|| format!("Failed to write {}", path.display()))
+}
+
+/// The `--summary-json` object.
+#[derive(Serialize)]
+struct Summary {
+    alive: usize,
+    total: usize,
+    best_addr: Option<SocketAddr>,
+    best_ms: Option<u128>,
+    scan_duration_ms: u128,
+    ts: String,
+}
+
+/// Atomically replace `path` with a one-line JSON summary of `ranking`, with
+/// latencies in `unit`.
+pub(crate) fn write_summary(path: &Path, ranking: &Ranking, unit: LatencyUnit) -> Result<()> {
+    let best = ranking.results.first();
+    let summary = Summary {
+        alive: ranking.results.len(),
+        total: ranking.tested,
+        best_addr: best.map(|best| best.addr),
+        best_ms: best.map(|best| best.latency),
+        scan_duration_ms: ranking.duration.as_millis(),
+        ts: jiff::Timestamp::now().to_string(),
+    };
+    write_atomic(path, &output::json(&summary, unit, false)?)
+}
+
+/// What `--export-warp-conf` needs from a `warp-registration.json` file.
+pub(crate) struct Registration {
+    /// WARP public key, at `AccountData.config.peers[0].public_key`
+    pub(crate) public_key: String,
+    /// Private key of the device, at `PrivateKey` or `private_key`
+    pub(crate) private_key: Option<String>,
+    /// Tunnel addresses, at `AccountData.config.interface.addresses`
+    pub(crate) addresses: Vec<String>,
+}
+
+/// Read the WARP public key, and the device keys and addresses if present,
+/// from a `warp-registration.json` file.
+pub(crate) fn read_registration(path: &Path) -> Result<Registration> {
+    let contents =
+        fs::read_to_string(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    let registration: serde_json::Value = serde_json::from_str(&contents)
+        .with_context(|| format!("{} is not valid JSON", path.display()))?;
+    let string = |pointer: &str| {
+        registration
+            .pointer(pointer)
+            .and_then(serde_json::Value::as_str)
+            .map(str::to_string)
+    };
+    let public_key = string("/AccountData/config/peers/0/public_key").with_context(|| {
+        format!(
+            "{} has no AccountData.config.peers[0].public_key",
+            path.display()
+        )
+    })?;
+    Ok(Registration {
+        public_key,
+        private_key: string("/PrivateKey").or_else(|| string("/private_key")),
+        addresses: ["v4", "v6"]
+            .iter()
+            .filter_map(|version| {
+                string(&format!(
+                    "/AccountData/config/interface/addresses/{version}"
+                ))
+            })
+            .collect(),
+    })
+}
+
+/// Write one WireGuard `[Peer]` block per result to `path`.
+///
+/// Without `public_key` the `PublicKey` line is left as a placeholder comment.
+pub(crate) fn write_wireguard(
+    path: &Path,
+    results: &[TestResult],
+    public_key: Option<&str>,
+    unit: LatencyUnit,
+) -> Result<()> {
+    let mut config = format!(
+        "# Generated by {} at {}\n",
+        env!("CARGO_PKG_NAME"),
+        jiff::Timestamp::now()
+    );
+
+    for result in results {
+        write!(
+            config,
+            "\n# {} - {} {}\n[Peer]\n",
+            result.addr,
+            result.latency,
+            unit.label()
+        )?;
+        match public_key {
+            Some(key) => writeln!(config, "PublicKey = {key}")?,
+            None => writeln!(config, "# PublicKey = <WARP public key>")?,
+        }
+        writeln!(config, "# AllowedIPs = 0.0.0.0/0, ::/0")?;
+        writeln!(config, "# PersistentKeepalive = 25")?;
+        writeln!(config, "Endpoint = {}", result.addr)?;
+    }
+
+    fs::write(path, config).with_context(|| format!("Failed to write {}", path.display()))
+}
+
+/// Write a systemd-networkd WireGuard device for `interface` with `best` as
+/// its only peer to `path`.
+///
+/// `[WireGuard]` sections are only read from `.netdev` files, so this is one.
+/// The private key is left as a placeholder, like the public key without
+/// `public_key`.
+pub(crate) fn write_networkd(
+    path: &Path,
+    best: &TestResult,
+    interface: &str,
+    public_key: Option<&str>,
+) -> Result<()> {
+    let mut config = format!(
+        "# Generated by {} at {}\n",
+        env!("CARGO_PKG_NAME"),
+        jiff::Timestamp::now()
+    );
+    writeln!(config, "\n[NetDev]\nName={interface}\nKind=wireguard")?;
+    writeln!(config, "\n[WireGuard]\n# PrivateKey=<WARP private key>")?;
+    writeln!(config, "\n[WireGuardPeer]")?;
+    match public_key {
+        Some(key) => writeln!(config, "PublicKey={key}")?,
+        None => writeln!(config, "# PublicKey=<WARP public key>")?,
+    }
+    writeln!(config, "AllowedIPs=0.0.0.0/0, ::/0")?;
+    writeln!(config, "Endpoint={}", best.addr)?;
+
+    fs::write(path, config).with_context(|| format!("Failed to write {}", path.display()))
+}
+
+/// Write a complete WireGuard configuration with `best` as the peer to
+/// `path`, readable only by its owner as it holds the private key.
+///
+/// Without a registration, or with one lacking the device key or addresses,
+/// the missing values are placeholders marked `# FILL IN`.
+pub(crate) fn write_warp_conf(
+    path: &Path,
+    best: &TestResult,
+    registration: Option<&Registration>,
+    public_key: Option<&str>,
+) -> Result<()> {
+    let mut config = format!(
+        "# Generated by {} at {}\n\n[Interface]\n",
+        env!("CARGO_PKG_NAME"),
+        jiff::Timestamp::now()
+    );
+    match registration.and_then(|reg| reg.private_key.as_deref()) {
+        Some(key) => writeln!(config, "PrivateKey = {key}")?,
+        None => writeln!(config, "PrivateKey = <device private key> # FILL IN")?,
+    }
+    match registration.filter(|reg| !reg.addresses.is_empty()) {
+        Some(reg) => writeln!(config, "Address = {}", reg.addresses.join(", "))?,
+        None => writeln!(config, "Address = <tunnel addresses> # FILL IN")?,
+    }
+    writeln!(config, "DNS = {}", WARP_DNS.join(", "))?;
+    writeln!(config, "MTU = 1280")?;
+
+    writeln!(config, "\n[Peer]")?;
+    match public_key {
+        Some(key) => writeln!(config, "PublicKey = {key}")?,
+        None => writeln!(config, "PublicKey = <WARP public key> # FILL IN")?,
+    }
+    writeln!(config, "AllowedIPs = 0.0.0.0/0, ::/0")?;
+    writeln!(config, "Endpoint = {}", best.addr)?;
+
+    let write = || {
+        let mut file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)?;
+        // Also narrows the mode of a file that already existed
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            file.set_permissions(fs::Permissions::from_mode(0o600))?;
+        }
+        file.write_all(config.as_bytes())
+    };
+    write().with_context(|| format!("Failed to write {}", path.display()))
+}
+
+/// Write a `resolv.conf` with the IP of `best` as the nameserver, and a
+/// `search` line for `search` if given, to `path`.
+pub(crate) fn write_resolv_conf(
+    path: &Path,
+    best: &TestResult,
+    search: Option<&str>,
+) -> Result<()> {
+    let mut config = format!(
+        "# Generated by {} at {}\n",
+        env!("CARGO_PKG_NAME"),
+        jiff::Timestamp::now()
+    );
+    if let Some(domain) = search {
+        writeln!(config, "search {domain}")?;
+    }
+    writeln!(config, "nameserver {}", best.addr.ip())?;
+
+    fs::write(path, config).with_context(|| format!("Failed to write {}", path.display()))
+}
+
+/// The `warp-cli` invocation that points the client at `result`.
+fn warp_cli_args(result: &TestResult) -> [String; 2] {
+    ["set-custom-endpoint".to_string(), result.addr.to_string()]
+}
+
+/// Print one `warp-cli set-custom-endpoint` command per result to stdout.
+pub(crate) fn print_warp_cli(results: &[TestResult]) {
+    for result in results {
+        println!("warp-cli {}", warp_cli_args(result).join(" "));
+    }
+}
+
+/// Point `warp-cli` at the best result.
+pub(crate) fn exec_warp_cli(best: &TestResult) -> Result<()> {
+    let status = Command::new("warp-cli")
+        .args(warp_cli_args(best))
+        .status()
+        .context("Failed to run warp-cli")?;
+
+    if !status.success() {
+        bail!("warp-cli exited with {status}");
+    }
+    Ok(())
+}
+
+/// Run `command` through `sh -c`, describing the best result, the number of
+/// working addresses and of tested ones, and every `--tag` in `WARP_*`
+/// environment variables.
+pub(crate) fn run_on_complete(
+    command: &str,
+    best: Option<&TestResult>,
+    ranking: &Ranking,
+    tags: &[(String, String)],
+) -> Result<()> {
+    let mut hook = Command::new("sh");
+    hook.arg("-c")
+        .arg(command)
+        .env("WARP_ALIVE_COUNT", ranking.results.len().to_string())
+        .env("WARP_TOTAL_TESTED", ranking.tested.to_string());
+    for (key, value) in tags {
+        let key: String = key
+            .chars()
+            .map(|c| {
+                if c.is_ascii_alphanumeric() {
+                    c.to_ascii_uppercase()
+                } else {
+                    '_'
+                }
+            })
+            .collect();
+        hook.env(format!("WARP_TAG_{key}"), value);
+    }
+    if let Some(best) = best {
+        hook.env("WARP_BEST_ADDR", best.addr.to_string())
+            .env("WARP_BEST_LATENCY_MS", best.latency.to_string());
+    }
+
+    let status = hook
+        .status()
+        .with_context(|| format!("Failed to run {command:?}"))?;
+    if !status.success() {
+        bail!("{command:?} exited with {status}");
+    }
+    Ok(())
+}
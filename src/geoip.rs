@@ -0,0 +1,47 @@
+use crate::TestResult;
+use anyhow::{Context, Result};
+use log::debug;
+use maxminddb::{Reader, geoip2};
+use schemars::JsonSchema;
+use serde::Serialize;
+use std::path::Path;
+
+#[derive(Clone, Debug, Serialize, JsonSchema)]
+pub(crate) struct Country {
+    /// ISO 3166-1 alpha-2 code, e.g. `US`
+    pub(crate) country_code: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) country_name: Option<String>,
+}
+
+/// Fill in the country of every result from the MaxMind database at `path`.
+pub(crate) fn annotate(path: &Path, results: &mut [TestResult]) -> Result<()> {
+    let reader = Reader::open_readfile(path)
+        .with_context(|| format!("Failed to open the GeoIP database {}", path.display()))?;
+    for result in results {
+        let ip = result.addr.ip();
+        let record = reader
+            .lookup(ip)
+            .and_then(|found| found.decode::<geoip2::Country>())
+            .with_context(|| format!("Failed to look up {ip} in {}", path.display()))?;
+        result.country = record.and_then(|record| {
+            Some(Country {
+                country_code: record.country.iso_code?.to_string(),
+                country_name: record.country.names.english.map(str::to_string),
+            })
+        });
+        if result.country.is_none() {
+            debug!("{ip} is not in the GeoIP database");
+        }
+    }
+    Ok(())
+}
+
+/// Whether `result` is in one of the `countries`, given as ISO codes.
+pub(crate) fn in_countries(result: &TestResult, countries: &[String]) -> bool {
+    result.country.as_ref().is_some_and(|country| {
+        countries
+            .iter()
+            .any(|code| code.eq_ignore_ascii_case(&country.country_code))
+    })
+}
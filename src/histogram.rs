@@ -0,0 +1,109 @@
+use crate::args::LatencyUnit;
+use schemars::JsonSchema;
+use serde::Serialize;
+
+/// Width reserved for the log prefix, label and count around each bar.
+const BAR_PADDING: usize = 32;
+
+/// Buckets of the histogram without `--histogram-bucket-ms` or
+/// `--histogram-buckets`.
+pub(crate) const DEFAULT_BUCKETS: u32 = 10;
+
+#[derive(Debug, Serialize, JsonSchema)]
+pub(crate) struct Bucket {
+    pub(crate) range_ms: [u128; 2],
+    pub(crate) count: usize,
+}
+
+/// Count `latencies` into buckets of `bucket_ms` width.
+///
+/// Buckets span from the lowest to the highest occupied one, empty buckets in
+/// between are kept so the shape of the distribution stays visible.
+pub(crate) fn build(latencies: impl Iterator<Item = u128>, bucket_ms: u128) -> Vec<Bucket> {
+    let bucket_ms = bucket_ms.max(1);
+    let indices: Vec<u128> = latencies.map(|latency| latency / bucket_ms).collect();
+
+    let (Some(first), Some(last)) = (indices.iter().min(), indices.iter().max()) else {
+        return Vec::new();
+    };
+
+    (*first..=*last)
+        .map(|index| Bucket {
+            range_ms: [index * bucket_ms, (index + 1) * bucket_ms],
+            count: indices.iter().filter(|&&i| i == index).count(),
+        })
+        .collect()
+}
+
+/// Count `latencies` into `buckets` buckets of equal width between `bounds`,
+/// or between the lowest and the highest latency if `None`.
+///
+/// Widths are rounded up to a whole latency unit, latencies outside the bounds
+/// count toward the first or last bucket. If all latencies are the same, a
+/// single bucket holds them.
+pub(crate) fn build_even(
+    latencies: impl Iterator<Item = u128>,
+    bounds: Option<[u128; 2]>,
+    buckets: u32,
+) -> Vec<Bucket> {
+    let latencies: Vec<u128> = latencies.collect();
+    let (Some(&lowest), Some(&highest)) = (latencies.iter().min(), latencies.iter().max()) else {
+        return Vec::new();
+    };
+    let [min, max] = bounds.unwrap_or([lowest, highest]);
+    if max <= min {
+        return vec![Bucket {
+            range_ms: [min, max],
+            count: latencies.len(),
+        }];
+    }
+
+    let buckets = u128::from(buckets.max(1));
+    let width = (max - min).div_ceil(buckets);
+    let mut counts = vec![0; buckets as usize];
+    for latency in latencies {
+        let index = (latency.saturating_sub(min) / width).min(buckets - 1);
+        counts[index as usize] += 1;
+    }
+    counts
+        .into_iter()
+        .zip(0..)
+        .map(|(count, index)| Bucket {
+            range_ms: [min + index * width, min + (index + 1) * width],
+            count,
+        })
+        .collect()
+}
+
+/// Render buckets as horizontal bars in `unit`, e.g.
+/// `[ 10ms- 20ms] ██████████ 42`.
+///
+/// Bars are scaled so the largest bucket fills the terminal width.
+pub(crate) fn render(buckets: &[Bucket], unit: LatencyUnit) -> Vec<String> {
+    let unit = unit.label();
+    let max_count = buckets.iter().map(|b| b.count).max().unwrap_or(0).max(1);
+    let label_width = buckets
+        .iter()
+        .map(|b| b.range_ms[1].to_string().len())
+        .max()
+        .unwrap_or(1);
+    let terminal_width = console::Term::stderr().size().1 as usize;
+    let bar_width = terminal_width
+        .saturating_sub(BAR_PADDING + label_width * 2)
+        .max(10);
+
+    buckets
+        .iter()
+        .map(|bucket| {
+            let len = (bucket.count * bar_width).div_ceil(max_count);
+            format!(
+                "[{:>w$}{unit}-{:>w$}{unit}] {} {}",
+                bucket.range_ms[0],
+                bucket.range_ms[1],
+                "█".repeat(len),
+                bucket.count,
+                w = label_width
+            )
+        })
+        .collect()
+}
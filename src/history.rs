@@ -0,0 +1,164 @@
+use crate::TestResult;
+use crate::args::{HistoryArgs, LatencyUnit};
+use anyhow::{Context, Result};
+use jiff::Timestamp;
+use jiff::tz::TimeZone;
+use log::debug;
+use rusqlite::{Connection, params};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::PathBuf;
+
+const SCHEMA: &str = "CREATE TABLE IF NOT EXISTS results (
+    id INTEGER PRIMARY KEY,
+    ts INTEGER NOT NULL,
+    addr TEXT NOT NULL,
+    port INTEGER NOT NULL,
+    latency_ms INTEGER NOT NULL,
+    loss_pct REAL NOT NULL
+)";
+
+const SECONDS_PER_DAY: f64 = 24.0 * 60.0 * 60.0;
+
+/// Shortest span of scans, in days, that a trend is shown for.
+const MIN_TREND_SPAN: f64 = 1.0 / 24.0;
+
+/// `$XDG_DATA_HOME/warp-speedtest/history.db`, by default under
+/// `~/.local/share`.
+fn db_path() -> Option<PathBuf> {
+    let base = std::env::var_os("XDG_DATA_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| {
+            std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".local/share"))
+        })?;
+    Some(base.join("warp-speedtest").join("history.db"))
+}
+
+fn open() -> Result<Connection> {
+    let path = db_path().context("Could not determine the data directory")?;
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+    }
+    let connection =
+        Connection::open(&path).with_context(|| format!("Failed to open {}", path.display()))?;
+    connection.execute(SCHEMA, [])?;
+    Ok(connection)
+}
+
+/// Add the working addresses of a scan, with latencies in `unit`, to the
+/// history database.
+pub(crate) fn record(results: &[TestResult], unit: LatencyUnit) -> Result<()> {
+    let mut connection = open()?;
+    let ts = Timestamp::now().as_second();
+    let transaction = connection.transaction()?;
+    {
+        let mut insert = transaction.prepare(
+            "INSERT INTO results (ts, addr, port, latency_ms, loss_pct) VALUES (?1, ?2, ?3, ?4, ?5)",
+        )?;
+        for result in results {
+            insert.execute(params![
+                ts,
+                result.addr.ip().to_string(),
+                result.addr.port(),
+                (result.latency / unit.per_ms()) as i64,
+                result.loss_pct
+            ])?;
+        }
+    }
+    transaction.commit()?;
+    debug!("Recorded {} results in the history", results.len());
+    Ok(())
+}
+
+/// One stored result.
+struct Row {
+    ts: i64,
+    addr: String,
+    latency_ms: i64,
+    loss_pct: f64,
+}
+
+/// Least squares slope of `(days, latency)` points, in ms per day. `None`
+/// when the points span less than [`MIN_TREND_SPAN`], as scans minutes apart
+/// would extrapolate noise to a daily rate.
+fn trend(points: &[(f64, f64)]) -> Option<f64> {
+    let n = points.len() as f64;
+    let mean_x = points.iter().map(|(x, _)| x).sum::<f64>() / n;
+    let mean_y = points.iter().map(|(_, y)| y).sum::<f64>() / n;
+    let covariance: f64 = points
+        .iter()
+        .map(|(x, y)| (x - mean_x) * (y - mean_y))
+        .sum();
+    let variance: f64 = points.iter().map(|(x, _)| (x - mean_x).powi(2)).sum();
+    let (first, last) = (points.first()?.0, points.last()?.0);
+    (last - first >= MIN_TREND_SPAN && variance > 0.0).then(|| covariance / variance)
+}
+
+/// Print the stored results matching `args`, or per-address statistics with
+/// `--stats`.
+pub(crate) fn run(args: &HistoryArgs) -> Result<()> {
+    let since = match args.since {
+        Some(date) => date.to_zoned(TimeZone::UTC)?.timestamp().as_second(),
+        None => 0,
+    };
+    let (ip, port) = match &args.addr {
+        Some(addr) => (Some(addr.ip().to_string()), Some(addr.port())),
+        None => (None, None),
+    };
+
+    let connection = open()?;
+    let mut query = connection.prepare(
+        "SELECT ts, addr, port, latency_ms, loss_pct FROM results
+         WHERE ts >= ?1 AND (?2 IS NULL OR addr = ?2) AND (?3 IS NULL OR port = ?3)
+         ORDER BY ts, id",
+    )?;
+    let rows = query
+        .query_map(params![since, ip, port], |row| {
+            let ip: String = row.get(1)?;
+            let port: u16 = row.get(2)?;
+            Ok(Row {
+                ts: row.get(0)?,
+                addr: format!("{ip}:{port}"),
+                latency_ms: row.get(3)?,
+                loss_pct: row.get(4)?,
+            })
+        })?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+
+    if !args.stats {
+        println!(
+            "{:<25} {:<45} {:>10} {:>8}",
+            "Time", "Address", "Latency", "Loss"
+        );
+        for row in &rows {
+            let time = Timestamp::from_second(row.ts)?;
+            println!(
+                "{:<25} {:<45} {:>7} ms {:>7.1}%",
+                time.to_string(),
+                row.addr,
+                row.latency_ms,
+                row.loss_pct
+            );
+        }
+        return Ok(());
+    }
+
+    let mut by_addr: BTreeMap<&str, Vec<(f64, f64)>> = BTreeMap::new();
+    for row in &rows {
+        by_addr
+            .entry(&row.addr)
+            .or_default()
+            .push((row.ts as f64 / SECONDS_PER_DAY, row.latency_ms as f64));
+    }
+    println!(
+        "{:<45} {:>6} {:>10} {:>12}",
+        "Address", "Scans", "Mean (ms)", "Trend"
+    );
+    for (addr, points) in by_addr {
+        let mean = points.iter().map(|(_, y)| y).sum::<f64>() / points.len() as f64;
+        let trend = trend(&points).map_or_else(|| "-".to_string(), |t| format!("{t:+.1} ms/d"));
+        println!("{addr:<45} {:>6} {mean:>10.1} {trend:>12}", points.len());
+    }
+    Ok(())
+}
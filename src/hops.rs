@@ -0,0 +1,109 @@
+use crate::{TestResult, handshake_packet};
+use futures::future;
+use log::{debug, warn};
+use socket2::{Domain, Protocol, Socket, Type};
+use std::io::{self, ErrorKind, Read};
+use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4, UdpSocket};
+use std::time::{Duration, Instant};
+
+/// Highest TTL tried before giving up on an address.
+const MAX_TTL: u8 = 30;
+
+/// How long every TTL waits for a response or an ICMP error.
+const HOP_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// How often the two sockets are polled while waiting.
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+const ICMP_TIME_EXCEEDED: u8 = 11;
+
+/// Whether `packet`, an IPv4 packet with an ICMP message read from a raw
+/// socket, is a time-exceeded error about a UDP datagram sent from
+/// `local_port` to `target`.
+fn is_time_exceeded(packet: &[u8], target: &SocketAddrV4, local_port: u16) -> bool {
+    let parse = || {
+        let header_len = usize::from(packet.first()? & 0x0f) * 4;
+        let icmp = packet.get(header_len..)?;
+        if *icmp.first()? != ICMP_TIME_EXCEEDED {
+            return None;
+        }
+        // The error quotes the IP header and the UDP header of the datagram
+        let quoted = icmp.get(8..)?;
+        let quoted_len = usize::from(quoted.first()? & 0x0f) * 4;
+        let destination = Ipv4Addr::from(<[u8; 4]>::try_from(quoted.get(16..20)?).ok()?);
+        let udp = quoted.get(quoted_len..quoted_len + 4)?;
+        let source_port = u16::from_be_bytes([udp[0], udp[1]]);
+        let destination_port = u16::from_be_bytes([udp[2], udp[3]]);
+        Some(
+            destination == *target.ip()
+                && destination_port == target.port()
+                && source_port == local_port,
+        )
+    };
+    parse().unwrap_or(false)
+}
+
+/// Send the handshake to `target` with TTLs 1, 2, … until it answers, and
+/// return the TTL that reached it. Hops that stay silent are skipped like
+/// traceroute does.
+fn count_hops(target: SocketAddrV4, icmp: &Socket) -> io::Result<Option<u8>> {
+    let packet = handshake_packet();
+    let mut buf = [0u8; 1500];
+    for ttl in 1..=MAX_TTL {
+        let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))?;
+        socket.connect(target)?;
+        socket.set_ttl(ttl.into())?;
+        socket.set_nonblocking(true)?;
+        let local_port = socket.local_addr()?.port();
+        socket.send(&packet)?;
+
+        let deadline = Instant::now() + HOP_TIMEOUT;
+        while Instant::now() < deadline {
+            match socket.recv(&mut buf) {
+                Ok(_) => return Ok(Some(ttl)),
+                // ICMP errors other than time exceeded, e.g. port unreachable
+                Err(e) if e.kind() != ErrorKind::WouldBlock => return Err(e),
+                Err(_) => {}
+            }
+            match (&*icmp).read(&mut buf) {
+                Ok(len) if is_time_exceeded(&buf[..len], &target, local_port) => break,
+                Ok(_) => {}
+                Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {}
+                Err(e) => return Err(e),
+            }
+        }
+    }
+    Ok(None)
+}
+
+/// Fill in the hop count of every IPv4 result in `results`.
+///
+/// Reading ICMP time-exceeded errors needs a raw socket, so without root or
+/// CAP_NET_RAW every count is left empty with a warning.
+pub(crate) async fn annotate(results: &mut [TestResult]) {
+    let counts = future::join_all(results.iter().map(|result| {
+        let addr = result.addr;
+        tokio::task::spawn_blocking(move || {
+            let SocketAddr::V4(target) = addr else {
+                debug!("Not counting the hops to {addr}, only IPv4 is supported");
+                return Ok(None);
+            };
+            let icmp = Socket::new(Domain::IPV4, Type::RAW, Some(Protocol::ICMPV4))?;
+            icmp.set_read_timeout(Some(POLL_INTERVAL))?;
+            count_hops(target, &icmp)
+        })
+    }))
+    .await;
+
+    let mut denied = false;
+    for (result, count) in results.iter_mut().zip(counts) {
+        match count.expect("hop counting panicked") {
+            Ok(hops) => result.hops = hops,
+            Err(e) if e.kind() == ErrorKind::PermissionDenied => denied = true,
+            Err(e) => warn!("Failed to count the hops to {}: {e}", result.addr),
+        }
+    }
+    if denied {
+        warn!("--hop-count needs root or CAP_NET_RAW to read ICMP time-exceeded errors");
+    }
+}
@@ -0,0 +1,81 @@
+use crate::args::ExportHostsArgs;
+use crate::diff;
+use anyhow::{Context, Result};
+use log::info;
+use std::fmt::Write as _;
+use std::fs;
+use std::path::Path;
+
+const BEGIN: &str = "# === warp-speedtest BEGIN ===";
+const END: &str = "# === warp-speedtest END ===";
+
+/// The marked block listing the first `top` results of `path`, one comment
+/// line each. Nothing in it resolves, it is only a record of the results.
+fn block(path: &Path, top: usize) -> Result<String> {
+    let mut block = format!("{BEGIN}\n");
+    for (n, (addr, latency)) in diff::load(path)?.into_iter().take(top).enumerate() {
+        writeln!(
+            block,
+            "# {latency}ms  {}  cloudflare-warp-{}.local",
+            addr.ip(),
+            n + 1
+        )?;
+    }
+    writeln!(block, "{END}")?;
+    Ok(block)
+}
+
+/// `contents` with its marked block replaced by `block`, or with `block`
+/// appended if there is none.
+fn replace_block(contents: &str, block: &str) -> String {
+    if let Some(begin) = contents.find(BEGIN)
+        && let Some(end) = contents[begin..].find(END)
+    {
+        let mut end = begin + end + END.len();
+        if contents[end..].starts_with('\n') {
+            end += 1;
+        }
+        return format!("{}{block}{}", &contents[..begin], &contents[end..]);
+    }
+
+    let mut contents = contents.to_string();
+    if !contents.is_empty() && !contents.ends_with('\n') {
+        contents.push('\n');
+    }
+    contents + block
+}
+
+/// Replace the marked block of the hosts file, after copying it to
+/// `<hosts>.bak`. Written in place rather than renamed over, as the hosts
+/// file is often a bind mount.
+fn update_hosts(hosts: &Path, block: &str) -> Result<()> {
+    let contents =
+        fs::read_to_string(hosts).with_context(|| format!("Failed to read {}", hosts.display()))?;
+    let mut backup = hosts.as_os_str().to_owned();
+    backup.push(".bak");
+    fs::write(&backup, &contents)
+        .with_context(|| format!("Failed to back up {}", hosts.display()))?;
+    fs::write(hosts, replace_block(&contents, block))
+        .with_context(|| format!("Failed to write {}", hosts.display()))?;
+    info!(
+        "Updated {}, the previous version is in {}",
+        hosts.display(),
+        Path::new(&backup).display()
+    );
+    Ok(())
+}
+
+pub(crate) fn run(args: &ExportHostsArgs) -> Result<()> {
+    let block = block(&args.results, args.top)?;
+    if let Some(output) = &args.output {
+        fs::write(output, &block)
+            .with_context(|| format!("Failed to write {}", output.display()))?;
+    }
+    if args.update_hosts {
+        update_hosts(&args.hosts_file, &block)?;
+    }
+    if args.output.is_none() && !args.update_hosts {
+        print!("{block}");
+    }
+    Ok(())
+}
@@ -0,0 +1,97 @@
+use crate::TestResult;
+use crate::args::LatencyUnit;
+use futures::future;
+use log::warn;
+use schemars::JsonSchema;
+use serde::Serialize;
+use std::io::ErrorKind;
+use std::time::Duration;
+use surge_ping::{Client, Config, ICMP, PingIdentifier, PingSequence, SurgeError};
+
+/// Echo requests sent to every result.
+const ECHOES: u16 = 3;
+
+const ECHO_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// WARP taking more than this many times the ICMP round trip is put down to
+/// the WARP endpoint rather than the network path.
+const OVERHEAD_RATIO: f64 = 2.0;
+
+#[derive(Clone, Copy, Debug, Serialize, JsonSchema)]
+pub(crate) struct PingComparison {
+    pub(crate) icmp_rtt_ms: f64,
+    pub(crate) warp_rtt_ms: f64,
+}
+
+impl PingComparison {
+    /// What the two round trips say about where the latency comes from.
+    pub(crate) fn verdict(&self) -> &'static str {
+        if self.warp_rtt_ms > self.icmp_rtt_ms * OVERHEAD_RATIO {
+            "WARP overhead"
+        } else {
+            "network path"
+        }
+    }
+}
+
+/// Average round trip of the echoes answered by `result`, `None` if none
+/// was.
+async fn echo_rtt(client: &Client, result: &TestResult) -> Result<Option<f64>, SurgeError> {
+    let mut pinger = client
+        .pinger(result.addr.ip(), PingIdentifier(rand::random()))
+        .await;
+    pinger.timeout(ECHO_TIMEOUT);
+
+    let mut rtts = Vec::new();
+    for seq in 0..ECHOES {
+        match pinger.ping(PingSequence(seq), &[0; 56]).await {
+            Ok((_, rtt)) => rtts.push(rtt.as_secs_f64() * 1000.0),
+            Err(SurgeError::Timeout { .. }) => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok((!rtts.is_empty()).then(|| rtts.iter().sum::<f64>() / rtts.len() as f64))
+}
+
+/// Ping every result and record its ICMP round trip next to its WARP one,
+/// both in ms.
+///
+/// Unprivileged ICMP sockets need `net.ipv4.ping_group_range` to include
+/// the user, otherwise root or CAP_NET_RAW. Without either the results are
+/// left as they are with a warning.
+pub(crate) async fn compare(results: &mut [TestResult], unit: LatencyUnit) {
+    let clients = [Config::default(), Config::builder().kind(ICMP::V6).build()]
+        .map(|config| Client::new(&config));
+    let rtts = future::join_all(results.iter().map(|result| {
+        let client = &clients[usize::from(result.addr.is_ipv6())];
+        async move {
+            match client {
+                Ok(client) => echo_rtt(client, result).await,
+                Err(e) => Err(SurgeError::IOError(e.kind().into())),
+            }
+        }
+    }))
+    .await;
+
+    let mut denied = false;
+    for (result, rtt) in results.iter_mut().zip(rtts) {
+        match rtt {
+            Ok(Some(icmp_rtt_ms)) => {
+                result.ping_comparison = Some(PingComparison {
+                    icmp_rtt_ms,
+                    warp_rtt_ms: unit.to_ms(result.latency as f64),
+                });
+            }
+            Ok(None) => warn!("{} did not answer ICMP echo requests", result.addr),
+            Err(SurgeError::IOError(e)) if e.kind() == ErrorKind::PermissionDenied => {
+                denied = true;
+            }
+            Err(e) => warn!("Failed to ping {}: {e}", result.addr),
+        }
+    }
+    if denied {
+        warn!(
+            "--ping-compare needs ICMP sockets, allow them with net.ipv4.ping_group_range or run as root"
+        );
+    }
+}
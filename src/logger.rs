@@ -0,0 +1,42 @@
+use env_logger::fmt::Formatter;
+use log::Record;
+use log::kv::{self, VisitSource};
+use serde_json::{Map, Value};
+use std::io::{self, Write};
+
+/// Format a log record as a single-line JSON object.
+///
+/// Key-values attached to the record (e.g. `info!(addr:% = addr; "...")`)
+/// become top-level keys next to `level`, `msg` and `ts`.
+pub(crate) fn format_json(buf: &mut Formatter, record: &Record) -> io::Result<()> {
+    let mut object = Map::new();
+    object.insert("level".to_string(), record.level().as_str().into());
+    object.insert("msg".to_string(), record.args().to_string().into());
+    object.insert("ts".to_string(), jiff::Timestamp::now().to_string().into());
+
+    // Visiting never fails, the visitor always returns `Ok`
+    let _ = record.key_values().visit(&mut JsonVisitor(&mut object));
+
+    writeln!(buf, "{}", Value::Object(object))
+}
+
+struct JsonVisitor<'a>(&'a mut Map<String, Value>);
+
+impl<'kvs> VisitSource<'kvs> for JsonVisitor<'_> {
+    fn visit_pair(&mut self, key: kv::Key<'kvs>, value: kv::Value<'kvs>) -> Result<(), kv::Error> {
+        let value = if let Some(n) = value.to_u64() {
+            n.into()
+        } else if let Some(n) = value.to_i64() {
+            n.into()
+        } else if let Some(n) = value.to_f64() {
+            n.into()
+        } else if let Some(b) = value.to_bool() {
+            b.into()
+        } else {
+            value.to_string().into()
+        };
+
+        self.0.insert(key.as_str().to_string(), value);
+        Ok(())
+    }
+}
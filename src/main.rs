@@ -1,204 +1,2718 @@
 mod args;
+#[cfg(feature = "asn")]
+mod asn;
+mod benchmark;
+mod checkpoint;
+mod cidrs;
+mod datacenter_map;
+mod diff;
+mod dualstack;
+mod export;
+#[cfg(feature = "geoip")]
+mod geoip;
+mod histogram;
+#[cfg(feature = "history")]
+mod history;
+mod hops;
+mod hosts;
+mod icmp;
+mod logger;
+mod metrics;
+#[cfg(all(feature = "mmsg", target_os = "linux"))]
+mod mmsg;
+mod monitor;
+mod mtu;
+#[cfg(feature = "otel")]
+mod otel;
+mod output;
+mod pilot;
+mod pmtud;
+mod ports;
+mod preflight;
+mod rdns;
+mod report;
+mod retest;
+mod rounds;
+mod score;
+mod socket;
+mod stats;
+mod subnet;
+mod targets;
+mod topn;
+#[cfg(feature = "tui")]
+mod tui;
+mod verify;
+mod webhook;
 
-use crate::args::{Args, SpeedTestMode};
-use anyhow::Result;
+use crate::args::{
+    Args, Command, LatencyUnit, LogFormat, Order, OutputFormat, PbStyle, SampleStrategy, SortBy,
+    SpeedTestMode,
+};
+use crate::checkpoint::Checkpoint;
+use crate::output::Report;
+use crate::socket::SocketOptions;
+use crate::targets::Target;
+use crate::topn::BoundedHeap;
+use anyhow::{Context, Result, bail};
 use clap::{CommandFactory, Parser};
 use clap_complete::generate;
-use futures::StreamExt;
+use futures::{Stream, StreamExt, future};
 use hex::decode;
+use indicatif::style::TemplateError;
 use indicatif::{ProgressBar, ProgressStyle};
-use ipnetwork::Ipv4Network;
-use log::{debug, info};
+use ipnetwork::{IpNetwork, Ipv4Network, Ipv6Network};
+use log::{debug, info, warn};
+use rand::distr::Distribution;
+use rand::distr::weighted::WeightedIndex;
+use rand::rngs::StdRng;
 use rand::seq::{IndexedRandom, IteratorRandom};
-use std::io;
-use std::net::{SocketAddr, SocketAddrV4};
-use std::sync::Arc;
+use rand::{Rng, SeedableRng};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
+use std::fs::File;
+use std::io::{self, IsTerminal, Write};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4};
+use std::path::Path;
+use std::pin::Pin;
+use std::process::ExitCode;
+use std::slice;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
 use std::time::Instant;
-use tokio::net::UdpSocket;
-use tokio::time::{Duration, timeout};
+use tokio::time::{Duration, sleep, sleep_until, timeout, timeout_at};
 
-#[derive(Debug)]
+/// How long to wait for a handshake response before giving up on a probe.
+const PROBE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Shortest timeout `--adaptive-timeout` waits, so that sub-millisecond
+/// round trips do not make every later probe time out.
+const MIN_ADAPTIVE_TIMEOUT: Duration = Duration::from_millis(50);
+
+/// Estimated scan durations above this are worth a warning.
+const LONG_SCAN_WARNING: Duration = Duration::from_secs(10 * 60);
+
+/// Size of a WireGuard handshake response.
+const HANDSHAKE_RESPONSE_LEN: usize = 92;
+
+/// Message type of a WireGuard handshake response.
+const HANDSHAKE_RESPONSE_TYPE: u8 = 2;
+
+/// Bytes read of every response, more than `--response-size-max` allows so
+/// longer ones are told apart.
+const MAX_RESPONSE_LEN: usize = 256;
+
+/// Cloudflare WARP IPv4 endpoint ranges, used unless `--fetch-cidrs` finds newer ones.
+const DEFAULT_V4_RANGES: [&str; 8] = [
+    "162.159.192.0/24",
+    "162.159.193.0/24",
+    "162.159.195.0/24",
+    "162.159.204.0/24",
+    "188.114.96.0/24",
+    "188.114.97.0/24",
+    "188.114.98.0/24",
+    "188.114.99.0/24",
+];
+
+/// Cloudflare WARP IPv6 endpoint ranges.
+const DEFAULT_V6_RANGES: [&str; 2] = ["2606:4700:d0::/48", "2606:4700:d1::/48"];
+
+/// Distance between the two latency clusters above which `--ecmp-detect`
+/// suspects the probes took different paths.
+const ECMP_GAP_MS: f64 = 20.0;
+
+/// Resamples drawn for `--confidence-interval`.
+const BOOTSTRAP_RESAMPLES: usize = 1000;
+
+/// UDP ports WARP endpoints answer on.
+const DEFAULT_PORTS: [u16; 45] = [
+    500, 854, 859, 864, 878, 880, 890, 891, 894, 903, 908, 928, 934, 939, 942, 943, 945, 946, 955,
+    968, 987, 988, 1002, 1010, 1014, 1018, 1070, 1074, 1180, 1387, 1701, 2408, 4500, 5050, 5242,
+    6515, 7103, 7152, 7156, 7281, 7559, 8319, 8742, 8854, 8886,
+];
+
+#[derive(Debug, Serialize, JsonSchema)]
 struct TestResult {
     addr: SocketAddr,
+    /// Average latency of the successful probes
+    #[serde(rename = "latency_ms")]
     latency: u128,
+    /// Share of probes that got no valid response, in percent
+    loss_pct: f64,
+    /// Standard deviation of the successful probes
+    jitter_ms: f64,
+    /// Mean absolute difference between consecutive successful probes, only
+    /// meaningful with `--attempts` of 3 or more
+    ping_variance_ms: f64,
+    /// Latency of every successful probe, in order
+    #[serde(skip)]
+    samples: Vec<u128>,
+    /// Number of probes sent, including extra ones from `--cv-threshold`
+    attempts: u32,
+    /// Confidence interval of `latency`, from `--confidence-interval`
+    #[serde(flatten, skip_serializing_if = "Option::is_none")]
+    ci: Option<ConfidenceInterval>,
+    /// Distribution of `samples`, from `--thorough-mode`
+    #[serde(flatten, skip_serializing_if = "Option::is_none")]
+    stats: Option<LatencyStats>,
+    /// IATA code of the Cloudflare PoP, from the static subnet mapping
+    pop: &'static str,
+    /// PTR name from `--rdns`, `Some(None)` if the lookup found none
+    #[serde(skip_serializing_if = "Option::is_none")]
+    hostname: Option<Option<String>>,
+    /// Largest IP packet that got through to `addr`, from `--pmtud`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pmtu: Option<u16>,
+    /// Ports of the IP that responded, with `--all-ports` or `--probe-all-ports`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    ports_alive: Option<usize>,
+    /// Network hops to `addr`, from `--hop-count`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    hops: Option<u8>,
+    /// ICMP round trip next to the WARP one, from `--ping-compare`
+    #[serde(flatten, skip_serializing_if = "Option::is_none")]
+    ping_comparison: Option<icmp::PingComparison>,
+    /// Whether the probes formed two latency clusters, from `--ecmp-detect`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    ecmp_suspected: Option<bool>,
+    /// Rank of the result by `--score-formula` or `--score`, lower is better
+    #[serde(skip_serializing_if = "Option::is_none")]
+    score: Option<f64>,
+    /// Whether `--verify-top-n` found the result slower than in the scan
+    #[serde(skip_serializing_if = "Option::is_none")]
+    unstable: Option<bool>,
+    /// Position among the working addresses, 1 is the best
+    rank: usize,
+    /// Share of the working addresses ranked below this one, in percent
+    percentile_rank: f64,
+    /// The AS announcing `addr`, from `--asn`
+    #[cfg(feature = "asn")]
+    #[serde(flatten, skip_serializing_if = "Option::is_none")]
+    asn: Option<asn::AsnInfo>,
+    /// The country of `addr`, from `--geoip`
+    #[cfg(feature = "geoip")]
+    #[serde(flatten, skip_serializing_if = "Option::is_none")]
+    country: Option<geoip::Country>,
+}
+
+#[derive(Clone, Copy, Debug, Serialize, JsonSchema)]
+struct ConfidenceInterval {
+    ci_low: f64,
+    ci_high: f64,
+}
+
+#[derive(Clone, Copy, Debug, Serialize, JsonSchema)]
+struct LatencyStats {
+    min_ms: f64,
+    max_ms: f64,
+    p50_ms: f64,
+    p90_ms: f64,
+    p99_ms: f64,
+}
+
+impl LatencyStats {
+    /// `None` when `samples` is empty.
+    fn of(samples: &[u128]) -> Option<Self> {
+        let mut sorted: Vec<f64> = samples.iter().map(|&s| s as f64).collect();
+        sorted.sort_by(f64::total_cmp);
+        Some(LatencyStats {
+            min_ms: *sorted.first()?,
+            max_ms: *sorted.last()?,
+            p50_ms: stats::quantile(&sorted, 0.5),
+            p90_ms: stats::quantile(&sorted, 0.9),
+            p99_ms: stats::quantile(&sorted, 0.99),
+        })
+    }
+}
+
+impl TestResult {
+    /// Summarize the latencies of the `samples` that succeeded out of `attempts`.
+    fn from_samples(addr: SocketAddr, samples: Vec<u128>, attempts: u32) -> Self {
+        let (latency, jitter_ms) = latency_and_jitter(&samples);
+        let ping_variance_ms =
+            stats::ping_variance(&samples.iter().map(|&s| s as f64).collect::<Vec<_>>());
+        let received = samples.len() as u32;
+        let attempts = attempts.max(received);
+        TestResult {
+            addr,
+            latency,
+            loss_pct: 100.0 * f64::from(attempts - received) / f64::from(attempts.max(1)),
+            jitter_ms,
+            ping_variance_ms,
+            samples,
+            attempts,
+            ci: None,
+            stats: None,
+            pop: datacenter_map::pop_of(addr.ip()),
+            hostname: None,
+            unstable: None,
+            score: None,
+            ecmp_suspected: None,
+            ports_alive: None,
+            pmtu: None,
+            hops: None,
+            ping_comparison: None,
+            rank: 0,
+            percentile_rank: 0.0,
+            #[cfg(feature = "asn")]
+            asn: None,
+            #[cfg(feature = "geoip")]
+            country: None,
+        }
+    }
+
+    /// Recompute the latency and jitter without the outlying samples. They
+    /// still count as received for the loss.
+    fn without_outliers(mut self) -> Self {
+        let mut kept = self.samples.clone();
+        stats::remove_outliers(&mut kept);
+        if kept.len() < self.samples.len() {
+            debug!(
+                "Ignoring {} outlying probes of {}",
+                self.samples.len() - kept.len(),
+                self.addr
+            );
+        }
+        (self.latency, self.jitter_ms) = latency_and_jitter(&kept);
+        self
+    }
+}
+
+/// Average latency and its standard deviation over `samples`.
+fn latency_and_jitter(samples: &[u128]) -> (u128, f64) {
+    let latency = samples.iter().sum::<u128>() / samples.len().max(1) as u128;
+    let as_f64: Vec<f64> = samples.iter().map(|&s| s as f64).collect();
+    (latency, stats::std_dev(&as_f64))
+}
+
+/// An address that never produced a valid response.
+#[derive(Debug, Serialize, JsonSchema)]
+struct DeadAddr {
+    addr: SocketAddr,
+    latency_ms: Option<u128>,
+    status: Status,
+}
+
+/// Everything learned from probing one address sample.
+#[derive(Debug, Default)]
+struct ScanResults {
+    /// Working addresses, fastest first
+    alive: Vec<TestResult>,
+    dead: Vec<DeadAddr>,
+    /// Failed probes by reason
+    errors: ErrorCounts,
+    /// Number of probes sent
+    probes: u64,
+    /// Time spent probing
+    duration: Duration,
+    /// Working addresses dropped by `--result-limit`
+    discarded: Vec<SocketAddr>,
+}
+
+impl ScanResults {
+    /// Drop the addresses of `addrs` that were never probed to the end, e.g.
+    /// because the scan stopped early.
+    fn retain_completed(&self, addrs: &mut Vec<SocketAddr>) {
+        let completed: HashSet<SocketAddr> = self
+            .alive
+            .iter()
+            .map(|res| res.addr)
+            .chain(self.dead.iter().map(|dead| dead.addr))
+            .chain(self.discarded.iter().copied())
+            .collect();
+        addrs.retain(|addr| completed.contains(addr));
+    }
+
+    /// Drop the working addresses excluded by `--max-latency`,
+    /// `--min-ports-alive`, `--probe-all-ports` or `--limit-per-subnet`.
+    fn apply_filters(&mut self, cli: &Args) {
+        let unit = cli.latency_unit;
+        if let Some(max) = cli.max_latency {
+            let max = unit.of_ms(max);
+            self.alive.retain(|res| res.latency <= max);
+        }
+        if let Some(max) = cli.max_jitter {
+            let max = unit.of_ms_f64(max);
+            self.alive.retain(|res| res.jitter_ms <= max);
+        }
+        if cli.every_port() {
+            ports::count_ports_alive(&mut self.alive);
+            if let Some(min) = cli.min_ports_alive {
+                ports::retain_min_ports_alive(&mut self.alive, min);
+            }
+        }
+        if cli.probe_all_ports {
+            ports::retain_best_port(&mut self.alive);
+        }
+        if let Some(limit) = cli.limit_per_subnet {
+            subnet::limit_per_subnet(&mut self.alive, limit);
+        }
+        if let Some(threshold) = cli.dedup_threshold_ms {
+            dedup_latencies(&mut self.alive, unit.of_ms(threshold));
+        }
+        apply_score(cli, &mut self.alive);
+    }
+}
+
+/// Rank `results` by `--score-formula` or `--score`, if given.
+fn apply_score(cli: &Args, results: &mut [TestResult]) {
+    let unit = cli.latency_unit;
+    if let Some(formula) = &cli.score_formula {
+        score::rank(results, formula, unit);
+    } else if cli.score {
+        let weights = score::Weights {
+            latency: cli.weight_latency,
+            loss: cli.weight_loss,
+            jitter: cli.weight_jitter,
+        };
+        score::rank_weighted(results, &weights, unit);
+    }
+}
+
+/// Keep only results more than `threshold` slower than the last kept one,
+/// going fastest first. Of equally fast results the lowest address is kept.
+///
+/// The kept results stay in their order, which `--sort-by` may have set.
+fn dedup_latencies(results: &mut Vec<TestResult>, threshold: u128) {
+    let mut by_latency: Vec<(u128, SocketAddr)> =
+        results.iter().map(|res| (res.latency, res.addr)).collect();
+    by_latency.sort();
+    let mut last_kept: Option<u128> = None;
+    let kept: HashSet<SocketAddr> = by_latency
+        .into_iter()
+        .filter(|&(latency, _)| {
+            let keep = last_kept.is_none_or(|last| latency - last > threshold);
+            if keep {
+                last_kept = Some(latency);
+            }
+            keep
+        })
+        .map(|(_, addr)| addr)
+        .collect();
+    results.retain(|res| kept.contains(&res.addr));
+}
+
+/// Number the results, already in their final order, and place them among
+/// each other.
+fn annotate_ranks(results: &mut [TestResult]) {
+    let alive = results.len() as f64;
+    for (i, result) in results.iter_mut().enumerate() {
+        result.rank = i + 1;
+        result.percentile_rank = 100.0 - result.rank as f64 / alive * 100.0;
+    }
+}
+
+/// Orders results fastest first, then by address, as the scan keeps them.
+struct ByLatency(TestResult);
+
+impl Ord for ByLatency {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        (self.0.latency, self.0.addr).cmp(&(other.0.latency, other.0.addr))
+    }
+}
+
+impl PartialOrd for ByLatency {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for ByLatency {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other).is_eq()
+    }
+}
+
+impl Eq for ByLatency {}
+
+/// The final ranking of a run, single scan or aggregated over rounds.
+#[derive(Debug)]
+struct Ranking {
+    /// Working addresses, best first
+    results: Vec<TestResult>,
+    /// Number of addresses probed
+    tested: usize,
+    /// Time spent probing, excluding address generation
+    duration: Duration,
+    /// Subnets where no tested address responded
+    unreachable_subnets: Vec<IpNetwork>,
+}
+
+/// Why a single probe did not produce a latency sample.
+#[derive(Debug)]
+enum ProbeError {
+    Timeout,
+    /// An ICMP port unreachable came back, nothing listens on the port
+    IcmpUnreachable,
+    SendError(io::Error),
+    RecvError(io::Error),
+    InvalidResponse,
+}
+
+impl ProbeError {
+    /// Classify a socket error, telling ICMP port unreachable apart.
+    ///
+    /// On a connected socket the kernel reports the ICMP message as
+    /// `ECONNREFUSED` on the next send or receive.
+    fn from_io(e: io::Error, wrap: fn(io::Error) -> ProbeError) -> ProbeError {
+        if e.kind() == io::ErrorKind::ConnectionRefused {
+            ProbeError::IcmpUnreachable
+        } else {
+            wrap(e)
+        }
+    }
+}
+
+impl fmt::Display for ProbeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProbeError::Timeout => write!(f, "timed out"),
+            ProbeError::IcmpUnreachable => write!(f, "port unreachable"),
+            ProbeError::SendError(e) => write!(f, "send failed: {e}"),
+            ProbeError::RecvError(e) => write!(f, "receive failed: {e}"),
+            ProbeError::InvalidResponse => write!(f, "invalid response"),
+        }
+    }
+}
+
+impl std::error::Error for ProbeError {}
+
+/// What the scan found in a subnet so far.
+#[derive(Clone, Copy, Debug, Default)]
+struct SubnetProgress {
+    /// Dead addresses since the last working one
+    failures: u32,
+    /// Working addresses
+    alive: u32,
+}
+
+/// Number of failed probes per kind of error.
+#[derive(Clone, Copy, Debug, Default, Serialize, JsonSchema)]
+struct ErrorCounts {
+    timeouts: u64,
+    icmp_unreachable: u64,
+    send_errors: u64,
+    recv_errors: u64,
+    invalid_responses: u64,
+    /// Addresses every probe of which failed to send, already counted in
+    /// `send_errors`
+    system_errors: u64,
+    /// Addresses not probed because of `--skip-subnet-after` or
+    /// `--stop-after-n-per-subnet`
+    skipped: u64,
+}
+
+impl ErrorCounts {
+    fn record(&mut self, error: &ProbeError) {
+        let count = match error {
+            ProbeError::Timeout => &mut self.timeouts,
+            ProbeError::IcmpUnreachable => &mut self.icmp_unreachable,
+            ProbeError::SendError(_) => &mut self.send_errors,
+            ProbeError::RecvError(_) => &mut self.recv_errors,
+            ProbeError::InvalidResponse => &mut self.invalid_responses,
+        };
+        *count += 1;
+    }
+
+    fn total(&self) -> u64 {
+        self.timeouts
+            + self.icmp_unreachable
+            + self.send_errors
+            + self.recv_errors
+            + self.invalid_responses
+            + self.skipped
+    }
+}
+
+/// Outcome of an address, as reported with `--all-results`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, JsonSchema, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum Status {
+    Alive,
+    Timeout,
+    IcmpUnreachable,
+    SendError,
+    RecvError,
+    InvalidResponse,
+    /// Every probe failed to send, so the address was never reached
+    SystemError,
+}
+
+impl Status {
+    fn as_str(self) -> &'static str {
+        match self {
+            Status::Alive => "alive",
+            Status::Timeout => "timeout",
+            Status::IcmpUnreachable => "icmp_unreachable",
+            Status::SendError => "send_error",
+            Status::RecvError => "recv_error",
+            Status::InvalidResponse => "invalid_response",
+            Status::SystemError => "system_error",
+        }
+    }
+}
+
+impl From<&ProbeError> for Status {
+    fn from(error: &ProbeError) -> Self {
+        match error {
+            ProbeError::Timeout => Status::Timeout,
+            ProbeError::IcmpUnreachable => Status::IcmpUnreachable,
+            ProbeError::SendError(_) => Status::SendError,
+            ProbeError::RecvError(_) => Status::RecvError,
+            ProbeError::InvalidResponse => Status::InvalidResponse,
+        }
+    }
+}
+
+/// Prefix length of a single address of `network`'s IP version.
+fn max_prefix(network: &IpNetwork) -> u8 {
+    if network.is_ipv4() { 32 } else { 128 }
+}
+
+/// The networks and ports scanned addresses are drawn from.
+struct AddressPool {
+    v4_ranges: Vec<Ipv4Network>,
+    v6_ranges: Vec<Ipv6Network>,
+    ports: Vec<u16>,
+    /// Weight of every entry of `ports`, from `--port-weights`
+    port_weights: Option<WeightedIndex<u32>>,
+    /// Always pick the first port, from `--no-randomize`
+    first_port_only: bool,
+    /// Addresses never tested, from `--blacklist-file` and `--exclude-subnet`
+    blacklist: Vec<IpNetwork>,
+    /// The only addresses tested, from `--whitelist-file` or `--input-file`
+    whitelist: Option<Vec<Target>>,
+    /// Scope ID of link-local IPv6 addresses, from `--ipv6-scope`
+    scope_id: Option<u32>,
+}
+
+impl AddressPool {
+    async fn new(cli: &Args) -> Result<Self> {
+        let defaults: Vec<Ipv4Network> = match &cli.ipv4_ranges_file {
+            Some(path) => targets::read_ranges(path)?,
+            None => DEFAULT_V4_RANGES
+                .iter()
+                .map(|cidr| cidr.parse().expect("Invalid CIDR"))
+                .collect(),
+        };
+        let default_v6: Vec<Ipv6Network> = match &cli.ipv6_ranges_file {
+            Some(path) => targets::read_ranges(path)?,
+            None => DEFAULT_V6_RANGES
+                .iter()
+                .map(|cidr| cidr.parse().expect("Invalid CIDR"))
+                .collect(),
+        };
+        let (mut v4_ranges, mut v6_ranges) = if !cli.cidr.is_empty() {
+            let (v4, v6) = cidrs::parse_ranges(&cli.cidr).await?;
+            let v6 = if v6.is_empty() { default_v6 } else { v6 };
+            (v4, v6)
+        } else if cli.fetch_cidrs {
+            (cidrs::fetch_v4(&defaults).await, default_v6)
+        } else {
+            (defaults, default_v6)
+        };
+        for network in &cli.extra_cidr {
+            match *network {
+                IpNetwork::V4(network) if !v4_ranges.contains(&network) => v4_ranges.push(network),
+                IpNetwork::V6(network) if !v6_ranges.contains(&network) => v6_ranges.push(network),
+                _ => debug!("{network} is already scanned"),
+            }
+        }
+
+        let (ports, port_weights) = weighted_ports(port_list(cli)?, &cli.port_weights)?;
+
+        let mut blacklist = match &cli.blacklist_file {
+            Some(path) => targets::read_networks(path)?,
+            None => Vec::new(),
+        };
+        blacklist.extend(&cli.exclude_subnet);
+        for range in &v4_ranges {
+            if let Some(excluded) = blacklist.iter().find(|network| {
+                network.prefix() <= range.prefix() && network.contains(range.network().into())
+            }) {
+                warn!("{excluded} excludes the whole {range} range");
+            }
+        }
+        let whitelist = match (&cli.whitelist_file, &cli.input_file) {
+            (Some(path), _) => Some(targets::read_targets(path)?),
+            (None, Some(path)) => Some(targets::read_saved(path, cli.export_csv_sep)?),
+            (None, None) => None,
+        };
+        // `--fixed-ips` tests every address, a /8 alone would be 16M of them
+        if cli.fixed_ips
+            && let Some(network) = whitelist.iter().flatten().find_map(|target| match target {
+                Target::Network(network) if network.prefix() < max_prefix(network) => Some(network),
+                _ => None,
+            })
+        {
+            bail!("--fixed-ips only tests single addresses, but the list has the {network} range");
+        }
+        let scope_id = cli
+            .ipv6_scope
+            .as_deref()
+            .map(|name| {
+                socket::interface_index(name)
+                    .with_context(|| format!("Unknown --ipv6-scope interface {name}"))
+            })
+            .transpose()?;
+
+        Ok(Self {
+            v4_ranges,
+            v6_ranges,
+            ports,
+            port_weights,
+            first_port_only: cli.no_randomize,
+            blacklist,
+            whitelist,
+            scope_id,
+        })
+    }
+
+    /// A random port of the pool, biased by `--port-weights` if given, or
+    /// its first port with `--no-randomize`.
+    fn random_port(&self, rng: &mut impl Rng) -> u16 {
+        if self.first_port_only {
+            return self.ports[0];
+        }
+        match &self.port_weights {
+            Some(weights) => self.ports[weights.sample(rng)],
+            None => *self.ports.choose(rng).expect("the port list is not empty"),
+        }
+    }
+
+    fn is_blacklisted(&self, ip: IpAddr) -> bool {
+        self.blacklist.iter().any(|network| network.contains(ip))
+    }
+}
+
+/// The ports of `--ports`, or the built-in ones, without `--exclude-port`.
+fn port_list(cli: &Args) -> Result<Vec<u16>> {
+    let mut ports = cli.ports.clone().unwrap_or_else(|| DEFAULT_PORTS.to_vec());
+    if !cli.exclude_port.is_empty() {
+        ports.retain(|port| !cli.exclude_port.contains(port));
+        if ports.is_empty() {
+            bail!("All ports excluded");
+        }
+        info!("Testing {} ports", ports.len());
+    }
+    Ok(ports)
+}
+
+/// Apply `--port-weights` to `ports`, returning the ports left and their
+/// weights. Ports without a weight get `1`, ports weighted `0` are dropped.
+fn weighted_ports(
+    mut ports: Vec<u16>,
+    weights: &[(u16, u32)],
+) -> Result<(Vec<u16>, Option<WeightedIndex<u32>>)> {
+    if weights.is_empty() {
+        return Ok((ports, None));
+    }
+
+    for (port, weight) in weights {
+        if !ports.contains(port) {
+            warn!("Ignoring the weight of port {port}, it is not in the port list");
+        } else if *weight == 0 {
+            warn!("Port {port} has weight 0 and will not be tested");
+        }
+    }
+    let weight_of = |port: &u16| {
+        weights
+            .iter()
+            .rev()
+            .find(|(p, _)| p == port)
+            .map_or(1, |&(_, weight)| weight)
+    };
+    ports.retain(|port| weight_of(port) > 0);
+
+    let index = WeightedIndex::new(ports.iter().map(weight_of))
+        .context("Every port has weight 0, nothing left to test")?;
+    Ok((ports, Some(index)))
+}
+
+#[tokio::main]
+async fn main() -> Result<ExitCode> {
+    let mut cli = Args::parse();
+
+    let mut logger = env_logger::builder();
+    logger
+        .filter_level(match cli.log_level {
+            Some(level) => level.into(),
+            None if cli.quiet => log::LevelFilter::Warn,
+            None => log::LevelFilter::Info,
+        })
+        .format_target(false)
+        .format_timestamp(None)
+        .parse_default_env();
+    // NO_COLOR is already honoured by the logger and by console
+    if cli.no_color {
+        logger.write_style(env_logger::WriteStyle::Never);
+        console::set_colors_enabled(false);
+        console::set_colors_enabled_stderr(false);
+    }
+    if cli.log_format == LogFormat::Json {
+        logger.format(logger::format_json);
+    }
+    logger.init();
+
+    // Generate shell completions
+    if let Some(generator) = cli.completion {
+        let mut cmd = Args::command();
+        let name = cmd.get_name().to_string();
+        generate(generator, &mut cmd, &name, &mut io::stdout());
+        return Ok(ExitCode::SUCCESS);
+    };
+
+    if cli.csv_header_only {
+        let sep = match cli.output_format {
+            OutputFormat::Tsv => '\t',
+            _ => cli.export_csv_sep,
+        };
+        let tags: BTreeMap<String, String> = cli.tag.iter().cloned().collect();
+        print!("{}", output::csv_header(sep, cli.latency_unit, tags.keys()));
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    if cli.results_json_schema {
+        print!("{}", output::json_schema(cli.latency_unit)?);
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    if let Some(Command::Diff(args)) = &cli.command {
+        diff::run(args)?;
+        return Ok(ExitCode::SUCCESS);
+    }
+    if let Some(Command::ExportHosts(args)) = &cli.command {
+        hosts::run(args)?;
+        return Ok(ExitCode::SUCCESS);
+    }
+    #[cfg(feature = "history")]
+    if let Some(Command::History(args)) = &cli.command {
+        history::run(args)?;
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    progress_style(&cli).context("Invalid --pb-template")?;
+
+    // Flushes the remaining spans when dropped, also on early returns
+    #[cfg(feature = "otel")]
+    let _otel = cli
+        .otel_endpoint
+        .as_deref()
+        .map(|endpoint| otel::init(endpoint, Duration::from_millis(cli.otel_flush_timeout)))
+        .transpose()?;
+
+    if cli.compare_v4_v6 && cli.mode != SpeedTestMode::Dual {
+        anyhow::bail!("--compare-v4-v6 needs both IP versions, use it with --mode dual");
+    }
+
+    if cli.fixed_ips && cli.input_file.is_none() && cli.whitelist_file.is_none() {
+        anyhow::bail!(
+            "--fixed-ips tests a given list, use it with --input-file or --whitelist-file"
+        );
+    }
+
+    if let (Some(min), Some(max)) = (cli.response_size_min, cli.response_size_max)
+        && min > max
+    {
+        bail!("--response-size-min {min} is above --response-size-max {max}");
+    }
+
+    if cli.jitter_only && cli.attempts < 2 {
+        anyhow::bail!("--jitter-only needs at least 2 --attempts to measure jitter");
+    }
+
+    if cli.min_sample > cli.attempts {
+        anyhow::bail!(
+            "--min-sample {} is more than --attempts {}",
+            cli.min_sample,
+            cli.attempts
+        );
+    }
+
+    if let Some(port) = cli.source_port
+        && usize::from(port).saturating_add(cli.concurrent_probers()) > usize::from(u16::MAX) + 1
+    {
+        anyhow::bail!(
+            "--source-port {port} with {} concurrent probes exceeds the highest port number",
+            cli.concurrent_probers()
+        );
+    }
+
+    if cli.json_pretty && cli.append {
+        warn!("Ignoring --json-pretty with --append, every run is kept on one line");
+        cli.json_pretty = false;
+    }
+
+    if cli.dry_run_stats {
+        print_dry_run_stats(&cli, port_list(&cli)?.len());
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    if let Some(path) = &cli.warp_registration {
+        cli.wireguard_pubkey = Some(export::read_registration(path)?.public_key);
+    }
+
+    socket::check(&socket_options(&cli))?;
+    if cli.mtu_check {
+        mtu::check(cli.bind_device.as_deref());
+    }
+
+    if !cli.skip_preflight {
+        preflight::check().await?;
+        if cli.test_connectivity && !cli.skip_preflight_check {
+            preflight::check_endpoint(cli.source_port, &socket_options(&cli)).await?;
+        }
+    }
+
+    let pool = AddressPool::new(&cli).await?;
+
+    if let Some(Command::Benchmark(args)) = &cli.command {
+        benchmark::run(&cli, args, &pool).await?;
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    if cli.recommend_attempts {
+        let piloted =
+            pilot::recommend_attempts(&cli, &pool, &socket_options(&cli), cli.margin_of_error_ms)
+                .await;
+        cli.addresses = cli.addresses.saturating_sub(piloted);
+    }
+
+    let estimate = estimate_scan_duration(&cli, pool.ports.len());
+    if estimate > LONG_SCAN_WARNING {
+        warn!(
+            "Worst-case scan duration is about {} minutes, consider lowering --attempts or --inter-attempt-delay",
+            estimate.as_secs().div_ceil(60)
+        );
+    }
+
+    // One generator for the whole run, so every round and --watch cycle
+    // draws a new sample, the same sequence of them for a fixed --seed
+    let mut rng = sample_rng(cli.seed);
+    let mut previous: Option<Vec<TestResult>> = None;
+    let mut exit_code = ExitCode::SUCCESS;
+    let mut cycle = 0;
+    loop {
+        cycle += 1;
+
+        let ranking = if cli.rounds == 1 {
+            run_single(&cli, &pool, &mut rng).await?
+        } else {
+            run_rounds(&cli, &pool, &mut rng).await?
+        };
+
+        export_results(&cli, &ranking)?;
+        if let Some(addr) = &cli.statsd_addr {
+            metrics::send_statsd(addr, &cli.statsd_prefix, &ranking, cli.latency_unit).await;
+        }
+        if let Some(threshold) = cli.alert_threshold {
+            let threshold = cli.latency_unit.of_ms(threshold);
+            exit_code = alert_exit_code(&ranking, threshold, cli.latency_unit);
+        } else if cli.numeric {
+            exit_code = if ranking.results.is_empty() {
+                ExitCode::from(1)
+            } else {
+                ExitCode::SUCCESS
+            };
+        }
+
+        if cli.fail_on_unreachable && !ranking.unreachable_subnets.is_empty() {
+            anyhow::bail!(
+                "{} subnets are completely unreachable",
+                ranking.unreachable_subnets.len()
+            );
+        }
+
+        if let Some(interval) = cli.ping_interval {
+            let top: Vec<SocketAddr> = ranking
+                .results
+                .iter()
+                .take(cli.top)
+                .map(|res| res.addr)
+                .collect();
+            monitor::run(
+                &top,
+                Duration::from_secs(interval),
+                cli.flap_threshold_ms,
+                cli.source_port,
+                &socket_options(&cli),
+            )
+            .await;
+            break;
+        }
+
+        let Some(interval) = cli.watch else {
+            break;
+        };
+
+        if let Some(previous) = &previous {
+            output::print_rank_changes(&ranking.results, previous, cli.top, cli.latency_unit);
+        }
+        previous = Some(ranking.results);
+
+        if cli.watch_count.is_some_and(|count| cycle >= count) {
+            break;
+        }
+
+        info!("Next scan in {interval} s, press Ctrl-C to stop");
+        tokio::select! {
+            _ = sleep(Duration::from_secs(interval)) => {}
+            _ = tokio::signal::ctrl_c() => {
+                info!("Stopping after {cycle} scans");
+                break;
+            }
+        }
+    }
+
+    Ok(exit_code)
+}
+
+/// The exit code for `--alert-threshold`: `1` without any working address,
+/// `2` if even the fastest one is slower than `threshold`.
+fn alert_exit_code(ranking: &Ranking, threshold: u128, unit: LatencyUnit) -> ExitCode {
+    match ranking.results.iter().map(|res| res.latency).min() {
+        None => {
+            warn!("Alert: no working IPs found");
+            ExitCode::from(1)
+        }
+        Some(best) if best > threshold => {
+            let unit = unit.label();
+            warn!("Alert: the fastest IP takes {best} {unit}, above {threshold} {unit}");
+            ExitCode::from(2)
+        }
+        Some(_) => ExitCode::SUCCESS,
+    }
+}
+
+/// The random generator addresses are sampled with, the same on every run
+/// for a fixed `seed`.
+fn sample_rng(seed: Option<u64>) -> StdRng {
+    match seed {
+        Some(seed) => StdRng::seed_from_u64(seed),
+        None => StdRng::from_rng(&mut rand::rng()),
+    }
+}
+
+/// Generate a fresh address sample drawn with `rng` for the configured IP
+/// version, or take the whitelisted addresses if there are any.
+///
+/// With `--all-ports` every sampled IP is paired with every port.
+fn generate_addrs(cli: &Args, pool: &AddressPool, rng: &mut StdRng) -> Vec<SocketAddr> {
+    let addrs = if let Some(whitelist) = &pool.whitelist {
+        let amount = if cli.fixed_ips {
+            usize::MAX
+        } else {
+            cli.addresses
+        };
+        targets::expand(
+            whitelist,
+            amount,
+            || pool.random_port(rng),
+            |ip| pool.is_blacklisted(ip),
+        )
+    } else {
+        let v4: Vec<SocketAddr> = generate_ipv4(
+            pool,
+            cli.addresses,
+            cli.sample_strategy,
+            cli.max_addresses_per_cidr,
+            rng,
+        )
+        .collect();
+        match cli.mode {
+            SpeedTestMode::Ipv4 => v4,
+            SpeedTestMode::Ipv6 => ipv6_twins(pool, &v4, rng),
+            SpeedTestMode::Dual => {
+                let v6 = ipv6_twins(pool, &v4, rng);
+                v4.into_iter().chain(v6).collect()
+            }
+        }
+    };
+
+    let mut addrs = if cli.every_port() {
+        ports::every_port(&addrs, &pool.ports)
+    } else {
+        addrs
+    };
+    if let Some(scope_id) = pool.scope_id {
+        for addr in &mut addrs {
+            if let SocketAddr::V6(addr) = addr
+                && addr.ip().is_unicast_link_local()
+            {
+                addr.set_scope_id(scope_id);
+            }
+        }
+    }
+
+    match cli.order {
+        Order::Random => {}
+        Order::Sequential => addrs.sort(),
+        Order::SubnetFirst => addrs = subnet::interleave(addrs),
+    }
+    addrs
+}
+
+/// Run a single scan, resuming from the checkpoint if enabled, and print it.
+///
+/// A checkpoint keeps the seed of the sample it records, so a resumed scan
+/// draws the same addresses and only probes those not tested yet.
+async fn run_single(cli: &Args, pool: &AddressPool, rng: &mut StdRng) -> Result<Ranking> {
+    let sample_seed = rng.random();
+    let mut checkpoint = cli
+        .checkpoint_path()
+        .map(|path| Checkpoint::open(&path, sample_seed, cli.latency_unit))
+        .transpose()?;
+    let seed = checkpoint.as_ref().map_or(sample_seed, Checkpoint::seed);
+    let mut addrs = generate_addrs(cli, pool, &mut sample_rng(Some(seed)));
+    if let Some(checkpoint) = &checkpoint {
+        checkpoint.skip_tested(&mut addrs);
+    }
+
+    let mut results = scan(cli, addrs.clone(), checkpoint.as_mut()).await?;
+
+    if let Some(checkpoint) = checkpoint {
+        checkpoint.merge_into(&mut results, &mut addrs);
+        checkpoint.finish(cli.keep_checkpoint)?;
+        sort_results(cli, &mut results.alive);
+    }
+
+    results.retain_completed(&mut addrs);
+    results.apply_filters(cli);
+    if let Some(n) = cli.verify_top_n {
+        let top = results.alive.len().min(n as usize);
+        verify::verify(
+            &mut results.alive[..top],
+            cli.attempts,
+            Duration::from_millis(cli.inter_attempt_delay),
+            cli.verify_threshold_pct,
+            cli.source_port,
+            &socket_options(cli),
+        )
+        .await;
+        rank_results(cli, &mut results.alive[..top]);
+    }
+    if cli.hop_count {
+        let top = results.alive.len().min(cli.top);
+        hops::annotate(&mut results.alive[..top]).await;
+    }
+    if cli.ping_compare {
+        let top = results.alive.len().min(cli.top);
+        icmp::compare(&mut results.alive[..top], cli.latency_unit).await;
+    }
+    if cli.pmtud {
+        let top = results.alive.len().min(cli.top);
+        pmtud::discover(&mut results.alive[..top], &socket_options(cli)).await;
+    }
+    if cli.rdns && !cli.quiet {
+        let top = results.alive.len().min(cli.top);
+        rdns::annotate(&mut results.alive[..top]).await;
+    }
+    #[cfg(feature = "asn")]
+    if cli.asn {
+        let database = asn::load().await?;
+        asn::annotate(&database, &mut results.alive);
+    }
+    #[cfg(feature = "geoip")]
+    if let Some(path) = &cli.geoip {
+        geoip::annotate(path, &mut results.alive)?;
+        if !cli.country_filter.is_empty() {
+            results
+                .alive
+                .retain(|res| geoip::in_countries(res, &cli.country_filter));
+        }
+    }
+    annotate_ranks(&mut results.alive);
+
+    #[cfg(feature = "history")]
+    if let Err(e) = history::record(&results.alive, cli.latency_unit) {
+        warn!("Failed to record the results in the history: {e:#}");
+    }
+
+    let report = build_report(cli, &addrs, &results);
+    print_results(cli, &report, cli.output_file.as_deref())?;
+    let files = cli
+        .outputs
+        .iter()
+        .map(|(format, path)| Ok((path.clone(), render(cli, &report, *format)?)))
+        .collect::<Result<_>>()?;
+    output::emit_all(files).await?;
+    if let Some(url) = &cli.webhook {
+        webhook::post(
+            url,
+            cli.webhook_auth_header.as_ref(),
+            report.to_json(false)?,
+        )
+        .await;
+    }
+    if let Some(path) = &cli.timeseries_csv {
+        export::write_timeseries(
+            path,
+            slice::from_ref(&results.alive),
+            cli.append_ts_csv,
+            cli.latency_unit,
+        )?;
+    }
+
+    Ok(Ranking {
+        tested: addrs.len(),
+        duration: results.duration,
+        unreachable_subnets: subnet::unreachable(&addrs, &results.alive),
+        results: results.alive,
+    })
+}
+
+/// Run every `--rounds` scan, print the aggregate and return it as a ranking.
+async fn run_rounds(cli: &Args, pool: &AddressPool, rng: &mut StdRng) -> Result<Ranking> {
+    let mut rounds = Vec::with_capacity(cli.rounds as usize);
+    let mut tested = Vec::new();
+    let mut duration = Duration::ZERO;
+    for round in 1..=cli.rounds {
+        let mut addrs = generate_addrs(cli, pool, rng);
+        let mut results = scan(cli, addrs.clone(), None).await?;
+        results.retain_completed(&mut addrs);
+        results.apply_filters(cli);
+        annotate_ranks(&mut results.alive);
+        duration += results.duration;
+
+        if cli.show_round_results {
+            info!("Round {round}/{}:", cli.rounds);
+            print_results(cli, &build_report(cli, &addrs, &results), None)?;
+        }
+
+        #[cfg(feature = "history")]
+        if let Err(e) = history::record(&results.alive, cli.latency_unit) {
+            warn!("Failed to record the results in the history: {e:#}");
+        }
+
+        tested.extend(addrs);
+        rounds.push(results.alive);
+    }
+
+    if let Some(path) = &cli.timeseries_csv {
+        export::write_timeseries(path, &rounds, cli.append_ts_csv, cli.latency_unit)?;
+    }
+
+    let mut stable = rounds::aggregate_rounds(&rounds, cli.ewma_alpha, !cli.all_ports);
+    stable.retain(|res| res.rounds_present >= cli.min_rounds_present);
+    if cli.sort_by_stability {
+        rounds::sort_by_stability(&mut stable);
+    } else if cli.score || cli.score_formula.is_some() {
+        // Scored like single scans, from the mean loss and jitter of the rounds
+        let mut scored: Vec<TestResult> = stable.iter().map(TestResult::from).collect();
+        apply_score(cli, &mut scored);
+        let rank: HashMap<SocketAddr, usize> = scored
+            .iter()
+            .enumerate()
+            .map(|(i, result)| (result.addr, i))
+            .collect();
+        stable.sort_by_key(|result| rank[&result.addr]);
+    }
+    let unreachable_subnets = subnet::unreachable(&tested, rounds.iter().flatten());
+
+    let output_file = cli.output_file.as_deref();
+    let top: Vec<TestResult> = stable.iter().take(cli.top).map(TestResult::from).collect();
+    let render = |format| -> Result<String> {
+        Ok(match format {
+            OutputFormat::Text => output::stable_text(&stable, usize::MAX, cli.latency_unit),
+            OutputFormat::Json => output::stable_json(
+                &stable,
+                rounds.len(),
+                cli.seed,
+                &unreachable_subnets,
+                cli.latency_unit,
+                cli.json_pretty,
+            )?,
+            OutputFormat::Csv => output::stable_csv(&stable, cli.export_csv_sep, cli.latency_unit),
+            OutputFormat::Tsv => output::stable_csv(&stable, '\t', cli.latency_unit),
+            OutputFormat::Influx => output::influx(
+                &top,
+                &cli.influx_measurement,
+                &cli.influx_tag,
+                cli.latency_unit,
+            ),
+        })
+    };
+    match cli.output_format {
+        _ if let Some(lines) = plain_lines(cli, stable.len(), &top, output_file) => {
+            save(cli, &lines, output_file, None)?
+        }
+        OutputFormat::Text if cli.quiet && output_file.is_none() => output::emit(
+            &output::stable_text(&stable, cli.top, cli.latency_unit),
+            None,
+        )?,
+        OutputFormat::Text => {
+            output::print_stable_text(
+                &stable,
+                rounds.len(),
+                cli.top,
+                cli.sort_by_stability,
+                cli.latency_unit,
+            );
+            output::print_unreachable(&unreachable_subnets);
+            if output_file.is_some() {
+                save(
+                    cli,
+                    &render(OutputFormat::Text)?,
+                    output_file,
+                    Some(OutputFormat::Text),
+                )?;
+            }
+        }
+        format => save(cli, &render(format)?, output_file, Some(format))?,
+    }
+    let files = cli
+        .outputs
+        .iter()
+        .map(|(format, path)| Ok((path.clone(), render(*format)?)))
+        .collect::<Result<_>>()?;
+    output::emit_all(files).await?;
+
+    if let Some(url) = &cli.webhook {
+        let body = output::stable_json(
+            &stable,
+            rounds.len(),
+            cli.seed,
+            &unreachable_subnets,
+            cli.latency_unit,
+            false,
+        )?;
+        webhook::post(url, cli.webhook_auth_header.as_ref(), body).await;
+    }
+
+    Ok(Ranking {
+        results: stable.iter().map(TestResult::from).collect(),
+        tested: tested.len(),
+        duration,
+        unreachable_subnets,
+    })
+}
+
+/// Write the top results to every requested export target.
+fn export_results(cli: &Args, ranking: &Ranking) -> Result<()> {
+    let top = &ranking.results[..ranking.results.len().min(cli.top)];
+
+    if let Some(path) = &cli.summary_json {
+        export::write_summary(path, ranking, cli.latency_unit)?;
+        debug!("Wrote the summary to {}", path.display());
+    }
+
+    if let Some(path) = &cli.metrics_file {
+        metrics::write_prometheus(path, ranking, top, cli.latency_unit)?;
+        debug!("Wrote metrics to {}", path.display());
+    }
+
+    if cli.warp_registration.is_some()
+        && let Some(key) = &cli.wireguard_pubkey
+    {
+        info!("WARP public key: {key}");
+    }
+
+    if let Some(path) = &cli.export_wireguard {
+        export::write_wireguard(path, top, cli.wireguard_pubkey.as_deref(), cli.latency_unit)?;
+        info!("Wrote WireGuard peers to {}", path.display());
+    }
+
+    if let Some(path) = &cli.export_networkd {
+        match top.first() {
+            Some(best) => {
+                export::write_networkd(
+                    path,
+                    best,
+                    &cli.networkd_interface,
+                    cli.wireguard_pubkey.as_deref(),
+                )?;
+                info!("Wrote the systemd-networkd device to {}", path.display());
+            }
+            None => warn!("No working IPs found, not writing {}", path.display()),
+        }
+    }
+
+    if let Some(path) = &cli.export_warp_conf {
+        match top.first() {
+            Some(best) => {
+                let registration = cli
+                    .warp_registration
+                    .as_deref()
+                    .map(export::read_registration)
+                    .transpose()?;
+                export::write_warp_conf(
+                    path,
+                    best,
+                    registration.as_ref(),
+                    cli.wireguard_pubkey.as_deref(),
+                )?;
+                info!("Wrote the WireGuard configuration to {}", path.display());
+            }
+            None => warn!("No working IPs found, not writing {}", path.display()),
+        }
+    }
+
+    if let Some(path) = &cli.export_resolv_conf {
+        match top.first() {
+            Some(best) => {
+                export::write_resolv_conf(path, best, cli.resolv_search.as_deref())?;
+                info!("Wrote the resolv.conf to {}", path.display());
+            }
+            None => warn!("No working IPs found, not writing {}", path.display()),
+        }
+    }
+
+    if let Some(path) = &cli.report_html {
+        report::write_html(path, ranking, top, cli.latency_unit)?;
+        info!("Wrote HTML report to {}", path.display());
+    }
+
+    if let Some(path) = &cli.report_md {
+        report::write_markdown(path, ranking, top, cli.latency_unit)?;
+        info!("Wrote Markdown report to {}", path.display());
+    }
+
+    if cli.export_warp_cli {
+        export::print_warp_cli(top);
+    }
+
+    if cli.exec_warp_cli {
+        match top.first() {
+            Some(best) => {
+                export::exec_warp_cli(best)?;
+                info!("Set WARP custom endpoint to {}", best.addr);
+            }
+            None => warn!("No working IPs found, not running warp-cli"),
+        }
+    }
+
+    #[cfg(feature = "clipboard")]
+    if cli.clipboard {
+        match top.first() {
+            Some(best) => match export::copy_to_clipboard(best) {
+                Ok(()) => info!("Copied {} to the clipboard", best.addr),
+                // Expected on headless machines, the results are printed anyway
+                Err(e) => warn!("{e:#}"),
+            },
+            None => warn!("No working IPs found, not copying to the clipboard"),
+        }
+    }
+
+    #[cfg(feature = "notify")]
+    if cli.notify && !cli.quiet {
+        // Expected on headless machines
+        if let Err(e) = export::notify(
+            top.first(),
+            ranking.results.len(),
+            ranking.tested,
+            cli.latency_unit,
+        ) {
+            debug!("{e:#}");
+        }
+    }
+
+    if let Some(command) = &cli.on_complete {
+        // A failing hook must not hide the results
+        if let Err(e) = export::run_on_complete(command, top.first(), ranking, &cli.tag) {
+            warn!("{e:#}");
+        }
+    }
+
+    Ok(())
 }
 
-#[tokio::main]
-async fn main() -> Result<()> {
-    env_logger::builder()
-        .filter_level(log::LevelFilter::Info)
-        .format_target(false)
-        .format_timestamp(None)
-        .parse_default_env()
-        .init();
+/// Everything reported about a single scan of the `tested` addresses.
+fn build_report<'a>(cli: &Args, tested: &[SocketAddr], results: &'a ScanResults) -> Report<'a> {
+    let alive_addrs = results.alive.as_slice();
+    let best_ports = cli
+        .all_ports
+        .then(|| ports::best_ports(tested, alive_addrs));
+    Report {
+        tags: cli.tag.iter().cloned().collect(),
+        alive: alive_addrs.len() + results.discarded.len(),
+        total: tested.len(),
+        scan_duration_ms: results.duration.as_millis(),
+        probes_per_second: results.probes as f64 / results.duration.as_secs_f64().max(f64::EPSILON),
+        results: alive_addrs,
+        fast_mode: cli.fast_mode,
+        jitter_only: cli.jitter_only,
+        latency_unit: cli.latency_unit,
+        latency_histogram: cli.histogram.then(|| {
+            let latencies = alive_addrs.iter().map(|res| res.latency);
+            match (cli.histogram_bucket_ms, cli.histogram_buckets) {
+                (Some(bucket_ms), _) => {
+                    histogram::build(latencies, cli.latency_unit.of_ms(bucket_ms.into()))
+                }
+                (None, Some(buckets)) => histogram::build_even(latencies, None, buckets),
+                (None, None) => histogram::build_even(
+                    latencies,
+                    Some([0, cli.latency_unit.of(PROBE_TIMEOUT)]),
+                    histogram::DEFAULT_BUCKETS,
+                ),
+            }
+        }),
+        subnet_stats: cli
+            .verbose_subnets
+            .then(|| subnet::subnet_stats(tested, alive_addrs)),
+        productivity: subnet::productivity(tested, alive_addrs),
+        seed: cli.seed,
+        unreachable_subnets: subnet::unreachable(tested, alive_addrs),
+        port_summary: best_ports.as_deref().map(ports::summary),
+        comparison: cli.compare_v4_v6.then(|| dualstack::compare(alive_addrs)),
+        best_ports,
+        dead: (cli.all_results || cli.fixed_ips).then_some(results.dead.as_slice()),
+        errors: results.errors,
+    }
+}
 
-    let cli = Args::parse();
+/// Print the report of a single scan in the configured output format, to
+/// `output_file` instead of stdout if given.
+fn print_results(cli: &Args, report: &Report, output_file: Option<&Path>) -> Result<()> {
+    let top = &report.results[..report.results.len().min(cli.top)];
+    if let Some(lines) = plain_lines(cli, report.alive, top, output_file) {
+        return save(cli, &lines, output_file, None);
+    }
 
-    // Generate shell completions
-    if let Some(generator) = cli.completion {
-        let mut cmd = Args::command();
-        let name = cmd.get_name().to_string();
-        generate(generator, &mut cmd, &name, &mut io::stdout());
-        return Ok(());
+    match cli.output_format {
+        OutputFormat::Text if cli.quiet && output_file.is_none() => {
+            output::emit(&report.to_text(cli.top), None)?
+        }
+        OutputFormat::Text => {
+            report.print_text(cli.top);
+            if output_file.is_some() {
+                save(
+                    cli,
+                    &report.to_text(usize::MAX),
+                    output_file,
+                    Some(OutputFormat::Text),
+                )?;
+            }
+        }
+        format => save(
+            cli,
+            &render(cli, report, format)?,
+            output_file,
+            Some(format),
+        )?,
+    }
+
+    Ok(())
+}
+
+/// Print `contents` or save them to `output_file`, appended to it with
+/// `--append`. `format` is `None` for `--numeric` and `--compact` lines.
+fn save(
+    cli: &Args,
+    contents: &str,
+    output_file: Option<&Path>,
+    format: Option<OutputFormat>,
+) -> Result<()> {
+    match output_file {
+        Some(path) if cli.append => output::append(contents, path, format),
+        _ => output::emit(contents, output_file),
+    }
+}
+
+/// The report of a single scan in `format`, as saved to a file.
+fn render(cli: &Args, report: &Report, format: OutputFormat) -> Result<String> {
+    Ok(match format {
+        OutputFormat::Text => report.to_text(usize::MAX),
+        OutputFormat::Json => report.to_json(cli.json_pretty)?,
+        OutputFormat::Csv => report.to_csv(cli.export_csv_sep),
+        OutputFormat::Tsv => report.to_csv('\t'),
+        OutputFormat::Influx => output::influx(
+            &report.results[..report.results.len().min(cli.top)],
+            &cli.influx_measurement,
+            &cli.influx_tag,
+            cli.latency_unit,
+        ),
+    })
+}
+
+/// The `--count-only` line of the `alive` count, or the `--numeric` or
+/// `--compact` lines of `top`, `None` in other modes.
+///
+/// With `--print0` every line ends in a null byte, plus a final newline on a
+/// terminal.
+fn plain_lines(
+    cli: &Args,
+    alive: usize,
+    top: &[TestResult],
+    output_file: Option<&Path>,
+) -> Option<String> {
+    let end = if cli.print0 { '\0' } else { '\n' };
+    let mut lines = if cli.count_only {
+        format!("{alive}{end}")
+    } else if cli.numeric {
+        output::numeric(top, end)
+    } else if cli.compact || cli.print0 {
+        output::compact(top, &cli.compact_sep, end)
+    } else {
+        return None;
     };
 
-    let addrs = match cli.mode {
-        SpeedTestMode::Ipv4 => generate_ipv4(cli.addresses),
-        SpeedTestMode::Ipv6 => todo!(),
+    if cli.print0 && !lines.is_empty() && output_file.is_none() && io::stdout().is_terminal() {
+        lines.push('\n');
+    }
+    Some(lines)
+}
+
+/// Number of addresses every round probes, with every port counted.
+fn addresses_per_round(cli: &Args, ports: usize) -> usize {
+    if cli.every_port() {
+        cli.addresses.saturating_mul(ports)
+    } else {
+        cli.addresses
+    }
+}
+
+/// Worst-case wall-clock time of all rounds, assuming every probe times out,
+/// `Duration::MAX` if that does not fit.
+fn estimate_scan_duration(cli: &Args, ports: usize) -> Duration {
+    let per_probe = PROBE_TIMEOUT + Duration::from_millis(cli.inter_attempt_delay);
+    let batches = addresses_per_round(cli, ports).div_ceil(cli.threads.max(1));
+    let secs =
+        per_probe.as_secs_f64() * f64::from(cli.attempts) * batches as f64 * f64::from(cli.rounds);
+    Duration::try_from_secs_f64(secs).unwrap_or(Duration::MAX)
+}
+
+/// Print what the scan would cost for `--dry-run-stats`.
+fn print_dry_run_stats(cli: &Args, ports: usize) {
+    let addresses = addresses_per_round(cli, ports).saturating_mul(cli.rounds as usize);
+    let probes = (addresses as u64).saturating_mul(u64::from(cli.attempts));
+    // Every address keeps the latency of each successful probe
+    let sample_bytes = probes.saturating_mul(size_of::<u128>() as u64);
+    println!(
+        "Probes: {probes} ({addresses} addresses * {} attempts)",
+        cli.attempts
+    );
+    println!(
+        "Worst-case duration: {:.0}s ({} threads, up to {} ms per probe)",
+        estimate_scan_duration(cli, ports).as_secs_f64(),
+        cli.threads,
+        PROBE_TIMEOUT.as_millis() + u128::from(cli.inter_attempt_delay)
+    );
+    println!(
+        "Latency samples: up to {:.1} KiB",
+        sample_bytes as f64 / 1024.0
+    );
+}
+
+/// Append a `--progress-to-file` line, `best` being in `unit`.
+fn write_progress(
+    file: &mut File,
+    pos: usize,
+    total: usize,
+    alive: usize,
+    best: Option<u128>,
+    unit: LatencyUnit,
+) -> Result<()> {
+    let line = output::json(
+        &output::Progress {
+            pos,
+            total,
+            alive,
+            best_ms: best,
+            ts: jiff::Timestamp::now().to_string(),
+        },
+        unit,
+        false,
+    )?;
+    write!(file, "{line}")?;
+    Ok(())
+}
+
+/// The progress bar style of `--pb-style`, with the `--pb-template` if given.
+fn progress_style(cli: &Args) -> Result<ProgressStyle, TemplateError> {
+    let (style, default_template) = match cli.pb_style {
+        PbStyle::Spinner => (
+            ProgressStyle::default_spinner(),
+            "{spinner:.green} [{elapsed_precise}] {pos}/{len} {msg}",
+        ),
+        PbStyle::Bar | PbStyle::None => (
+            ProgressStyle::default_bar(),
+            "{spinner:.green} [{elapsed_precise}] [{bar:40.cyan/blue}] {pos}/{len} {msg}",
+        ),
     };
+    Ok(style
+        .template(cli.pb_template.as_deref().unwrap_or(default_template))?
+        .progress_chars("#>-"))
+}
 
-    let progress_bar: Option<Arc<ProgressBar>> = if !log::log_enabled!(log::Level::Debug) {
-        let pb = Arc::new(ProgressBar::new(addrs.len() as u64 * cli.attempts as u64));
+/// Probe every address in `addrs`, sorting the working ones fastest first.
+///
+/// Every completed address is also recorded to `checkpoint`, if given.
+async fn scan(
+    cli: &Args,
+    addrs: Vec<SocketAddr>,
+    mut checkpoint: Option<&mut Checkpoint>,
+) -> Result<ScanResults> {
+    let attempts = cli.attempts;
+    let delay = Duration::from_millis(cli.inter_attempt_delay);
+    let cv_threshold = cli.cv_threshold;
+    let budget = cli.attempts_per_ms.map(Duration::from_millis);
+    let socket_options = socket_options(cli);
 
-        pb.set_style(
-            ProgressStyle::default_bar()
-                .template(
-                    "{spinner:.green} [{elapsed_precise}] [{bar:40.cyan/blue}] {pos}/{len} {msg}",
-                )
-                .unwrap()
-                .progress_chars("#>-"),
-        );
+    #[cfg(all(feature = "mmsg", target_os = "linux"))]
+    let packet = handshake_packet();
+    #[cfg(all(feature = "mmsg", target_os = "linux"))]
+    let prober = cli
+        .use_mmsg
+        .then(|| mmsg::Prober::bind(&addrs, cli.source_port, &socket_options, &packet))
+        .transpose()?
+        .map(Arc::new);
 
-        pb.set_message(format!(
-            "({} addresses * {} attempts)",
-            addrs.len(),
-            cli.attempts
-        ));
+    // With a fixed source port every concurrent prober needs a port of its own,
+    // so hand out `source_port..source_port + concurrent_probers()` and return
+    // them when done.
+    let source_ports: Arc<Mutex<Vec<u16>>> = Arc::new(Mutex::new(match cli.source_port {
+        Some(port) => source_port_pool(port, cli.concurrent_probers()),
+        None => Vec::new(),
+    }));
+
+    let total_addrs = addrs.len();
+    let total_probes = total_addrs as u64 * attempts as u64;
+
+    // Set to stop starting new addresses, in-flight ones still finish
+    let cancel = Arc::new(AtomicBool::new(false));
+
+    #[cfg(feature = "tui")]
+    let dashboard = cli
+        .tui
+        .then(|| tui::spawn(total_probes, cli.latency_unit, Arc::clone(&cancel)));
+    #[cfg(feature = "tui")]
+    let events = dashboard.as_ref().map(|(sender, _)| sender.clone());
+    #[cfg(not(feature = "tui"))]
+    let dashboard: Option<()> = None;
+
+    let progress_bar: Option<Arc<ProgressBar>> = if dashboard.is_none()
+        && !cli.quiet
+        && cli.pb_style != PbStyle::None
+        && !log::log_enabled!(log::Level::Debug)
+    {
+        let pb = Arc::new(ProgressBar::new(total_probes));
+
+        pb.set_style(progress_style(cli).expect("template is validated at startup"));
+        // A spinner only turns when ticked, probes may take a while
+        if cli.pb_style == PbStyle::Spinner {
+            pb.enable_steady_tick(Duration::from_millis(100));
+        }
+
+        pb.set_message(if cli.fast_mode {
+            "Probing... 0 alive so far".to_string()
+        } else if cli.every_port() {
+            let ips = addrs
+                .iter()
+                .map(SocketAddr::ip)
+                .collect::<HashSet<_>>()
+                .len();
+            format!(
+                "({} addresses * {} ports * {} attempts)",
+                ips,
+                addrs.len() / ips.max(1),
+                attempts
+            )
+        } else {
+            format!("({} addresses * {} attempts)", addrs.len(), attempts)
+        });
 
         Some(pb)
     } else {
         None
     };
 
-    let stream = tokio_stream::iter(addrs.into_iter())
-        .map(|ip_port| {
-            let pb: Option<Arc<ProgressBar>> = progress_bar.as_ref().map(Arc::clone);
-            async move {
-                let mut latencies = Vec::with_capacity(cli.attempts as usize);
-                for _ in 0..cli.attempts {
-                    if let Some(pb) = pb.as_ref() {
-                        pb.inc(1);
+    #[cfg(feature = "otel")]
+    let scan_span = otel::scan_span(addrs.len());
+    let errors = Arc::new(Mutex::new(ErrorCounts::default()));
+    let probes = Arc::new(AtomicU64::new(0));
+    // For `--skip-subnet-after` and `--stop-after-n-per-subnet`
+    let subnet_progress: Arc<Mutex<HashMap<IpNetwork, SubnetProgress>>> = Arc::default();
+    // Subnets without a working address yet, for `--stop-after-first-per-subnet`
+    let mut pending_subnets: HashSet<IpNetwork> = if cli.stop_after_first_per_subnet {
+        addrs
+            .iter()
+            .map(|addr| subnet::subnet_of(addr.ip()))
+            .collect()
+    } else {
+        HashSet::new()
+    };
+    let probe_log = cli
+        .latency_log_file
+        .as_deref()
+        .map(|path| output::ProbeLog::create(path, cli.latency_unit))
+        .transpose()?;
+    // When the next probe may be sent, for `--send-interval`
+    let send_interval = cli.send_interval.map(Duration::from_millis);
+    let next_send = Arc::new(Mutex::new(tokio::time::Instant::now()));
+    // Why the very first probe failed, for `--fail-fast`
+    let first_failure: Arc<Mutex<Option<String>>> = Arc::default();
+    let subnet_limiter = cli
+        .concurrent_subnets
+        .map(|n| subnet::SubnetLimiter::new(n as usize));
+    let scan_start = Instant::now();
+    // Probes one address, `None` if it was skipped
+    let probe_addr = |ip_port: SocketAddr| {
+        let pb: Option<Arc<ProgressBar>> = progress_bar.as_ref().map(Arc::clone);
+        let source_ports = Arc::clone(&source_ports);
+        let errors = Arc::clone(&errors);
+        let probes = Arc::clone(&probes);
+        let subnet_progress = Arc::clone(&subnet_progress);
+        let socket_options = &socket_options;
+        #[cfg(feature = "otel")]
+        let scan_span = &scan_span;
+        #[cfg(feature = "tui")]
+        let events = events.clone();
+        let subnet_limiter = subnet_limiter.as_ref();
+        let first_failure = Arc::clone(&first_failure);
+        let next_send = Arc::clone(&next_send);
+        let probe_log = probe_log.as_ref();
+        #[cfg(all(feature = "mmsg", target_os = "linux"))]
+        let (prober, packet) = (prober.as_ref().map(Arc::clone), &packet);
+        async move {
+            let subnet = subnet::subnet_of(ip_port.ip());
+            let progress = subnet_progress
+                .lock()
+                .unwrap()
+                .get(&subnet)
+                .copied()
+                .unwrap_or_default();
+            let skip_reason = if cli
+                .skip_subnet_after
+                .is_some_and(|limit| progress.failures >= limit)
+            {
+                Some("failed too often in a row")
+            } else if cli
+                .stop_after_n_per_subnet
+                .is_some_and(|limit| progress.alive >= limit)
+            {
+                Some("has enough working IPs")
+            } else {
+                None
+            };
+            if let Some(reason) = skip_reason {
+                debug!("Skipping {ip_port}, {subnet} {reason}");
+                errors.lock().unwrap().skipped += 1;
+                if let Some(pb) = pb.as_ref() {
+                    pb.inc(attempts.into());
+                }
+                return None;
+            }
+            let _active = match subnet_limiter {
+                Some(limiter) => Some(limiter.enter(subnet).await),
+                None => None,
+            };
+
+            // Never more probers than ports, falling back to an ephemeral
+            // port would send from one the firewall may block
+            let source_port = cli.source_port.map(|_| {
+                source_ports
+                    .lock()
+                    .unwrap()
+                    .pop()
+                    .expect("a source port for every concurrent prober")
+            });
+            let mut latencies = Vec::with_capacity(attempts as usize);
+            let mut last_error = None;
+            let mut sent = 0;
+            // Probes that failed to send, all of them make a system error
+            let mut send_failures = 0;
+            // Time spent waiting for responses, for `--attempts-per-ms`
+            let mut spent = Duration::ZERO;
+            // Round trip of the first probe, for `--adaptive-timeout`
+            let mut first_rtt = None;
+            while match budget {
+                Some(budget) => sent == 0 || (spent < budget && sent < cli.max_attempts),
+                None => {
+                    sent < attempts
+                        || (sent < attempts + cli.max_extra_attempts
+                            && cv_threshold
+                                .is_some_and(|threshold| latency_cv(&latencies) > threshold))
+                }
+            } {
+                if sent > 0 && !delay.is_zero() {
+                    sleep(delay).await;
+                }
+                if let Some(pb) = pb.as_ref() {
+                    if sent >= attempts {
+                        pb.inc_length(1);
                     }
-                    if let Ok(result) = speedtest(&ip_port).await {
+                    pb.inc(1);
+                }
+                sent += 1;
+                let first = probes.fetch_add(1, Ordering::Relaxed) == 0;
+                #[cfg(feature = "otel")]
+                let span = otel::probe_span(scan_span, &ip_port);
+                // `--adaptive-timeout` waits twice the first round trip
+                let probe_timeout = match first_rtt {
+                    Some(rtt) if cli.adaptive_timeout && sent > 1 => {
+                        (cli.latency_unit.duration(rtt) * 2)
+                            .clamp(MIN_ADAPTIVE_TIMEOUT, PROBE_TIMEOUT)
+                    }
+                    _ => PROBE_TIMEOUT,
+                };
+                if let Some(interval) = send_interval {
+                    let slot = {
+                        let mut next = next_send.lock().unwrap();
+                        let slot = (*next).max(tokio::time::Instant::now());
+                        *next = slot + interval;
+                        slot
+                    };
+                    sleep_until(slot).await;
+                }
+                let probe_start = Instant::now();
+                #[cfg(all(feature = "mmsg", target_os = "linux"))]
+                let probe = match &prober {
+                    Some(prober) => prober.probe(&ip_port, packet).await,
+                    None => {
+                        speedtest_within(
+                            &ip_port,
+                            source_port.unwrap_or(0),
+                            socket_options,
+                            probe_timeout,
+                        )
+                        .await
+                    }
+                };
+                #[cfg(not(all(feature = "mmsg", target_os = "linux")))]
+                let probe = speedtest_within(
+                    &ip_port,
+                    source_port.unwrap_or(0),
+                    socket_options,
+                    probe_timeout,
+                )
+                .await;
+                spent += probe_start.elapsed();
+                #[cfg(feature = "otel")]
+                otel::record(&span, &probe);
+                #[cfg(feature = "tui")]
+                if let Some(events) = &events {
+                    let _ = events.send(tui::Event::Probe {
+                        addr: ip_port,
+                        latency: probe.as_ref().ok().map(|res| res.latency),
+                    });
+                }
+                if let Some(log) = probe_log {
+                    log.record(&ip_port, sent, &probe);
+                }
+                match probe {
+                    Ok(result) => {
+                        if sent == 1 {
+                            first_rtt = Some(result.latency);
+                        }
                         latencies.push(result.latency);
                     }
+                    Err(e) => {
+                        errors.lock().unwrap().record(&e);
+                        if matches!(e, ProbeError::SendError(_)) {
+                            send_failures += 1;
+                        }
+                        if first && cli.fail_fast {
+                            *first_failure.lock().unwrap() =
+                                Some(format!("The first probe, to {ip_port}, failed: {e}"));
+                            last_error = Some(e);
+                            break;
+                        }
+                        last_error = Some(e);
+                    }
                 }
+            }
+
+            if let Some(port) = source_port {
+                source_ports.lock().unwrap().push(port);
+            }
+            // The bar counts --attempts probes per address
+            if let Some(pb) = pb.as_ref()
+                && sent < attempts
+            {
+                pb.inc((attempts - sent).into());
+            }
 
-                if latencies.is_empty() {
-                    None
+            let outcome = if latencies.len() < cli.min_sample as usize {
+                let status = if send_failures > 0 && send_failures == sent {
+                    errors.lock().unwrap().system_errors += 1;
+                    Status::SystemError
                 } else {
-                    let avg_latency = latencies.iter().sum::<u128>() / latencies.len() as u128;
-                    Some(TestResult {
-                        addr: ip_port,
-                        latency: avg_latency,
-                    })
+                    last_error.as_ref().map_or(Status::Timeout, Status::from)
+                };
+                Err(DeadAddr {
+                    addr: ip_port,
+                    latency_ms: None,
+                    status,
+                })
+            } else {
+                if sent > attempts {
+                    debug!(
+                        "{ip_port} needed {sent} probes, cv {:.2}",
+                        latency_cv(&latencies)
+                    );
+                }
+                let mut result = TestResult::from_samples(ip_port, latencies, sent);
+                if cli.remove_outliers {
+                    result = result.without_outliers();
+                }
+                if let Some(pct) = cli.confidence_interval
+                    && result.samples.len() >= 2
+                {
+                    let (ci_low, ci_high) = stats::bootstrap_ci(
+                        &result.samples,
+                        pct / 100.0,
+                        BOOTSTRAP_RESAMPLES,
+                        &mut rand::rng(),
+                    );
+                    result.ci = Some(ConfidenceInterval { ci_low, ci_high });
                 }
+                if cli.thorough_mode {
+                    result.stats = LatencyStats::of(&result.samples);
+                }
+                if cli.ecmp_detect {
+                    let samples: Vec<f64> = result.samples.iter().map(|&s| s as f64).collect();
+                    result.ecmp_suspected = Some(stats::is_bimodal(
+                        &samples,
+                        cli.latency_unit.of_ms_f64(ECMP_GAP_MS),
+                    ));
+                }
+                Ok(result)
+            };
+
+            if cli.skip_subnet_after.is_some() || cli.stop_after_n_per_subnet.is_some() {
+                let mut subnets = subnet_progress.lock().unwrap();
+                let progress = subnets.entry(subnet).or_default();
+                match outcome {
+                    Ok(_) => {
+                        progress.failures = 0;
+                        progress.alive += 1;
+                    }
+                    Err(_) => progress.failures += 1,
+                }
+            }
+
+            #[cfg(feature = "tui")]
+            if let Some(events) = &events {
+                let _ = events.send(tui::Event::Done {
+                    addr: ip_port,
+                    latency: outcome.as_ref().ok().map(|res| res.latency),
+                });
             }
+
+            Some(outcome)
+        }
+    };
+    let mut stream: Pin<Box<dyn Stream<Item = Result<TestResult, DeadAddr>> + Send + '_>> = if cli
+        .probe_all_ports
+    {
+        // `--threads` IPs at a time, every one with its own bound on ports
+        Box::pin(
+            tokio_stream::iter(ports::group_by_ip(addrs))
+                .take_while(|_| future::ready(!cancel.load(Ordering::Relaxed)))
+                .map(|(ip, ports)| {
+                    ports::probe_all_ports(ip, ports, cli.port_concurrency as usize, &probe_addr)
+                })
+                .buffer_unordered(cli.threads)
+                .flat_map(tokio_stream::iter)
+                .filter_map(future::ready),
+        )
+    } else {
+        Box::pin(
+            tokio_stream::iter(addrs)
+                .take_while(|_| future::ready(!cancel.load(Ordering::Relaxed)))
+                .map(&probe_addr)
+                .buffer_unordered(cli.threads)
+                .filter_map(future::ready),
+        )
+    };
+
+    let mut results = ScanResults::default();
+    let mut qualifying: u64 = 0;
+    // Once `--stop-after` is reached, in-flight probes get until this to finish
+    let mut grace_deadline = None;
+    // `--progress-to-file` defaults to a line a second
+    let status_interval = cli.status_interval.map(Duration::from_secs).or(cli
+        .progress_to_file
+        .is_some()
+        .then_some(Duration::from_secs(1)));
+    let mut progress_file = cli
+        .progress_to_file
+        .as_ref()
+        .map(|path| {
+            File::create(path).with_context(|| format!("Failed to create {}", path.display()))
         })
-        .buffer_unordered(cli.threads)
-        .filter_map(|res| async move { res });
+        .transpose()?;
+    let mut last_status = Instant::now();
+    let mut best: Option<u128> = None;
+    // Working addresses so far, including those `--result-limit` dropped
+    let mut alive = 0;
+    // Working addresses are pushed as they complete, with `--result-limit`
+    // only the fastest are kept
+    let mut kept = match cli.result_limit {
+        0 => BoundedHeap::unbounded(),
+        limit => BoundedHeap::new(limit),
+    };
+    loop {
+        let outcome = match grace_deadline {
+            Some(deadline) => match timeout_at(deadline, stream.next()).await {
+                Ok(outcome) => outcome,
+                Err(_) => {
+                    debug!("Abandoning in-flight probes after the grace period");
+                    break;
+                }
+            },
+            None => stream.next().await,
+        };
+        let Some(outcome) = outcome else {
+            break;
+        };
+        if let Some(failure) = first_failure.lock().unwrap().take() {
+            cancel.store(true, Ordering::Relaxed);
+            if let Some(pb) = &progress_bar {
+                pb.finish_and_clear();
+            }
+            bail!(failure);
+        }
+
+        if let Some(checkpoint) = checkpoint.as_deref_mut() {
+            checkpoint.record(match &outcome {
+                Ok(result) => result.into(),
+                Err(dead) => dead.into(),
+            })?;
+        }
+
+        // Show results as they come in when verbose, the progress bar is hidden
+        if log::log_enabled!(log::Level::Debug) {
+            match &outcome {
+                Ok(result) => info!(
+                    addr:% = result.addr,
+                    latency = result.latency;
+                    "{} - {} {}",
+                    result.addr,
+                    result.latency,
+                    cli.latency_unit.label()
+                ),
+                Err(dead) => info!(
+                    addr:% = dead.addr,
+                    status = dead.status.as_str();
+                    "{} - {}",
+                    dead.addr,
+                    dead.status.as_str()
+                ),
+            }
+        }
+
+        match outcome {
+            Ok(result) => {
+                if cli
+                    .max_latency
+                    .is_none_or(|max| result.latency <= cli.latency_unit.of_ms(max))
+                {
+                    qualifying += 1;
+                    pending_subnets.remove(&subnet::subnet_of(result.addr.ip()));
+                }
+                // `--fast-mode` streams working addresses above the progress bar
+                if cli.fast_mode
+                    && let Some(pb) = &progress_bar
+                {
+                    pb.println(format!(
+                        "{} - {} {}",
+                        result.addr,
+                        result.latency,
+                        cli.latency_unit.label()
+                    ));
+                    pb.set_message(format!("Probing... {} alive so far", alive + 1));
+                }
+                best = Some(best.map_or(result.latency, |best| best.min(result.latency)));
+                alive += 1;
+                if let Some(ByLatency(evicted)) = kept.push(ByLatency(result)) {
+                    results.discarded.push(evicted.addr);
+                }
+            }
+            Err(dead) => results.dead.push(dead),
+        }
+
+        if let Some(interval) = status_interval
+            && last_status.elapsed() >= interval
+        {
+            last_status = Instant::now();
+            let pos = alive + results.dead.len();
+            if cli.status_interval.is_some() {
+                let best = best.map_or_else(
+                    || "-".to_string(),
+                    |best| format!("{best}{}", cli.latency_unit.label()),
+                );
+                let status = format!(
+                    "[Status] {pos}/{total_addrs} probed, {alive} alive so far, best: {best}"
+                );
+                match &progress_bar {
+                    Some(pb) => pb.println(status),
+                    None => eprintln!("{status}"),
+                }
+            }
+            if let Some(file) = progress_file.as_mut() {
+                write_progress(file, pos, total_addrs, alive, best, cli.latency_unit)?;
+            }
+        }
+
+        // Dropping the stream below cancels the in-flight probes
+        if cli.first && qualifying > 0 {
+            info!("Found a working IP, stopping");
+            break;
+        }
+
+        let reached = cli.stop_after.is_some_and(|wanted| qualifying >= wanted);
+        let covered = cli.stop_after_first_per_subnet && pending_subnets.is_empty();
+        if (reached || covered) && grace_deadline.is_none() {
+            if reached {
+                info!("Found {qualifying} working IPs, stopping early");
+            } else {
+                info!("Found a working IP in every subnet, stopping early");
+            }
+            cancel.store(true, Ordering::Relaxed);
+            grace_deadline =
+                Some(tokio::time::Instant::now() + Duration::from_millis(cli.stop_after_grace_ms));
+        }
+    }
+    // The final state, wherever the last periodic line left off
+    if let Some(file) = progress_file.as_mut() {
+        let pos = alive + results.dead.len();
+        write_progress(file, pos, total_addrs, alive, best, cli.latency_unit)?;
+    }
+    results.errors = *errors.lock().unwrap();
+    results.probes = probes.load(Ordering::Relaxed);
+    if cli.retest_failed {
+        let concurrency = if cli.source_port.is_some() {
+            1
+        } else {
+            cli.threads
+        };
+        let source_port = cli.source_port.unwrap_or(0);
+        for result in
+            retest::send_errors(&mut results, concurrency, source_port, &socket_options).await
+        {
+            if let Some(ByLatency(evicted)) = kept.push(ByLatency(result)) {
+                results.discarded.push(evicted.addr);
+            }
+        }
+    }
+    // Already fastest first, only other orders need sorting again
+    results.alive = kept.into_sorted().into_iter().map(|kept| kept.0).collect();
+    if cli.sort_by != SortBy::Latency || cli.thorough_mode {
+        sort_results(cli, &mut results.alive);
+    }
+    results.duration = scan_start.elapsed();
 
-    let mut alive_addrs: Vec<TestResult> = stream.collect().await;
-    alive_addrs.sort_by_key(|res| res.latency);
+    // Release the workers' handles on the shared state
+    drop(stream);
+    if let Some(log) = &probe_log {
+        log.finish()?;
+    }
+
+    #[cfg(feature = "tui")]
+    if let Some((sender, handle)) = dashboard {
+        drop(events);
+        drop(sender);
+        handle.await??;
+    }
 
-    if let Some(pb) = progress_bar {
+    if let Some(pb) = &progress_bar {
         pb.finish_with_message("Done!");
     }
 
-    info!(
-        "Found {} working IPs out of {} IPs",
-        alive_addrs.len(),
-        cli.addresses
-    );
+    Ok(results)
+}
+
+/// The `probers` consecutive source ports from `first`, handed out by the
+/// scan workers.
+fn source_port_pool(first: u16, probers: usize) -> Vec<u16> {
+    (0..probers).map(|i| first + i as u16).collect()
+}
 
-    info!("Top 5 IPs with lowest latency:");
-    for result in alive_addrs.iter().take(5) {
-        info!("{} - {} ms", result.addr, result.latency);
+/// Order `results` by `--score-formula` or `--score` if given, otherwise as
+/// `--sort-by` says.
+fn rank_results(cli: &Args, results: &mut [TestResult]) {
+    if cli.score || cli.score_formula.is_some() {
+        apply_score(cli, results);
+    } else {
+        sort_results(cli, results);
     }
+}
 
-    Ok(())
+/// Order `results` as `--sort-by` says, fastest first by default.
+fn sort_results(cli: &Args, results: &mut [TestResult]) {
+    match cli.sort_by {
+        SortBy::PingVariance => {
+            results.sort_by(|a, b| a.ping_variance_ms.total_cmp(&b.ping_variance_ms));
+        }
+        SortBy::Jitter => results.sort_by(|a, b| a.jitter_ms.total_cmp(&b.jitter_ms)),
+        SortBy::Latency if cli.thorough_mode => {
+            let median = |res: &TestResult| res.stats.map_or(f64::INFINITY, |stats| stats.p50_ms);
+            results.sort_by(|a, b| median(a).total_cmp(&median(b)));
+        }
+        SortBy::Latency => {
+            results.sort_by(|a, b| a.latency.cmp(&b.latency).then(a.addr.cmp(&b.addr)));
+        }
+    }
+}
+
+/// The probe socket options set on the command line.
+fn socket_options(cli: &Args) -> SocketOptions {
+    SocketOptions {
+        recv_buf_size: cli.recv_buf_size,
+        send_buf_size: cli.send_buf_size,
+        mark: cli.socket_mark,
+        priority: cli.socket_priority,
+        bind_device: cli.bind_device.clone(),
+        connect: !cli.allow_src_mismatch,
+        latency_unit: cli.latency_unit,
+        response_size: cli
+            .response_size_min
+            .zip(cli.response_size_max)
+            .map(|(min, max)| (min.into(), max.into())),
+    }
+}
+
+/// Coefficient of variation of the latencies seen so far, `0.0` with fewer
+/// than two of them.
+fn latency_cv(latencies: &[u128]) -> f64 {
+    if latencies.len() < 2 {
+        return 0.0;
+    }
+    let as_f64: Vec<f64> = latencies.iter().map(|&l| l as f64).collect();
+    stats::coefficient_of_variation(&as_f64)
+}
+
+/// Generate `amount` of IPv4 addresses from `pool` with a random port.
+///
+/// With [`SampleStrategy::Uniform`], `amount` is split evenly across the
+/// ranges first so every subnet is represented equally, regardless of its
+/// size. [`SampleStrategy::Sequential`] takes the first addresses in range
+/// order. Blacklisted addresses are excluded before sampling. With `cap`, at
+/// most that many addresses come from any one range, see [`capped_shares`].
+///
+/// The ranges are walked lazily and reservoir-sampled, so only the `amount`
+/// chosen addresses are ever held in memory, however large the ranges are.
+/// Ports are assigned as the returned iterator is consumed.
+fn generate_ipv4<'a>(
+    pool: &'a AddressPool,
+    amount: usize,
+    strategy: SampleStrategy,
+    cap: Option<u32>,
+    rng: &'a mut impl Rng,
+) -> impl Iterator<Item = SocketAddr> + 'a {
+    let ips = pool
+        .v4_ranges
+        .iter()
+        .flat_map(|network| network.iter())
+        .filter(|ip| !pool.is_blacklisted((*ip).into()));
+    let ips = match (strategy, cap) {
+        (SampleStrategy::Uniform, None) => sample_uniform(pool, amount, rng),
+        (SampleStrategy::Weighted, None) => ips.choose_multiple(rng, amount),
+        (SampleStrategy::Sequential, None) => ips.take(amount).collect(),
+        (SampleStrategy::Sequential, Some(cap)) => pool
+            .v4_ranges
+            .iter()
+            .flat_map(|network| {
+                network
+                    .iter()
+                    .filter(|ip| !pool.is_blacklisted((*ip).into()))
+                    .take(cap as usize)
+            })
+            .take(amount)
+            .collect(),
+        (strategy, Some(cap)) => {
+            let sizes: Vec<u64> = pool
+                .v4_ranges
+                .iter()
+                .map(|network| network.size().into())
+                .collect();
+            let shares = capped_shares(
+                &sizes,
+                amount,
+                cap.into(),
+                strategy == SampleStrategy::Weighted,
+            );
+            sample_shares(pool, &shares, rng)
+        }
+    };
+    if let Some(cap) = cap
+        && ips.len() < amount
+    {
+        warn!(
+            "Only {} of the {amount} --addresses can be sampled with --max-addresses-per-cidr {cap}",
+            ips.len()
+        );
+    }
+
+    ips.into_iter()
+        .map(move |addr| SocketAddr::V4(SocketAddrV4::new(addr, pool.random_port(rng))))
 }
 
-/// Generate `amount` of random IPv4 addresses with a random port.
-fn generate_ipv4(amount: usize) -> Vec<SocketAddr> {
-    let v4_ranges = [
-        "162.159.192.0/24",
-        "162.159.193.0/24",
-        "162.159.195.0/24",
-        "162.159.204.0/24",
-        "188.114.96.0/24",
-        "188.114.97.0/24",
-        "188.114.98.0/24",
-        "188.114.99.0/24",
-    ];
-
-    let ports = [
-        500, 854, 859, 864, 878, 880, 890, 891, 894, 903, 908, 928, 934, 939, 942, 943, 945, 946,
-        955, 968, 987, 988, 1002, 1010, 1014, 1018, 1070, 1074, 1180, 1387, 1701, 2408, 4500, 5050,
-        5242, 6515, 7103, 7152, 7156, 7281, 7559, 8319, 8742, 8854, 8886,
-    ];
-
-    let mut rng = rand::rng();
-
-    let all_ips: Vec<_> = v4_ranges
+/// The IPv6 twin of every IPv4 address in `addrs`, on the same port.
+///
+/// Cloudflare embeds the IPv4 address of an endpoint in the low 32 bits of
+/// its IPv6 one, e.g. `162.159.192.1` and `2606:4700:d0::a29f:c001`. Each
+/// twin is placed in a random IPv6 range of the pool.
+fn ipv6_twins(pool: &AddressPool, addrs: &[SocketAddr], rng: &mut impl Rng) -> Vec<SocketAddr> {
+    addrs
         .iter()
-        .flat_map(|cidr| {
-            let network: Ipv4Network = cidr.parse().expect("Invalid CIDR");
-            network.iter()
+        .filter_map(|addr| {
+            let IpAddr::V4(ip) = addr.ip() else {
+                return None;
+            };
+            let network = pool.v6_ranges.choose(rng)?;
+            let twin = Ipv6Addr::from(u128::from(network.network()) | u128::from(u32::from(ip)));
+            Some(SocketAddr::new(twin.into(), addr.port()))
         })
+        .filter(|addr| !pool.is_blacklisted(addr.ip()))
+        .collect()
+}
+
+/// Sample `amount` addresses spread evenly across the pool's ranges.
+///
+/// Each network gets `amount / networks.len()` addresses, and the remainder is
+/// handed out round-robin starting from the first network.
+fn sample_uniform(pool: &AddressPool, amount: usize, rng: &mut impl Rng) -> Vec<Ipv4Addr> {
+    let networks = &pool.v4_ranges;
+    let base = amount / networks.len();
+    let remainder = amount % networks.len();
+    let shares: Vec<usize> = (0..networks.len())
+        .map(|i| base + usize::from(i < remainder))
         .collect();
+    sample_shares(pool, &shares, rng)
+}
 
-    all_ips
+/// Sample `shares[i]` addresses from the `i`th range of the pool.
+fn sample_shares(pool: &AddressPool, shares: &[usize], rng: &mut impl Rng) -> Vec<Ipv4Addr> {
+    pool.v4_ranges
         .iter()
-        .choose_multiple(&mut rng, amount)
-        .iter()
-        .map(|&addr| {
-            let port = ports.choose(&mut rng).unwrap();
-            SocketAddr::V4(SocketAddrV4::new(*addr, *port))
+        .zip(shares)
+        .flat_map(|(network, &share)| {
+            network
+                .iter()
+                .filter(|ip| !pool.is_blacklisted((*ip).into()))
+                .choose_multiple(rng, share)
         })
         .collect()
 }
 
-/// Measures the latency to a Cloudflare Warp node through UDP
-async fn speedtest(addr: &SocketAddr) -> Result<TestResult> {
-    let warp_handshake_packet = "013cbdafb4135cac96a29484d7a0175ab152dd3e59be35049beadf758b8d48af14ca65f25a168934746fe8bc8867b1c17113d71c0fac5c141ef9f35783ffa5357c9871f4a006662b83ad71245a862495376a5fe3b4f2e1f06974d748416670e5f9b086297f652e6dfbf742fbfc63c3d8aeb175a3e9b7582fbc67c77577e4c0b32b05f92900000000000000000000000000000000";
-    let packet_data = decode(warp_handshake_packet).expect("Invalid hex string");
+/// How many of `amount` addresses to sample from ranges of `sizes`, with at
+/// most `cap` from each.
+///
+/// Shares are even, or proportional to the size of the range if `weighted`.
+/// Whatever a full range would have gotten over its limit is split again
+/// among the others, until `amount` is reached or every range is full.
+fn capped_shares(sizes: &[u64], amount: usize, cap: u64, weighted: bool) -> Vec<usize> {
+    let limits: Vec<u64> = sizes.iter().map(|&size| size.min(cap)).collect();
+    let mut shares = vec![0u64; sizes.len()];
+    let mut left = (amount as u64).min(limits.iter().sum());
+    while left > 0 {
+        let open: Vec<usize> = (0..sizes.len())
+            .filter(|&i| shares[i] < limits[i])
+            .collect();
+        let weight = |i: usize| if weighted { sizes[i] } else { 1 };
+        let total: u64 = open.iter().map(|&i| weight(i)).sum();
+        let mut given = 0;
+        for &i in &open {
+            let share = (u128::from(left) * u128::from(weight(i)) / u128::from(total)) as u64;
+            let share = share.min(limits[i] - shares[i]);
+            shares[i] += share;
+            given += share;
+        }
+        if given == 0 {
+            // Fewer addresses left than open ranges, one each from the first
+            for &i in open.iter().take(left as usize) {
+                shares[i] += 1;
+            }
+            break;
+        }
+        left -= given;
+    }
+    shares.into_iter().map(|share| share as usize).collect()
+}
 
-    let bind_addr = match addr {
-        SocketAddr::V4(_) => "0.0.0.0:0",
-        SocketAddr::V6(_) => "[::]:0",
+/// Whether `response` is a handshake response: of the handshake response
+/// type, and [`HANDSHAKE_RESPONSE_LEN`] bytes long or within the `size`
+/// bounds of `--response-size-min` and `--response-size-max`.
+fn is_handshake_response(response: &[u8], size: Option<(usize, usize)>) -> bool {
+    let sized = match size {
+        Some((min, max)) => (min..=max).contains(&response.len()),
+        None => response.len() == HANDSHAKE_RESPONSE_LEN,
     };
-    let socket = UdpSocket::bind(bind_addr).await?;
+    sized && response.first() == Some(&HANDSHAKE_RESPONSE_TYPE)
+}
+
+/// The WireGuard handshake initiation sent to every endpoint.
+fn handshake_packet() -> Vec<u8> {
+    let warp_handshake_packet = "013cbdafb4135cac96a29484d7a0175ab152dd3e59be35049beadf758b8d48af14ca65f25a168934746fe8bc8867b1c17113d71c0fac5c141ef9f35783ffa5357c9871f4a006662b83ad71245a862495376a5fe3b4f2e1f06974d748416670e5f9b086297f652e6dfbf742fbfc63c3d8aeb175a3e9b7582fbc67c77577e4c0b32b05f92900000000000000000000000000000000";
+    decode(warp_handshake_packet).expect("Invalid hex string")
+}
+
+/// Measures the latency to a Cloudflare Warp node through UDP
+///
+/// The probe is sent from `source_port`, `0` lets the OS pick one. Unless
+/// `options.connect` is off, only a response from `addr` itself counts.
+async fn speedtest(
+    addr: &SocketAddr,
+    source_port: u16,
+    options: &SocketOptions,
+) -> Result<TestResult, ProbeError> {
+    speedtest_within(addr, source_port, options, PROBE_TIMEOUT).await
+}
+
+/// [`speedtest`] waiting at most `probe_timeout` for the response.
+async fn speedtest_within(
+    addr: &SocketAddr,
+    source_port: u16,
+    options: &SocketOptions,
+    probe_timeout: Duration,
+) -> Result<TestResult, ProbeError> {
+    let packet_data = handshake_packet();
 
-    socket.send_to(&packet_data, addr).await?;
+    let socket = socket::bind(addr, source_port, options).map_err(ProbeError::SendError)?;
+
+    socket
+        .send_to(&packet_data, addr)
+        .await
+        .map_err(|e| ProbeError::from_io(e, ProbeError::SendError))?;
     debug!("Pinging {addr}");
 
-    let mut buf = [0u8; 92];
+    let mut buf = [0u8; MAX_RESPONSE_LEN];
     let start = Instant::now();
 
-    let recv_result = timeout(Duration::from_secs(1), socket.recv_from(&mut buf)).await;
+    // A connected socket makes ICMP port unreachable surface as an error right
+    // away instead of after the full timeout
+    let recv = async {
+        if options.connect {
+            socket::recv(&socket, &mut buf)
+                .await
+                .map(|len| (len, *addr))
+        } else {
+            socket.recv_from(&mut buf).await
+        }
+    };
+    let recv_result = timeout(probe_timeout, recv).await;
 
     match recv_result {
         Ok(Ok((len, src))) => {
-            let elapsed = start.elapsed().as_millis();
-            debug!("Received {len} bytes from {src} in {elapsed} ms");
+            let elapsed = options.latency_unit.of(start.elapsed());
+            if !is_handshake_response(&buf[..len], options.response_size) {
+                debug!("Invalid {len} byte response from {src}");
+                return Err(ProbeError::InvalidResponse);
+            }
+            if src != *addr {
+                debug!("Accepting response to {addr} from {src}");
+            }
+            debug!(
+                addr:% = src,
+                latency = elapsed;
+                "Received {len} bytes from {src} in {elapsed} {}",
+                options.latency_unit.label()
+            );
 
-            Ok(TestResult {
-                addr: *addr,
-                latency: elapsed,
-            })
+            Ok(TestResult::from_samples(*addr, vec![elapsed], 1))
         }
         Ok(Err(e)) => {
-            // Underlying recv_from error
-            Err(e.into())
+            let e = ProbeError::from_io(e, ProbeError::RecvError);
+            debug!("{e} from {addr}");
+            Err(e)
         }
         Err(_) => {
             // Timeout elapsed
             debug!("Timeout from {addr}");
-            Err(anyhow::anyhow!(
-                "Timeout waiting for response from {}",
-                addr
-            ))
+            Err(ProbeError::Timeout)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::stream;
+    use std::sync::atomic::AtomicUsize;
+
+    /// A pool of the `v4_ranges` CIDRs and `ports`, nothing else configured.
+    fn pool(v4_ranges: &[&str], ports: &[u16]) -> AddressPool {
+        AddressPool {
+            v4_ranges: v4_ranges.iter().map(|cidr| cidr.parse().unwrap()).collect(),
+            v6_ranges: Vec::new(),
+            ports: ports.to_vec(),
+            port_weights: None,
+            first_port_only: false,
+            blacklist: Vec::new(),
+            whitelist: None,
+            scope_id: None,
+        }
+    }
+
+    #[test]
+    fn sample_uniform_spreads_evenly() {
+        let ranges: Vec<String> = (0..8).map(|i| format!("10.0.{i}.0/24")).collect();
+        let ranges: Vec<&str> = ranges.iter().map(String::as_str).collect();
+        let pool = pool(&ranges, &[2408]);
+        let mut rng = StdRng::seed_from_u64(0);
+
+        let ips = sample_uniform(&pool, 83, &mut rng);
+        assert_eq!(ips.len(), 83);
+        for (i, network) in pool.v4_ranges.iter().enumerate() {
+            let count = ips.iter().filter(|&&ip| network.contains(ip)).count();
+            // The 3 left over go to the first ranges
+            assert_eq!(count, if i < 3 { 11 } else { 10 }, "{network}");
         }
     }
+
+    #[test]
+    fn seed_samples_the_same_addresses() {
+        let pool = pool(&["10.0.0.0/16", "10.1.0.0/16"], &[500, 2408, 4500]);
+        let sample = |seed: &str| {
+            let cli = Args::try_parse_from(["warp", "--addresses", "50", "--seed", seed]).unwrap();
+            generate_addrs(&cli, &pool, &mut sample_rng(cli.seed))
+        };
+
+        assert_eq!(sample("42"), sample("42"));
+        assert_ne!(sample("42"), sample("43"));
+    }
+
+    #[test]
+    fn dedup_keeps_the_sort_by_order() {
+        let cli =
+            Args::try_parse_from(["warp", "--sort-by", "jitter", "--dedup-threshold-ms", "1"])
+                .unwrap();
+        let result = |addr: &str, latency: u128, jitter_ms: f64| TestResult {
+            jitter_ms,
+            ..TestResult::from_samples(addr.parse().unwrap(), vec![latency], 1)
+        };
+        // Ranked by jitter as the scan leaves them
+        let mut results = ScanResults {
+            alive: vec![
+                result("10.0.0.1:2408", 30, 0.5),
+                result("10.0.0.2:2408", 20, 1.0),
+                result("10.0.0.3:2408", 10, 4.0),
+                result("10.0.0.4:2408", 21, 9.0),
+            ],
+            ..ScanResults::default()
+        };
+        results.apply_filters(&cli);
+
+        // 10.0.0.4 is within 1 ms of the faster 10.0.0.2
+        let kept: Vec<SocketAddr> = results.alive.iter().map(|res| res.addr).collect();
+        assert_eq!(
+            kept,
+            ["10.0.0.1:2408", "10.0.0.2:2408", "10.0.0.3:2408"].map(|addr| addr.parse().unwrap())
+        );
+    }
+
+    #[test]
+    fn scan_estimate_saturates() {
+        let cli =
+            Args::try_parse_from(["warp", "-n", "250", "-t", "100", "--attempts", "2"]).unwrap();
+        assert_eq!(estimate_scan_duration(&cli, 1), Duration::from_secs(6));
+
+        let huge = usize::MAX.to_string();
+        let cli = Args::try_parse_from(["warp", "-n", &huge, "-t", "1", "--all-ports"]).unwrap();
+        assert_eq!(estimate_scan_duration(&cli, 2), Duration::MAX);
+    }
+
+    #[tokio::test]
+    async fn source_port_pool_covers_every_prober() {
+        let cli = Args::try_parse_from([
+            "warp",
+            "--threads",
+            "2",
+            "--probe-all-ports",
+            "--port-concurrency",
+            "3",
+            "--source-port",
+            "40000",
+        ])
+        .unwrap();
+        let pool = Mutex::new(source_port_pool(40000, cli.concurrent_probers()));
+        let peak = AtomicUsize::new(0);
+
+        // The scan's shape: --threads IPs at a time, each probing its ports
+        let ips = (1..=6).map(|i| IpAddr::from([10, 0, 0, i]));
+        stream::iter(ips)
+            .map(|ip| {
+                ports::probe_all_ports(
+                    ip,
+                    (1..=5).collect(),
+                    cli.port_concurrency as usize,
+                    |_| async {
+                        let port = pool.lock().unwrap().pop().expect("the pool ran dry");
+                        let taken = 6 - pool.lock().unwrap().len();
+                        peak.fetch_max(taken, Ordering::SeqCst);
+                        sleep(Duration::from_millis(2)).await;
+                        pool.lock().unwrap().push(port);
+                    },
+                )
+            })
+            .buffer_unordered(cli.threads)
+            .collect::<Vec<_>>()
+            .await;
+        assert_eq!(peak.load(Ordering::SeqCst), 6);
+    }
+
+    #[test]
+    fn weighted_ports_drop_weight_zero() {
+        let (ports, weights) =
+            weighted_ports(vec![500, 1701, 2408], &[(500, 0), (2408, 9)]).unwrap();
+        assert_eq!(ports, [1701, 2408]);
+
+        let mut pool = pool(&["10.0.0.0/24"], &ports);
+        pool.port_weights = weights;
+        let mut rng = StdRng::seed_from_u64(0);
+        let picked: Vec<u16> = (0..1000).map(|_| pool.random_port(&mut rng)).collect();
+        let heavy = picked.iter().filter(|&&port| port == 2408).count();
+        // 2408 is 9 times as likely as 1701
+        assert!((850..=950).contains(&heavy), "{heavy}");
+    }
+
+    #[test]
+    fn weighted_ports_need_a_weighted_port() {
+        assert!(weighted_ports(vec![500, 2408], &[(500, 0), (2408, 0)]).is_err());
+    }
+
+    #[tokio::test]
+    async fn no_randomize_uses_the_first_port() {
+        let cli = Args::try_parse_from([
+            "warp",
+            "--addresses",
+            "50",
+            "--ports",
+            "2408,500,1701",
+            "--no-randomize",
+        ])
+        .unwrap();
+        let pool = AddressPool::new(&cli).await.unwrap();
+
+        let addrs = generate_addrs(&cli, &pool, &mut sample_rng(cli.seed));
+        assert_eq!(addrs.len(), 50);
+        assert!(addrs.iter().all(|addr| addr.port() == 2408));
+    }
+
+    #[tokio::test]
+    async fn extra_cidr_is_scanned_minus_excluded_subnets() {
+        let cli = Args::try_parse_from([
+            "warp",
+            "--extra-cidr",
+            "192.0.2.0/24",
+            "--extra-cidr",
+            "162.159.192.0/24",
+            "--exclude-subnet",
+            "192.0.2.0/25",
+        ])
+        .unwrap();
+        let pool = AddressPool::new(&cli).await.unwrap();
+        let extra: Ipv4Network = "192.0.2.0/24".parse().unwrap();
+        // The default range is not added twice
+        assert_eq!(pool.v4_ranges.len(), DEFAULT_V4_RANGES.len() + 1);
+        let index = pool
+            .v4_ranges
+            .iter()
+            .position(|range| *range == extra)
+            .unwrap();
+
+        // Ask for more than the 128 addresses left in the extra range
+        let mut shares = vec![0; pool.v4_ranges.len()];
+        shares[index] = 200;
+        let mut rng = StdRng::seed_from_u64(0);
+        let ips = sample_shares(&pool, &shares, &mut rng);
+        assert_eq!(ips.len(), 128);
+        assert!(
+            ips.iter()
+                .all(|ip| extra.contains(*ip) && ip.octets()[3] >= 128)
+        );
+    }
 }
@@ -0,0 +1,116 @@
+use crate::args::LatencyUnit;
+use crate::export;
+use crate::{Ranking, TestResult};
+use anyhow::{Context, Result};
+use log::{debug, warn};
+use std::fmt::Write as _;
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::path::Path;
+use tokio::net::{UdpSocket, lookup_host};
+
+/// Append a gauge with its `HELP` and `TYPE` lines.
+fn gauge(out: &mut String, name: &str, help: &str, value: impl std::fmt::Display) {
+    let _ = writeln!(out, "# HELP {name} {help}");
+    let _ = writeln!(out, "# TYPE {name} gauge");
+    let _ = writeln!(out, "{name} {value}");
+}
+
+/// Write the scan metrics in the Prometheus text format, atomically replacing
+/// `path` so the node_exporter textfile collector never reads a partial file.
+/// Latencies measured in `unit` are written in milliseconds.
+pub(crate) fn write_prometheus(
+    path: &Path,
+    ranking: &Ranking,
+    top: &[TestResult],
+    unit: LatencyUnit,
+) -> Result<()> {
+    let mut out = String::new();
+
+    if let Some(best) = top.first() {
+        gauge(
+            &mut out,
+            "warp_speedtest_best_latency_ms",
+            "Latency of the fastest endpoint in milliseconds.",
+            unit.to_ms(best.latency as f64),
+        );
+    }
+    gauge(
+        &mut out,
+        "warp_speedtest_alive_count",
+        "Number of endpoints that responded.",
+        ranking.results.len(),
+    );
+    gauge(
+        &mut out,
+        "warp_speedtest_total_tested",
+        "Number of endpoints probed.",
+        ranking.tested,
+    );
+    gauge(
+        &mut out,
+        "warp_speedtest_scan_duration_seconds",
+        "Time spent probing endpoints.",
+        ranking.duration.as_secs_f64(),
+    );
+    gauge(
+        &mut out,
+        "warp_speedtest_last_run_timestamp_seconds",
+        "Unix time the scan finished.",
+        jiff::Timestamp::now().as_second(),
+    );
+
+    let name = "warp_speedtest_endpoint_latency_ms";
+    let _ = writeln!(
+        out,
+        "# HELP {name} Latency of the top endpoints in milliseconds."
+    );
+    let _ = writeln!(out, "# TYPE {name} gauge");
+    for result in top {
+        let _ = writeln!(
+            out,
+            "{name}{{addr=\"{}\"}} {}",
+            result.addr,
+            unit.to_ms(result.latency as f64)
+        );
+    }
+
+    export::write_atomic(path, &out)
+}
+
+/// Send the scan gauges to the StatsD agent at `addr`, named `prefix.name`.
+///
+/// Failures are only logged, an unreachable agent does not fail the scan.
+/// Latencies measured in `unit` are sent in milliseconds.
+pub(crate) async fn send_statsd(addr: &str, prefix: &str, ranking: &Ranking, unit: LatencyUnit) {
+    let mut out = String::new();
+    if let Some(best) = ranking.results.first() {
+        let best = unit.to_ms(best.latency as f64);
+        let _ = writeln!(out, "{prefix}.best_latency_ms:{best}|g");
+    }
+    let _ = writeln!(out, "{prefix}.alive_count:{}|g", ranking.results.len());
+    let _ = writeln!(
+        out,
+        "{prefix}.scan_duration_ms:{}|g",
+        ranking.duration.as_millis()
+    );
+
+    match send_datagram(addr, out.trim_end()).await {
+        Ok(()) => debug!("Sent metrics to StatsD at {addr}"),
+        Err(e) => warn!("Failed to send metrics to StatsD: {e:#}"),
+    }
+}
+
+async fn send_datagram(addr: &str, payload: &str) -> Result<()> {
+    let target = lookup_host(addr)
+        .await
+        .with_context(|| format!("Failed to resolve {addr}"))?
+        .next()
+        .with_context(|| format!("{addr} resolved to no address"))?;
+    let local: SocketAddr = match target {
+        SocketAddr::V4(_) => (Ipv4Addr::UNSPECIFIED, 0).into(),
+        SocketAddr::V6(_) => (Ipv6Addr::UNSPECIFIED, 0).into(),
+    };
+    let socket = UdpSocket::bind(local).await?;
+    socket.send_to(payload.as_bytes(), target).await?;
+    Ok(())
+}
@@ -0,0 +1,227 @@
+use crate::args::LatencyUnit;
+use crate::socket::{self, SocketOptions};
+use crate::{PROBE_TIMEOUT, ProbeError, TestResult, is_handshake_response};
+use log::debug;
+use nix::sys::socket::{MsgFlags, MultiHeaders, SockaddrStorage, recvmmsg};
+use std::collections::HashMap;
+use std::io::{self, IoSliceMut};
+use std::net::SocketAddr;
+use std::os::fd::{AsRawFd, RawFd};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+use tokio::io::Interest;
+use tokio::net::UdpSocket;
+use tokio::sync::oneshot;
+use tokio::task::JoinHandle;
+use tokio::time::timeout;
+
+/// Most responses drained by a single `recvmmsg` call.
+const BATCH: usize = 64;
+
+/// Receive buffer per message, larger than any valid response so oversized
+/// ones are recognized instead of truncated.
+const BUF_LEN: usize = 256;
+
+/// What the receiver saw from a probed address.
+enum Response {
+    Valid(Instant),
+    Invalid,
+}
+
+struct Shared {
+    socket: UdpSocket,
+    /// Sender index of the handshake initiation, echoed back as the
+    /// receiver index of every response to it
+    sender_index: [u8; 4],
+    pending: Mutex<HashMap<SocketAddr, oneshot::Sender<Response>>>,
+    /// From `--response-size-min` and `--response-size-max`
+    response_size: Option<(usize, usize)>,
+}
+
+/// Probes many addresses over one shared socket per IP version.
+///
+/// Responses are drained in batches by a background task and matched to
+/// their probe by source address. Sends stay one per syscall, each worker
+/// sends as soon as it starts.
+pub(crate) struct Prober {
+    v4: Option<Family>,
+    v6: Option<Family>,
+    latency_unit: LatencyUnit,
+}
+
+struct Family {
+    shared: Arc<Shared>,
+    receiver: JoinHandle<()>,
+}
+
+impl Drop for Family {
+    fn drop(&mut self) {
+        self.receiver.abort();
+    }
+}
+
+impl Prober {
+    /// Bind a shared socket for every IP version present in `addrs`.
+    pub(crate) fn bind(
+        addrs: &[SocketAddr],
+        source_port: Option<u16>,
+        options: &SocketOptions,
+        packet: &[u8],
+    ) -> io::Result<Self> {
+        let bind = |addr: &SocketAddr| Family::bind(addr, source_port, options, packet);
+        Ok(Prober {
+            v4: addrs.iter().find(|a| a.is_ipv4()).map(bind).transpose()?,
+            v6: addrs.iter().find(|a| a.is_ipv6()).map(bind).transpose()?,
+            latency_unit: options.latency_unit,
+        })
+    }
+
+    /// Send `packet` to `addr` and wait for its response.
+    pub(crate) async fn probe(
+        &self,
+        addr: &SocketAddr,
+        packet: &[u8],
+    ) -> Result<TestResult, ProbeError> {
+        let family = match addr {
+            SocketAddr::V4(_) => &self.v4,
+            SocketAddr::V6(_) => &self.v6,
+        };
+        let shared = &family
+            .as_ref()
+            .expect("a socket is bound for every IP version probed")
+            .shared;
+
+        let (sender, response) = oneshot::channel();
+        shared.pending.lock().unwrap().insert(*addr, sender);
+
+        let start = Instant::now();
+        if let Err(e) = shared.socket.send_to(packet, addr).await {
+            shared.pending.lock().unwrap().remove(addr);
+            return Err(ProbeError::SendError(e));
+        }
+        debug!("Pinging {addr}");
+
+        let outcome = timeout(PROBE_TIMEOUT, response).await;
+        shared.pending.lock().unwrap().remove(addr);
+
+        match outcome {
+            Ok(Ok(Response::Valid(received))) => {
+                let elapsed = self.latency_unit.of(received.duration_since(start));
+                debug!(
+                    addr:% = addr,
+                    latency = elapsed;
+                    "Received response from {addr} in {elapsed} {}",
+                    self.latency_unit.label()
+                );
+                Ok(TestResult::from_samples(*addr, vec![elapsed], 1))
+            }
+            Ok(Ok(Response::Invalid)) => Err(ProbeError::InvalidResponse),
+            // Timed out, or the receiver is gone
+            Ok(Err(_)) | Err(_) => {
+                debug!("Timeout from {addr}");
+                Err(ProbeError::Timeout)
+            }
+        }
+    }
+}
+
+impl Family {
+    fn bind(
+        addr: &SocketAddr,
+        source_port: Option<u16>,
+        options: &SocketOptions,
+        packet: &[u8],
+    ) -> io::Result<Self> {
+        let shared = Arc::new(Shared {
+            // Shared by every address, responses are matched by source instead
+            socket: socket::bind(
+                addr,
+                source_port.unwrap_or(0),
+                &SocketOptions {
+                    connect: false,
+                    ..options.clone()
+                },
+            )?,
+            sender_index: packet[4..8].try_into().expect("packet has a sender index"),
+            pending: Mutex::new(HashMap::new()),
+            response_size: options.response_size,
+        });
+        let receiver = tokio::spawn(receive(Arc::clone(&shared)));
+        Ok(Family { shared, receiver })
+    }
+}
+
+/// Hand every response to the probe waiting for its source address.
+async fn receive(shared: Arc<Shared>) {
+    let mut buffers = vec![[0u8; BUF_LEN]; BATCH];
+    let fd = shared.socket.as_raw_fd();
+
+    loop {
+        if shared.socket.readable().await.is_err() {
+            return;
+        }
+        let received = shared
+            .socket
+            .try_io(Interest::READABLE, || recv_batch(fd, &mut buffers));
+        let now = Instant::now();
+
+        let messages = match received {
+            Ok(messages) => messages,
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => continue,
+            Err(e) => {
+                debug!("recvmmsg failed: {e}");
+                continue;
+            }
+        };
+        debug!("Drained {} responses in one recvmmsg call", messages.len());
+
+        let mut pending = shared.pending.lock().unwrap();
+        for (i, src, len) in messages {
+            let buf = &buffers[i][..len];
+            let Some(sender) = pending.remove(&src) else {
+                debug!("Unexpected {len} byte response from {src}");
+                continue;
+            };
+            let valid = is_handshake_response(buf, shared.response_size)
+                && buf.get(8..12) == Some(&shared.sender_index[..]);
+            if !valid {
+                debug!("Invalid {len} byte response from {src}");
+            }
+            let _ = sender.send(if valid {
+                Response::Valid(now)
+            } else {
+                Response::Invalid
+            });
+        }
+    }
+}
+
+/// Receive up to [`BATCH`] datagrams into `buffers`, returning the buffer
+/// index, source and length of each one.
+///
+/// The headers hold raw pointers and cannot live across an `.await`, so they
+/// are allocated per call.
+fn recv_batch(
+    fd: RawFd,
+    buffers: &mut [[u8; BUF_LEN]],
+) -> io::Result<Vec<(usize, SocketAddr, usize)>> {
+    let mut headers = MultiHeaders::<SockaddrStorage>::preallocate(buffers.len(), None);
+    let mut slices: Vec<[IoSliceMut; 1]> = buffers
+        .iter_mut()
+        .map(|buf| [IoSliceMut::new(buf)])
+        .collect();
+    let results = recvmmsg(fd, &mut headers, &mut slices, MsgFlags::MSG_DONTWAIT, None)?;
+
+    Ok(results
+        .enumerate()
+        .filter_map(|(i, msg)| {
+            let addr = msg.address?;
+            let src = match (addr.as_sockaddr_in(), addr.as_sockaddr_in6()) {
+                (Some(v4), _) => SocketAddr::V4((*v4).into()),
+                (_, Some(v6)) => SocketAddr::V6((*v6).into()),
+                _ => return None,
+            };
+            Some((i, src, msg.bytes))
+        })
+        .collect())
+}
@@ -0,0 +1,121 @@
+use crate::socket::SocketOptions;
+use crate::speedtest;
+use futures::{StreamExt, stream};
+use log::{debug, info, warn};
+use std::net::SocketAddr;
+use std::time::Duration;
+use tokio::time::MissedTickBehavior;
+
+/// Consecutive failed pings after which an endpoint is reported unreachable.
+const FAILURES_BEFORE_ALERT: u32 = 3;
+
+struct Endpoint {
+    addr: SocketAddr,
+    failures: u32,
+    down: bool,
+}
+
+/// Ping every address of `addrs` once per `interval` until Ctrl-C, alerting
+/// when one stops responding and when it recovers.
+///
+/// A change of the fastest endpoint is reported as a flap when its latency
+/// differs from the previous fastest one by more than `flap_threshold_ms`.
+/// With a `source_port` the endpoints are pinged one at a time from it.
+pub(crate) async fn run(
+    addrs: &[SocketAddr],
+    interval: Duration,
+    flap_threshold_ms: u128,
+    source_port: Option<u16>,
+    options: &SocketOptions,
+) {
+    if addrs.is_empty() {
+        warn!("No working IPs found, nothing to monitor");
+        return;
+    }
+    let flap_threshold = options.latency_unit.of_ms(flap_threshold_ms);
+    let unit = options.latency_unit.label();
+
+    let mut endpoints: Vec<Endpoint> = addrs
+        .iter()
+        .map(|&addr| Endpoint {
+            addr,
+            failures: 0,
+            down: false,
+        })
+        .collect();
+    info!(
+        "Monitoring {} IPs every {} s, press Ctrl-C to stop",
+        endpoints.len(),
+        interval.as_secs()
+    );
+
+    // Fastest endpoint of the previous interval and its latency
+    let mut best: Option<(SocketAddr, u128)> = None;
+    let mut flaps = 0;
+    let mut ticks = tokio::time::interval(interval);
+    ticks.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    // The scan just probed every address, skip the immediate first tick
+    ticks.tick().await;
+    loop {
+        tokio::select! {
+            _ = ticks.tick() => {}
+            _ = tokio::signal::ctrl_c() => {
+                info!("Stopping monitoring, the best endpoint flapped {flaps} times");
+                return;
+            }
+        }
+
+        let concurrency = if source_port.is_some() {
+            1
+        } else {
+            endpoints.len()
+        };
+        let pings: Vec<_> = stream::iter(
+            endpoints
+                .iter()
+                .map(|endpoint| speedtest(&endpoint.addr, source_port.unwrap_or(0), options)),
+        )
+        .buffered(concurrency)
+        .collect()
+        .await;
+        let mut fastest: Option<(SocketAddr, u128)> = None;
+        for (endpoint, ping) in endpoints.iter_mut().zip(pings) {
+            match ping {
+                Ok(result) => {
+                    if fastest.is_none_or(|(_, latency)| result.latency < latency) {
+                        fastest = Some((endpoint.addr, result.latency));
+                    }
+                    debug!("{} - {} {unit}", endpoint.addr, result.latency);
+                    if endpoint.down {
+                        info!(
+                            "[RECOVERED] {} is reachable again at {} {unit}",
+                            endpoint.addr, result.latency
+                        );
+                    }
+                    endpoint.failures = 0;
+                    endpoint.down = false;
+                }
+                Err(e) => {
+                    debug!("{} - {e:?}", endpoint.addr);
+                    endpoint.failures += 1;
+                    if endpoint.failures == FAILURES_BEFORE_ALERT {
+                        warn!("[ALERT] {} has become unreachable", endpoint.addr);
+                        endpoint.down = true;
+                    }
+                }
+            }
+        }
+
+        if let (Some((from, before)), Some((to, after))) = (best, fastest)
+            && from != to
+            && after.abs_diff(before) > flap_threshold
+        {
+            let delta = after as i128 - before as i128;
+            warn!("[FLAP] Best endpoint changed from {from} to {to} (delta: {delta:+}{unit})");
+            flaps += 1;
+        }
+        if fastest.is_some() {
+            best = fastest;
+        }
+    }
+}
@@ -0,0 +1,99 @@
+use log::{debug, info, warn};
+use std::net::Ipv4Addr;
+
+/// Smallest MTU WireGuard supports.
+pub(crate) const WIREGUARD_MIN_MTU: u32 = 1280;
+
+/// Address whose route decides the outgoing interface.
+const ROUTE_TARGET: Ipv4Addr = Ipv4Addr::new(162, 159, 192, 1);
+
+/// The interface of the most specific route to `target` in
+/// `/proc/net/route`, whose addresses are little-endian hex.
+#[cfg(target_os = "linux")]
+fn route_interface(target: Ipv4Addr) -> Option<String> {
+    let routes = std::fs::read_to_string("/proc/net/route").ok()?;
+    let target = u32::from(target).swap_bytes();
+    routes
+        .lines()
+        .skip(1)
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let destination = u32::from_str_radix(fields.get(1)?, 16).ok()?;
+            let mask = u32::from_str_radix(fields.get(7)?, 16).ok()?;
+            (target & mask == destination).then(|| (mask.count_ones(), fields[0].to_string()))
+        })
+        .max_by_key(|(prefix, _)| *prefix)
+        .map(|(_, interface)| interface)
+}
+
+#[cfg(target_os = "linux")]
+fn interface_mtu(interface: &str) -> Option<u32> {
+    std::fs::read_to_string(format!("/sys/class/net/{interface}/mtu"))
+        .ok()?
+        .trim()
+        .parse()
+        .ok()
+}
+
+/// The `interface: en0` line of `route -n get`.
+#[cfg(target_os = "macos")]
+fn route_interface(target: Ipv4Addr) -> Option<String> {
+    let output = std::process::Command::new("route")
+        .args(["-n", "get", &target.to_string()])
+        .output()
+        .ok()?;
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .find_map(|line| line.trim().strip_prefix("interface: "))
+        .map(str::to_string)
+}
+
+/// The `mtu 1500` of `ifconfig <interface>`.
+#[cfg(target_os = "macos")]
+fn interface_mtu(interface: &str) -> Option<u32> {
+    let output = std::process::Command::new("ifconfig")
+        .arg(interface)
+        .output()
+        .ok()?;
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let mut words = stdout.split_whitespace();
+    words.find(|&word| word == "mtu")?;
+    words.next()?.parse().ok()
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+fn route_interface(_target: Ipv4Addr) -> Option<String> {
+    None
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+fn interface_mtu(_interface: &str) -> Option<u32> {
+    None
+}
+
+/// Warn if the interface probes leave through, `bind_device` or the one
+/// routing to Cloudflare, has an MTU too small for WireGuard.
+pub(crate) fn check(bind_device: Option<&str>) {
+    if !cfg!(any(target_os = "linux", target_os = "macos")) {
+        debug!("--mtu-check is not supported on this platform");
+        return;
+    }
+
+    let Some(interface) = bind_device
+        .map(str::to_string)
+        .or_else(|| route_interface(ROUTE_TARGET))
+    else {
+        warn!("Could not determine the interface routing to {ROUTE_TARGET}");
+        return;
+    };
+    let Some(mtu) = interface_mtu(&interface) else {
+        warn!("Could not determine the MTU of {interface}");
+        return;
+    };
+
+    if mtu < WIREGUARD_MIN_MTU {
+        warn!("{interface} has an MTU of {mtu}, below the {WIREGUARD_MIN_MTU} WireGuard needs");
+    } else {
+        info!("{interface} has an MTU of {mtu}");
+    }
+}
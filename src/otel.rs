@@ -0,0 +1,82 @@
+use crate::{ProbeError, Status, TestResult};
+use anyhow::{Context, Result};
+use log::warn;
+use opentelemetry::trace::TracerProvider;
+use opentelemetry_otlp::{SpanExporter, WithExportConfig};
+use opentelemetry_sdk::Resource;
+use opentelemetry_sdk::trace::SdkTracerProvider;
+use std::net::SocketAddr;
+use std::time::Duration;
+use tracing::Span;
+use tracing::field::Empty;
+use tracing_subscriber::layer::SubscriberExt;
+
+/// Exports spans while alive, flushes them when dropped.
+pub(crate) struct Exporter {
+    provider: SdkTracerProvider,
+    flush_timeout: Duration,
+}
+
+impl Drop for Exporter {
+    fn drop(&mut self) {
+        if let Err(e) = self.provider.shutdown_with_timeout(self.flush_timeout) {
+            warn!("Failed to flush traces: {e}");
+        }
+    }
+}
+
+/// Send every span to the OTLP/HTTP collector at `endpoint`.
+pub(crate) fn init(endpoint: &str, flush_timeout: Duration) -> Result<Exporter> {
+    let exporter = SpanExporter::builder()
+        .with_http()
+        .with_endpoint(endpoint)
+        .build()
+        .context("Failed to set up the OTLP exporter")?;
+    let provider = SdkTracerProvider::builder()
+        .with_batch_exporter(exporter)
+        .with_resource(
+            Resource::builder()
+                .with_service_name(env!("CARGO_PKG_NAME"))
+                .build(),
+        )
+        .build();
+
+    let layer = tracing_opentelemetry::layer().with_tracer(provider.tracer(env!("CARGO_PKG_NAME")));
+    tracing::subscriber::set_global_default(tracing_subscriber::registry().with(layer))
+        .context("Failed to install the tracing subscriber")?;
+
+    Ok(Exporter {
+        provider,
+        flush_timeout,
+    })
+}
+
+/// The span wrapping every probe of a scan.
+pub(crate) fn scan_span(addresses: usize) -> Span {
+    tracing::info_span!("warp.scan", warp.addresses = addresses)
+}
+
+/// A span for one probe to `addr`, ending when dropped. Fill it in with
+/// [`record`].
+pub(crate) fn probe_span(parent: &Span, addr: &SocketAddr) -> Span {
+    tracing::info_span!(
+        parent: parent,
+        "warp.probe",
+        net.peer.ip = %addr.ip(),
+        net.peer.port = addr.port(),
+        warp.latency_ms = Empty,
+        warp.status = Empty,
+    )
+}
+
+/// Attach the outcome of a probe to its span.
+pub(crate) fn record(span: &Span, probe: &Result<TestResult, ProbeError>) {
+    let status = match probe {
+        Ok(result) => {
+            span.record("warp.latency_ms", result.latency as u64);
+            Status::Alive
+        }
+        Err(e) => Status::from(e),
+    };
+    span.record("warp.status", status.as_str());
+}
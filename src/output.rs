@@ -0,0 +1,889 @@
+use crate::args::{LatencyUnit, OutputFormat};
+use crate::dualstack::Comparison;
+use crate::export;
+use crate::histogram::{self, Bucket};
+use crate::mtu;
+use crate::ports::{IpPorts, PortSummary};
+use crate::rounds::StableResult;
+use crate::subnet::{Productivity, SubnetStats};
+use crate::{DeadAddr, ErrorCounts, ProbeError, Status, TestResult};
+use anyhow::{Context, Result};
+use futures::future;
+use ipnetwork::IpNetwork;
+use log::{debug, info, warn};
+use schemars::JsonSchema;
+use serde::{Serialize, Serializer};
+use serde_json::Value;
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::fs::{File, OpenOptions};
+use std::io::{BufWriter, Write as _};
+use std::mem;
+use std::net::{IpAddr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// Version of the single scan JSON layout, in the `$id` of its schema. Bumped
+/// whenever a field is removed or changes meaning.
+const JSON_SCHEMA_VERSION: u32 = 2;
+
+/// Keys of the JSON documents holding latencies in `--latency-unit`, as named
+/// for milliseconds. The other `_ms` keys are always in milliseconds.
+const LATENCY_KEYS: &[&str] = &[
+    "latency_ms",
+    "jitter_ms",
+    "ping_variance_ms",
+    "min_ms",
+    "max_ms",
+    "p50_ms",
+    "p90_ms",
+    "p99_ms",
+    "range_ms",
+    "mean_ms",
+    "median_ms",
+    "std_dev_ms",
+    "v4_latency_ms",
+    "v6_latency_ms",
+    "delta_ms",
+    "best_ms",
+];
+
+#[derive(Serialize)]
+struct StableReport<'a> {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    seed: Option<u64>,
+    latency_unit: LatencyUnit,
+    rounds: usize,
+    stable: usize,
+    results: &'a [StableResult],
+    #[serde(skip_serializing_if = "<[IpNetwork]>::is_empty")]
+    unreachable_subnets: &'a [IpNetwork],
+}
+
+/// Everything reported about a single scan.
+#[derive(Serialize, JsonSchema)]
+pub(crate) struct Report<'a> {
+    /// From `--tag`
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub(crate) tags: BTreeMap<String, String>,
+    /// The `--seed` addresses were sampled with
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) seed: Option<u64>,
+    pub(crate) alive: usize,
+    pub(crate) total: usize,
+    /// Time spent probing, without address generation
+    pub(crate) scan_duration_ms: u128,
+    pub(crate) probes_per_second: f64,
+    /// Every result is from a single probe, from `--fast-mode`
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub(crate) fast_mode: bool,
+    /// Results are ranked by jitter, from `--jitter-only`
+    #[serde(skip)]
+    pub(crate) jitter_only: bool,
+    pub(crate) latency_unit: LatencyUnit,
+    #[serde(serialize_with = "serialize_alive")]
+    #[schemars(with = "Vec<AliveResult<'static>>")]
+    pub(crate) results: &'a [TestResult],
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) dead: Option<&'a [DeadAddr]>,
+    /// Failed probes by reason
+    pub(crate) errors: ErrorCounts,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) latency_histogram: Option<Vec<Bucket>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) subnet_stats: Option<Vec<SubnetStats>>,
+    /// Most and least productive subnets
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) productivity: Option<Productivity>,
+    /// Subnets where no tested address responded
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    #[schemars(with = "Vec<String>")]
+    pub(crate) unreachable_subnets: Vec<IpNetwork>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) best_ports: Option<Vec<IpPorts>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) port_summary: Option<PortSummary>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) comparison: Option<Vec<Comparison>>,
+}
+
+impl Report<'_> {
+    /// Log the scan summary and the fastest `top` results.
+    pub(crate) fn print_text(&self, top: usize) {
+        info!(
+            alive = self.alive,
+            total = self.total,
+            scan_duration_ms = self.scan_duration_ms,
+            probes_per_second = self.probes_per_second;
+            "Found {} working IPs out of {} IPs in {:.1}s ({:.0} probes/sec)",
+            self.alive,
+            self.total,
+            self.scan_duration_ms as f64 / 1000.0,
+            self.probes_per_second
+        );
+        if self.errors.total() > 0 {
+            let errors = &self.errors;
+            info!(
+                "Timeouts: {}  Send errors: {}  Recv errors: {}  Invalid responses: {}",
+                errors.timeouts, errors.send_errors, errors.recv_errors, errors.invalid_responses
+            );
+            // The kinds the line above leaves out, only when any happened
+            if errors.icmp_unreachable + errors.system_errors + errors.skipped > 0 {
+                info!(
+                    "ICMP unreachable: {}  System errors: {}  Skipped: {}",
+                    errors.icmp_unreachable, errors.system_errors, errors.skipped
+                );
+            }
+        }
+
+        if let Some(productivity) = &self.productivity {
+            for (label, count) in [("Most", productivity.most), ("Least", productivity.least)] {
+                info!(
+                    "{label} productive: {} ({}/{} alive)",
+                    count.subnet, count.alive, count.tested
+                );
+            }
+        }
+
+        info!("Top {top} IPs with lowest latency:");
+        let unit = self.latency_unit.label();
+        let verbose = log::log_enabled!(log::Level::Debug);
+        for result in self.results.iter().take(top) {
+            let ci = match result.ci {
+                Some(ci) if verbose || result.stats.is_some() => {
+                    format!(" [{:.0}, {:.0}]", ci.ci_low, ci.ci_high)
+                }
+                _ => String::new(),
+            };
+            let stats = match result.stats {
+                Some(stats) => format!(
+                    " (min {:.0}, p50 {:.0}, p90 {:.0}, p99 {:.0}, max {:.0}, jitter {:.1})",
+                    stats.min_ms,
+                    stats.p50_ms,
+                    stats.p90_ms,
+                    stats.p99_ms,
+                    stats.max_ms,
+                    result.jitter_ms
+                ),
+                None => String::new(),
+            };
+            let rank = if verbose {
+                format!(
+                    " #{} (top {:.0}%)",
+                    result.rank,
+                    100.0 - result.percentile_rank
+                )
+            } else {
+                String::new()
+            };
+            let pop = if verbose {
+                format!(" (PoP {})", result.pop)
+            } else {
+                String::new()
+            };
+            let hostname = match &result.hostname {
+                Some(Some(hostname)) => format!(" ({hostname})"),
+                Some(None) => " (<no-rdns>)".to_string(),
+                None => String::new(),
+            };
+            let unstable = if result.unstable == Some(true) {
+                " [unstable]"
+            } else {
+                ""
+            };
+            let score = match result.score {
+                Some(score) if verbose => format!(" (score {score:.1})"),
+                _ => String::new(),
+            };
+            let ecmp = if verbose && result.ecmp_suspected == Some(true) {
+                " [ecmp suspected]"
+            } else {
+                ""
+            };
+            let hops = match result.hops {
+                Some(hops) => format!(" ({hops} hops)"),
+                None => String::new(),
+            };
+            let pmtu = match result.pmtu {
+                Some(pmtu) if u32::from(pmtu) < mtu::WIREGUARD_MIN_MTU => {
+                    format!(" (PMTU {pmtu}) [PMTU below {}]", mtu::WIREGUARD_MIN_MTU)
+                }
+                Some(pmtu) => format!(" (PMTU {pmtu})"),
+                None => String::new(),
+            };
+            let icmp = match result.ping_comparison {
+                Some(comparison) => format!(
+                    " (ICMP {:.0} {unit}, {})",
+                    self.latency_unit.of_ms_f64(comparison.icmp_rtt_ms),
+                    comparison.verdict()
+                ),
+                None => String::new(),
+            };
+            let single_probe = if self.fast_mode {
+                " [single-probe]"
+            } else {
+                ""
+            };
+            let primary = if self.jitter_only {
+                format!(
+                    "jitter {:.1} {unit} (latency {} {unit})",
+                    result.jitter_ms, result.latency
+                )
+            } else {
+                format!("{} {unit}", result.latency)
+            };
+            info!(
+                addr:% = result.addr,
+                latency_ms = result.latency;
+                "{} - {primary}{rank}{ci}{stats}{pop}{score}{hostname}{icmp}{hops}{pmtu}{unstable}{ecmp}{single_probe}",
+                result.addr
+            );
+        }
+
+        if let Some(dead) = self.dead {
+            info!("Dead/unreachable addresses:");
+            for addr in dead {
+                info!(
+                    addr:% = addr.addr,
+                    status = addr.status.as_str();
+                    "{} - {}",
+                    addr.addr,
+                    addr.status.as_str()
+                );
+            }
+        }
+
+        if let Some(subnets) = &self.subnet_stats {
+            info!("Subnet breakdown:");
+            for subnet in subnets {
+                info!(
+                    "{} - {}/{} alive, mean {:.1} {unit}, median {:.1} {unit}, std dev {:.1} {unit}",
+                    subnet.subnet,
+                    subnet.alive,
+                    subnet.tested,
+                    subnet.mean_ms,
+                    subnet.median_ms,
+                    subnet.std_dev_ms
+                );
+            }
+        }
+
+        print_unreachable(&self.unreachable_subnets);
+
+        if let Some(ips) = &self.best_ports {
+            info!("Best port per IP:");
+            for ip in ips {
+                match (ip.best_port, ip.latency_ms) {
+                    (Some(port), Some(latency)) => info!(
+                        "{} - port {} at {} {unit}, {}/{} ports alive",
+                        ip.ip, port, latency, ip.ports_alive, ip.ports_tested
+                    ),
+                    _ => info!("{} - no port responded", ip.ip),
+                }
+                for port in &ip.port_breakdown {
+                    debug!("    {} -> {} {unit}", port.port, port.latency_ms);
+                }
+            }
+        }
+
+        if let Some(summary) = &self.port_summary
+            && let (Some(best), Some(common)) = (summary.best_port, summary.most_often_fastest)
+        {
+            info!("Fastest port overall: {best}, most often fastest per IP: {common}");
+        }
+
+        if let Some(comparison) = &self.comparison {
+            info!("IPv4 vs IPv6:");
+            for pair in comparison {
+                info!(
+                    "{} {} {unit} / {} {} {unit} - {}",
+                    pair.v4,
+                    pair.v4_latency_ms,
+                    pair.v6,
+                    pair.v6_latency_ms,
+                    pair.verdict(self.latency_unit)
+                );
+            }
+        }
+
+        if let Some(buckets) = &self.latency_histogram {
+            info!("Latency distribution:");
+            for line in histogram::render(buckets, self.latency_unit) {
+                info!("{line}");
+            }
+        }
+    }
+
+    /// The report as a single JSON document, indented if `pretty`.
+    pub(crate) fn to_json(&self, pretty: bool) -> Result<String> {
+        json(self, self.latency_unit, pretty)
+    }
+
+    /// One row per working address, followed by the dead ones if included,
+    /// with fields separated by `sep`.
+    pub(crate) fn to_csv(&self, sep: char) -> String {
+        let mut csv = csv_header(sep, self.latency_unit, self.tags.keys());
+        let tags = self.tags.values().map(String::as_str);
+        for result in self.results {
+            let fields = [
+                result.addr.to_string(),
+                result.latency.to_string(),
+                format!("{:.1}", result.loss_pct),
+                format!("{:.1}", result.jitter_ms),
+                format!("{:.1}", result.ping_variance_ms),
+            ];
+            let fields = fields.iter().map(String::as_str);
+            push_csv_row(
+                &mut csv,
+                sep,
+                fields.chain([Status::Alive.as_str()]).chain(tags.clone()),
+            );
+        }
+        for addr in self.dead.unwrap_or_default() {
+            let fields = [
+                addr.addr.to_string(),
+                addr.latency_ms.map(|ms| ms.to_string()).unwrap_or_default(),
+            ];
+            let fields = fields.iter().map(String::as_str);
+            let empty = ["", "", ""];
+            push_csv_row(
+                &mut csv,
+                sep,
+                fields
+                    .chain(empty)
+                    .chain([addr.status.as_str()])
+                    .chain(tags.clone()),
+            );
+        }
+        csv
+    }
+
+    /// One `addr - latency ms` line for each of the fastest `top` addresses.
+    pub(crate) fn to_text(&self, top: usize) -> String {
+        let unit = self.latency_unit.label();
+        self.results
+            .iter()
+            .take(top)
+            .map(|result| format!("{} - {} {unit}\n", result.addr, result.latency))
+            .collect()
+    }
+}
+
+/// The header line of [`Report::to_csv`], with latencies in `unit` and a
+/// column per `--tag` key in `tags`.
+pub(crate) fn csv_header<'a>(
+    sep: char,
+    unit: LatencyUnit,
+    tags: impl IntoIterator<Item = &'a String>,
+) -> String {
+    let columns = [
+        "addr".to_string(),
+        unit.column("latency"),
+        "loss_pct".to_string(),
+        unit.column("jitter"),
+        unit.column("ping_variance"),
+        "status".to_string(),
+    ];
+    let columns: Vec<String> = columns
+        .into_iter()
+        .chain(tags.into_iter().cloned())
+        .collect();
+    let mut header = String::new();
+    push_csv_row(&mut header, sep, columns.iter().map(String::as_str));
+    header
+}
+
+/// Append `fields` to `csv` as one line separated by `sep`, each quoted by
+/// [`csv_field`] if needed.
+fn push_csv_row<'a>(csv: &mut String, sep: char, fields: impl IntoIterator<Item = &'a str>) {
+    for (i, field) in fields.into_iter().enumerate() {
+        if i > 0 {
+            csv.push(sep);
+        }
+        csv.push_str(&csv_field(field, sep));
+    }
+    csv.push('\n');
+}
+
+/// `field` as a CSV field between `sep`s, quoted with inner quotes doubled
+/// if it contains `sep`, a quote or a line break.
+fn csv_field(field: &str, sep: char) -> Cow<'_, str> {
+    if field.contains([sep, '"', '\n', '\r']) {
+        Cow::Owned(format!("\"{}\"", field.replace('"', "\"\"")))
+    } else {
+        Cow::Borrowed(field)
+    }
+}
+
+/// Log the subnets where no tested address responded, if there are any.
+pub(crate) fn print_unreachable(subnets: &[IpNetwork]) {
+    if subnets.is_empty() {
+        return;
+    }
+
+    info!("Unreachable subnets:");
+    for subnet in subnets {
+        info!(subnet:% = subnet; "{subnet} - no address responded");
+    }
+}
+
+/// Write rendered results to `path`, or to stdout without one.
+pub(crate) fn emit(contents: &str, path: Option<&Path>) -> Result<()> {
+    match path {
+        Some(path) => {
+            export::write_atomic(path, contents)?;
+            info!("Saved results to {}", path.display());
+        }
+        None => print!("{contents}"),
+    }
+    Ok(())
+}
+
+/// Append `contents` of `format` to `path`, leaving out the CSV header if
+/// the file already has one and preceding text with the time of the run.
+pub(crate) fn append(contents: &str, path: &Path, format: Option<OutputFormat>) -> Result<()> {
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .with_context(|| format!("Failed to open {}", path.display()))?;
+    let empty = file.metadata()?.len() == 0;
+
+    let contents = match format {
+        Some(OutputFormat::Csv | OutputFormat::Tsv) if !empty => contents
+            .split_once('\n')
+            .map_or("", |(_, rows)| rows)
+            .to_string(),
+        Some(OutputFormat::Text) => {
+            format!("=== Run at {} ===\n{contents}", jiff::Timestamp::now())
+        }
+        _ => contents.to_string(),
+    };
+    file.write_all(contents.as_bytes())
+        .with_context(|| format!("Failed to write {}", path.display()))?;
+    info!("Appended results to {}", path.display());
+    Ok(())
+}
+
+/// Write every `(path, contents)` pair at once, failing if any write fails.
+pub(crate) async fn emit_all(files: Vec<(PathBuf, String)>) -> Result<()> {
+    let writes = files.into_iter().map(|(path, contents)| {
+        tokio::task::spawn_blocking(move || {
+            export::write_atomic(&path, &contents)?;
+            info!("Saved results to {}", path.display());
+            Ok::<_, anyhow::Error>(())
+        })
+    });
+    for write in future::join_all(writes).await {
+        write??;
+    }
+    Ok(())
+}
+
+/// Log the addresses aggregated over several rounds, fastest first, with
+/// latencies in `unit`.
+pub(crate) fn print_stable_text(
+    results: &[StableResult],
+    rounds: usize,
+    top: usize,
+    by_stability: bool,
+    unit: LatencyUnit,
+) {
+    let unit = unit.label();
+    info!(
+        stable = results.len(),
+        rounds = rounds;
+        "Found {} stable IPs across {} rounds",
+        results.len(),
+        rounds
+    );
+
+    if by_stability {
+        info!("Top {top} most stable IPs:");
+    } else {
+        info!("Top {top} IPs with lowest smoothed latency:");
+    }
+    for result in results.iter().take(top) {
+        info!(
+            addr:% = result.addr,
+            latency_ms = result.latency_ms,
+            cv = result.cv,
+            stability = result.stability;
+            "{} - {:.1} ± {:.1} {unit} (cv {:.2}, stability {:.2}, seen in {}/{} rounds)",
+            result.addr,
+            result.latency_ms,
+            result.std_dev_ms,
+            result.cv,
+            result.stability,
+            result.rounds_present,
+            rounds
+        );
+    }
+}
+
+/// JSON Schema of the single scan report of `--output-format json`, with
+/// latencies in `unit`.
+pub(crate) fn json_schema(unit: LatencyUnit) -> Result<String> {
+    let mut schema = schemars::schema_for!(Report<'static>);
+    schema.insert(
+        "$id".to_string(),
+        format!(
+            "urn:{}:report:v{JSON_SCHEMA_VERSION}",
+            env!("CARGO_PKG_NAME")
+        )
+        .into(),
+    );
+    json(&schema, unit, true)
+}
+
+/// `value` as JSON ending in a newline, on one line unless `pretty`, with the
+/// `LATENCY_KEYS` named for `unit`.
+pub(crate) fn json(value: &impl Serialize, unit: LatencyUnit, pretty: bool) -> Result<String> {
+    let mut value = serde_json::to_value(value)?;
+    if unit != LatencyUnit::Ms {
+        rename_latency_keys(&mut value, unit);
+    }
+    let json = if pretty {
+        serde_json::to_string_pretty(&value)?
+    } else {
+        serde_json::to_string(&value)?
+    };
+    Ok(json + "\n")
+}
+
+/// Rename the `LATENCY_KEYS` of every object in `value` for `unit`, including
+/// the property names listed as `required` by a schema.
+fn rename_latency_keys(value: &mut Value, unit: LatencyUnit) {
+    match value {
+        Value::Object(map) => {
+            *map = mem::take(map)
+                .into_iter()
+                .map(|(key, mut value)| {
+                    if key == "required"
+                        && let Value::Array(names) = &mut value
+                    {
+                        for name in names {
+                            if let Value::String(name) = name
+                                && let Some(renamed) = latency_key(name, unit)
+                            {
+                                *name = renamed;
+                            }
+                        }
+                    }
+                    rename_latency_keys(&mut value, unit);
+                    (latency_key(&key, unit).unwrap_or(key), value)
+                })
+                .collect();
+        }
+        Value::Array(values) => {
+            for value in values {
+                rename_latency_keys(value, unit);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// `key` named for `unit` if it is one of the `LATENCY_KEYS`.
+fn latency_key(key: &str, unit: LatencyUnit) -> Option<String> {
+    let name = key
+        .strip_suffix("_ms")
+        .filter(|_| LATENCY_KEYS.contains(&key))?;
+    Some(unit.column(name))
+}
+
+/// The addresses aggregated over several rounds as a JSON document with
+/// latencies in `unit`, indented if `pretty`.
+pub(crate) fn stable_json(
+    results: &[StableResult],
+    rounds: usize,
+    seed: Option<u64>,
+    unreachable_subnets: &[IpNetwork],
+    unit: LatencyUnit,
+    pretty: bool,
+) -> Result<String> {
+    let report = StableReport {
+        seed,
+        latency_unit: unit,
+        unreachable_subnets,
+        rounds,
+        stable: results.len(),
+        results,
+    };
+
+    json(&report, unit, pretty)
+}
+
+/// The addresses aggregated over several rounds as CSV, with latencies in
+/// `unit`.
+pub(crate) fn stable_csv(results: &[StableResult], sep: char, unit: LatencyUnit) -> String {
+    let mut csv = String::new();
+    let columns = [
+        "addr".to_string(),
+        unit.column("latency"),
+        unit.column("std_dev"),
+        "loss_pct".to_string(),
+        unit.column("jitter"),
+        "cv".to_string(),
+        "stability".to_string(),
+        "rounds_present".to_string(),
+    ];
+    push_csv_row(&mut csv, sep, columns.iter().map(String::as_str));
+    for result in results {
+        let fields = [
+            result.addr.to_string(),
+            format!("{:.1}", result.latency_ms),
+            format!("{:.1}", result.std_dev_ms),
+            format!("{:.1}", result.loss_pct),
+            format!("{:.1}", result.jitter_ms),
+            format!("{:.3}", result.cv),
+            format!("{:.3}", result.stability),
+            result.rounds_present.to_string(),
+        ];
+        push_csv_row(&mut csv, sep, fields.iter().map(String::as_str));
+    }
+    csv
+}
+
+/// One `ip:port<sep>latency` line per result ending in `end`, without
+/// brackets around IPv6 addresses. A literal `\t` separator stands for a tab.
+pub(crate) fn compact(results: &[TestResult], sep: &str, end: char) -> String {
+    let sep = if sep == "\\t" { "\t" } else { sep };
+    results
+        .iter()
+        .map(|result| {
+            format!(
+                "{}:{}{sep}{}{end}",
+                result.addr.ip(),
+                result.addr.port(),
+                result.latency
+            )
+        })
+        .collect()
+}
+
+/// One IP per result ending in `end`, IPv6 in brackets so a port can be
+/// appended.
+pub(crate) fn numeric(results: &[TestResult], end: char) -> String {
+    results
+        .iter()
+        .map(|result| match result.addr.ip() {
+            IpAddr::V4(ip) => format!("{ip}{end}"),
+            IpAddr::V6(ip) => format!("[{ip}]{end}"),
+        })
+        .collect()
+}
+
+/// InfluxDB line protocol with one point per result, all stamped now, with
+/// latencies in `unit`.
+pub(crate) fn influx(
+    results: &[TestResult],
+    measurement: &str,
+    tags: &[(String, String)],
+    unit: LatencyUnit,
+) -> String {
+    let latency = unit.column("latency");
+    let jitter = unit.column("jitter");
+    let timestamp = jiff::Timestamp::now().as_nanosecond();
+    let escape = |s: &str| {
+        s.replace(',', "\\,")
+            .replace('=', "\\=")
+            .replace(' ', "\\ ")
+    };
+    let global_tags: String = tags
+        .iter()
+        .map(|(key, value)| format!(",{}={}", escape(key), escape(value)))
+        .collect();
+
+    let mut lines = String::new();
+    for result in results {
+        let _ = writeln!(
+            lines,
+            "{}{global_tags},addr={},port={} {latency}={}i,loss_pct={},{jitter}={}i {timestamp}",
+            escape(measurement),
+            escape(&result.addr.ip().to_string()),
+            result.addr.port(),
+            result.latency,
+            result.loss_pct,
+            result.jitter_ms.round() as u64
+        );
+    }
+    lines
+}
+
+/// One `addr - latency ms` line for each of the fastest `top` aggregated
+/// addresses, with latencies in `unit`.
+pub(crate) fn stable_text(results: &[StableResult], top: usize, unit: LatencyUnit) -> String {
+    let unit = unit.label();
+    results
+        .iter()
+        .take(top)
+        .map(|result| format!("{} - {:.1} {unit}\n", result.addr, result.latency_ms))
+        .collect()
+}
+
+/// A working address with an explicit `"status": "alive"`, matching the shape
+/// of the dead entries.
+#[derive(Serialize, JsonSchema)]
+struct AliveResult<'a> {
+    #[serde(flatten)]
+    result: &'a TestResult,
+    status: Status,
+}
+
+fn serialize_alive<S: Serializer>(
+    results: &&[TestResult],
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.collect_seq(results.iter().map(|result| AliveResult {
+        result,
+        status: Status::Alive,
+    }))
+}
+
+/// Log how the top results moved in rank compared to the previous scan, with
+/// latencies in `unit`.
+pub(crate) fn print_rank_changes(
+    current: &[TestResult],
+    previous: &[TestResult],
+    top: usize,
+    unit: LatencyUnit,
+) {
+    let unit = unit.label();
+    info!("Rank changes since the previous scan:");
+    for (rank, result) in current.iter().take(top).enumerate() {
+        let change = match previous.iter().position(|prev| prev.addr == result.addr) {
+            Some(prev_rank) if prev_rank > rank => format!("▲{}", prev_rank - rank),
+            Some(prev_rank) if prev_rank < rank => format!("▼{}", rank - prev_rank),
+            Some(_) => "=".to_string(),
+            None => "new".to_string(),
+        };
+        info!(
+            "#{} {} - {} {unit} {}",
+            rank + 1,
+            result.addr,
+            result.latency,
+            change
+        );
+    }
+}
+
+/// The `--latency-log-file`, one CSV row per probe.
+pub(crate) struct ProbeLog {
+    path: PathBuf,
+    /// `None` once a write failed
+    writer: Mutex<Option<BufWriter<File>>>,
+}
+
+impl ProbeLog {
+    /// Create the log at `path` with its header, latencies being in `unit`.
+    pub(crate) fn create(path: &Path, unit: LatencyUnit) -> Result<Self> {
+        let file =
+            File::create(path).with_context(|| format!("Failed to create {}", path.display()))?;
+        let mut writer = BufWriter::new(file);
+        writeln!(
+            writer,
+            "addr,port,attempt,{},status",
+            unit.column("latency")
+        )
+        .with_context(|| format!("Failed to write {}", path.display()))?;
+        Ok(Self {
+            path: path.to_path_buf(),
+            writer: Mutex::new(Some(writer)),
+        })
+    }
+
+    /// Add the `attempt`th probe to `addr`, 1 being the first. The log stops
+    /// with a warning at the first failed write.
+    pub(crate) fn record(
+        &self,
+        addr: &SocketAddr,
+        attempt: u32,
+        probe: &Result<TestResult, ProbeError>,
+    ) {
+        let (latency, status) = match probe {
+            Ok(result) => (result.latency.to_string(), Status::Alive),
+            Err(e) => (String::new(), Status::from(e)),
+        };
+        let mut writer = self.writer.lock().unwrap();
+        if let Some(file) = writer.as_mut()
+            && let Err(e) = writeln!(
+                file,
+                "{},{},{attempt},{latency},{}",
+                addr.ip(),
+                addr.port(),
+                status.as_str()
+            )
+        {
+            warn!("Stopped writing {}: {e}", self.path.display());
+            *writer = None;
+        }
+    }
+
+    /// Write out the rows still buffered.
+    pub(crate) fn finish(&self) -> Result<()> {
+        match self.writer.lock().unwrap().as_mut() {
+            Some(file) => file
+                .flush()
+                .with_context(|| format!("Failed to write {}", self.path.display())),
+            None => Ok(()),
+        }
+    }
+}
+
+/// One line of `--progress-to-file`.
+#[derive(Serialize)]
+pub(crate) struct Progress {
+    /// Addresses done so far
+    pub(crate) pos: usize,
+    pub(crate) total: usize,
+    pub(crate) alive: usize,
+    pub(crate) best_ms: Option<u128>,
+    pub(crate) ts: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn csv_header_names_columns_by_unit() {
+        let tags = ["site;name".to_string()];
+        assert_eq!(
+            csv_header(';', LatencyUnit::Us, &tags),
+            "addr;latency_us;loss_pct;jitter_us;ping_variance_us;status;\"site;name\"\n"
+        );
+    }
+
+    #[test]
+    fn csv_rows_quote_fields_holding_the_separator() {
+        let mut csv = String::new();
+        push_csv_row(&mut csv, ':', ["[2606:4700::1]:2408", "12", "0.0"]);
+        push_csv_row(&mut csv, '.', ["162.159.192.1:2408", "12", "0.0"]);
+        assert_eq!(
+            csv,
+            "\"[2606:4700::1]:2408\":12:0.0\n\"162.159.192.1:2408\".12.\"0.0\"\n"
+        );
+    }
+
+    #[test]
+    fn json_names_latency_keys_by_unit() {
+        let progress = Progress {
+            pos: 1,
+            total: 2,
+            alive: 1,
+            best_ms: Some(1500),
+            ts: String::new(),
+        };
+        assert_eq!(
+            json(&progress, LatencyUnit::Us, false).unwrap(),
+            "{\"pos\":1,\"total\":2,\"alive\":1,\"best_us\":1500,\"ts\":\"\"}\n"
+        );
+
+        let schema = json_schema(LatencyUnit::Us).unwrap();
+        assert!(schema.contains("\"latency_us\"") && schema.contains("\"jitter_us\""));
+        assert!(!schema.contains("\"latency_ms\""));
+        assert!(schema.contains("\"scan_duration_ms\""));
+    }
+}
@@ -0,0 +1,66 @@
+use crate::socket::SocketOptions;
+use crate::{AddressPool, Args, generate_addrs, sample_rng, speedtest, stats};
+use futures::{StreamExt, stream};
+use log::{info, warn};
+
+/// Addresses probed by the pilot scan.
+const PILOT_ADDRESSES: usize = 5;
+
+/// Probes sent to every pilot address.
+const PILOT_PROBES: u32 = 5;
+
+/// z-score of a 95% confidence level.
+const Z_95: f64 = 1.96;
+
+/// Probe a few addresses and log how many `--attempts` pin their latency
+/// down to `±margin_ms` at 95% confidence. Returns the number of addresses
+/// probed, which the main scan leaves out of its budget.
+///
+/// With `--source-port` the addresses are probed one at a time from it.
+pub(crate) async fn recommend_attempts(
+    cli: &Args,
+    pool: &AddressPool,
+    options: &SocketOptions,
+    margin_ms: f64,
+) -> usize {
+    let addrs: Vec<_> = generate_addrs(cli, pool, &mut sample_rng(cli.seed))
+        .into_iter()
+        .take(PILOT_ADDRESSES)
+        .collect();
+    info!(
+        "Running a pilot scan of {} IPs with {PILOT_PROBES} probes each",
+        addrs.len()
+    );
+
+    let concurrency = if cli.source_port.is_some() {
+        1
+    } else {
+        PILOT_ADDRESSES
+    };
+    let variances: Vec<_> = stream::iter(addrs.iter().map(|addr| async move {
+        let mut latencies = Vec::new();
+        for _ in 0..PILOT_PROBES {
+            if let Ok(result) = speedtest(addr, cli.source_port.unwrap_or(0), options).await {
+                latencies.push(options.latency_unit.to_ms(result.latency as f64));
+            }
+        }
+        (latencies.len() >= 2).then(|| stats::std_dev(&latencies).powi(2))
+    }))
+    .buffered(concurrency)
+    .collect()
+    .await;
+    let variances: Vec<f64> = variances.into_iter().flatten().collect();
+
+    if variances.is_empty() {
+        warn!("Too few pilot probes succeeded to recommend --attempts");
+        return addrs.len();
+    }
+
+    // Every address has its own mean, so pool the variances around them
+    let std_dev = stats::mean(&variances).sqrt();
+    let attempts = ((Z_95 * std_dev / margin_ms).powi(2).ceil() as u32).max(1);
+    info!(
+        "Recommended --attempts {attempts} for ±{margin_ms} ms at 95% confidence (pilot std dev {std_dev:.1} ms)"
+    );
+    addrs.len()
+}
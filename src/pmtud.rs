@@ -0,0 +1,78 @@
+use crate::mtu::WIREGUARD_MIN_MTU;
+use crate::socket::{self, SocketOptions};
+use crate::{PROBE_TIMEOUT, TestResult, handshake_packet};
+use futures::future;
+use log::{debug, warn};
+use std::net::SocketAddr;
+use tokio::time::timeout;
+
+/// IP packet sizes tried, smallest first.
+const SIZES: [u16; 5] = [1200, 1300, 1400, 1450, 1480];
+
+/// Whether `addr` answers a handshake padded to an IP packet of `size`
+/// bytes.
+async fn answers(addr: &SocketAddr, size: u16, options: &SocketOptions) -> bool {
+    let socket = match socket::bind(addr, 0, options) {
+        Ok(socket) => socket,
+        Err(e) => {
+            debug!("Failed to bind a socket for {addr}: {e}");
+            return false;
+        }
+    };
+    #[cfg(target_os = "linux")]
+    if let Err(e) = socket::set_dont_fragment(&socket, addr.is_ipv6()) {
+        debug!("Failed to set Don't Fragment for {addr}: {e}");
+    }
+
+    let headers = if addr.is_ipv6() { 40 + 8 } else { 20 + 8 };
+    let mut packet = handshake_packet();
+    packet.resize(usize::from(size) - headers, 0);
+    // Fails right away if `size` exceeds an MTU the kernel already knows of
+    if let Err(e) = socket.send_to(&packet, addr).await {
+        debug!("Could not send {size} bytes to {addr}: {e}");
+        return false;
+    }
+
+    let mut buf = [0u8; 1500];
+    loop {
+        match timeout(PROBE_TIMEOUT, socket.recv_from(&mut buf)).await {
+            Ok(Ok((_, from))) if from == *addr => return true,
+            Ok(Ok(_)) => {}
+            Ok(Err(_)) | Err(_) => return false,
+        }
+    }
+}
+
+/// The largest of [`SIZES`] that gets through to `addr`.
+async fn path_mtu(addr: &SocketAddr, options: &SocketOptions) -> Option<u16> {
+    let mut largest = None;
+    for size in SIZES {
+        if !answers(addr, size, options).await {
+            break;
+        }
+        largest = Some(size);
+    }
+    largest
+}
+
+/// Probe the path MTU to every result with ever larger padded handshakes.
+///
+/// Packets are sent with the Don't Fragment bit on Linux only, elsewhere
+/// the OS may fragment them and the MTU found is only an upper bound.
+pub(crate) async fn discover(results: &mut [TestResult], options: &SocketOptions) {
+    let mtus = future::join_all(results.iter().map(|result| path_mtu(&result.addr, options))).await;
+    for (result, pmtu) in results.iter_mut().zip(mtus) {
+        match pmtu {
+            Some(pmtu) if u32::from(pmtu) < WIREGUARD_MIN_MTU => warn!(
+                "The path MTU to {} is {pmtu} bytes, below the {WIREGUARD_MIN_MTU} bytes WireGuard needs",
+                result.addr
+            ),
+            Some(_) => {}
+            None => warn!(
+                "{} did not answer even {} byte packets",
+                result.addr, SIZES[0]
+            ),
+        }
+        result.pmtu = pmtu;
+    }
+}
@@ -0,0 +1,239 @@
+use crate::TestResult;
+use futures::future;
+use schemars::JsonSchema;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::net::{IpAddr, SocketAddr};
+use tokio::sync::Semaphore;
+
+/// How one IP responded across every port it was probed on.
+#[derive(Debug, Serialize, JsonSchema)]
+pub(crate) struct IpPorts {
+    pub(crate) ip: IpAddr,
+    pub(crate) best_port: Option<u16>,
+    pub(crate) latency_ms: Option<u128>,
+    pub(crate) ports_alive: usize,
+    pub(crate) ports_tested: usize,
+    /// Every port that responded, fastest first
+    pub(crate) port_breakdown: Vec<PortLatency>,
+}
+
+#[derive(Debug, Serialize, JsonSchema)]
+pub(crate) struct PortLatency {
+    pub(crate) port: u16,
+    pub(crate) latency_ms: u128,
+}
+
+/// Which ports did best across all IPs.
+#[derive(Debug, Serialize, JsonSchema)]
+pub(crate) struct PortSummary {
+    /// Port of the single fastest result
+    pub(crate) best_port: Option<u16>,
+    /// Port that was the fastest of its IP most often
+    pub(crate) most_often_fastest: Option<u16>,
+}
+
+/// Pair every distinct IP of `addrs` with every port in `ports`.
+pub(crate) fn every_port(addrs: &[SocketAddr], ports: &[u16]) -> Vec<SocketAddr> {
+    let mut seen = HashSet::new();
+    addrs
+        .iter()
+        .map(SocketAddr::ip)
+        .filter(|ip| seen.insert(*ip))
+        .flat_map(|ip| ports.iter().map(move |&port| SocketAddr::new(ip, port)))
+        .collect()
+}
+
+/// The ports of every distinct IP of `addrs`, in order of first appearance.
+pub(crate) fn group_by_ip(addrs: Vec<SocketAddr>) -> Vec<(IpAddr, Vec<u16>)> {
+    let mut groups: Vec<(IpAddr, Vec<u16>)> = Vec::new();
+    let mut index: HashMap<IpAddr, usize> = HashMap::new();
+    for addr in addrs {
+        let i = *index.entry(addr.ip()).or_insert_with(|| {
+            groups.push((addr.ip(), Vec::new()));
+            groups.len() - 1
+        });
+        groups[i].1.push(addr.port());
+    }
+    groups
+}
+
+/// Probe `ip` on every one of `ports` with `probe`, at most `concurrency`
+/// ports at a time so a single IP never holds more sockets than that.
+///
+/// Returns once every port is done, in `ports` order.
+pub(crate) async fn probe_all_ports<F: Future>(
+    ip: IpAddr,
+    ports: Vec<u16>,
+    concurrency: usize,
+    probe: impl Fn(SocketAddr) -> F,
+) -> Vec<F::Output> {
+    let semaphore = Semaphore::new(concurrency);
+    future::join_all(ports.into_iter().map(|port| {
+        let probe = probe(SocketAddr::new(ip, port));
+        let semaphore = &semaphore;
+        async move {
+            let _permit = semaphore.acquire().await.expect("never closed");
+            probe.await
+        }
+    }))
+    .await
+}
+
+/// The fastest port of every tested IP, fastest IP first.
+///
+/// IPs where no port responded are listed last, without a best port.
+pub(crate) fn best_ports(tested: &[SocketAddr], alive: &[TestResult]) -> Vec<IpPorts> {
+    let mut ips: BTreeMap<IpAddr, IpPorts> = BTreeMap::new();
+    for addr in tested {
+        ips.entry(addr.ip())
+            .or_insert_with(|| IpPorts {
+                ip: addr.ip(),
+                best_port: None,
+                latency_ms: None,
+                ports_alive: 0,
+                ports_tested: 0,
+                port_breakdown: Vec::new(),
+            })
+            .ports_tested += 1;
+    }
+    for result in alive {
+        let Some(ip) = ips.get_mut(&result.addr.ip()) else {
+            continue;
+        };
+        ip.ports_alive += 1;
+        if ip.latency_ms.is_none_or(|best| result.latency < best) {
+            ip.best_port = Some(result.addr.port());
+            ip.latency_ms = Some(result.latency);
+        }
+        ip.port_breakdown.push(PortLatency {
+            port: result.addr.port(),
+            latency_ms: result.latency,
+        });
+    }
+
+    let mut ips: Vec<IpPorts> = ips.into_values().collect();
+    for ip in &mut ips {
+        ip.port_breakdown.sort_by_key(|port| port.latency_ms);
+    }
+    ips.sort_by_key(|ip| (ip.latency_ms.is_none(), ip.latency_ms));
+    ips
+}
+
+/// Summarize the best ports of `ips`, as returned by [`best_ports`].
+pub(crate) fn summary(ips: &[IpPorts]) -> PortSummary {
+    let mut fastest: BTreeMap<u16, usize> = BTreeMap::new();
+    for port in ips.iter().filter_map(|ip| ip.best_port) {
+        *fastest.entry(port).or_default() += 1;
+    }
+
+    PortSummary {
+        best_port: ips.first().and_then(|ip| ip.best_port),
+        // Ties go to the lowest port number
+        most_often_fastest: fastest
+            .into_iter()
+            .rev()
+            .max_by_key(|&(_, count)| count)
+            .map(|(port, _)| port),
+    }
+}
+
+/// Keep only the lowest latency port of every IP, whatever order `results`
+/// is sorted in. Ties go to the port listed first.
+pub(crate) fn retain_best_port(results: &mut Vec<TestResult>) {
+    let mut best: HashMap<IpAddr, &TestResult> = HashMap::new();
+    for result in results.iter() {
+        best.entry(result.addr.ip())
+            .and_modify(|best| {
+                if result.latency < best.latency {
+                    *best = result;
+                }
+            })
+            .or_insert(result);
+    }
+    let best: HashSet<SocketAddr> = best.into_values().map(|result| result.addr).collect();
+    results.retain(|result| best.contains(&result.addr));
+}
+
+/// Record in every result how many ports of its IP responded.
+pub(crate) fn count_ports_alive(results: &mut [TestResult]) {
+    let mut alive: HashMap<IpAddr, usize> = HashMap::new();
+    for result in results.iter() {
+        *alive.entry(result.addr.ip()).or_default() += 1;
+    }
+    for result in results {
+        result.ports_alive = Some(alive[&result.addr.ip()]);
+    }
+}
+
+/// Drop the results of IPs where fewer than `min` ports responded, as
+/// counted by [`count_ports_alive`].
+pub(crate) fn retain_min_ports_alive(results: &mut Vec<TestResult>, min: usize) {
+    results.retain(|result| result.ports_alive.is_some_and(|alive| alive >= min));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    fn result(addr: &str, latency: u128) -> TestResult {
+        TestResult::from_samples(addr.parse().unwrap(), vec![latency], 1)
+    }
+
+    #[test]
+    fn retain_best_port_keeps_the_lowest_latency() {
+        let mut results = vec![
+            result("10.0.0.1:500", 30),
+            result("10.0.0.2:500", 5),
+            result("10.0.0.1:2408", 10),
+            result("10.0.0.1:4500", 10),
+        ];
+        retain_best_port(&mut results);
+
+        let kept: Vec<SocketAddr> = results.iter().map(|result| result.addr).collect();
+        assert_eq!(
+            kept,
+            [
+                "10.0.0.2:500".parse().unwrap(),
+                "10.0.0.1:2408".parse().unwrap()
+            ]
+        );
+    }
+
+    #[test]
+    fn group_by_ip_keeps_first_appearance_order() {
+        let addrs = ["10.0.0.2:500", "10.0.0.1:500", "10.0.0.2:2408"]
+            .map(|addr| addr.parse().unwrap())
+            .to_vec();
+        let ip = |ip: &str| ip.parse::<IpAddr>().unwrap();
+        assert_eq!(
+            group_by_ip(addrs),
+            [
+                (ip("10.0.0.2"), vec![500, 2408]),
+                (ip("10.0.0.1"), vec![500])
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn probe_all_ports_limits_concurrency() {
+        let (running, peak) = (AtomicUsize::new(0), AtomicUsize::new(0));
+        let ports: Vec<u16> = (1..=10).collect();
+        let probed = probe_all_ports("10.0.0.1".parse().unwrap(), ports, 3, |addr| {
+            let (running, peak) = (&running, &peak);
+            async move {
+                let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+                peak.fetch_max(now, Ordering::SeqCst);
+                tokio::time::sleep(Duration::from_millis(5)).await;
+                running.fetch_sub(1, Ordering::SeqCst);
+                addr.port()
+            }
+        })
+        .await;
+
+        assert_eq!(probed, (1..=10).collect::<Vec<u16>>());
+        assert_eq!(peak.load(Ordering::SeqCst), 3);
+    }
+}
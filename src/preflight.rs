@@ -0,0 +1,99 @@
+use crate::socket::SocketOptions;
+use crate::speedtest;
+use anyhow::{Result, bail};
+use log::{debug, warn};
+use std::io::{self, BufRead, IsTerminal, Write};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::time::Duration;
+use tokio::net::UdpSocket;
+use tokio::time::timeout;
+
+/// Resolver queried to check that UDP traffic gets through at all.
+const PREFLIGHT_ADDR: SocketAddr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(1, 1, 1, 1)), 53);
+
+const PREFLIGHT_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// WARP endpoint probed by `--test-connectivity`.
+const WARP_ENDPOINT: SocketAddr =
+    SocketAddr::new(IpAddr::V4(Ipv4Addr::new(162, 159, 192, 1)), 2408);
+
+/// A DNS query for the root `NS` records.
+const DNS_QUERY: [u8; 17] = [
+    0x57, 0x41, // id
+    0x01, 0x00, // standard query, recursion desired
+    0x00, 0x01, // one question
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // no other records
+    0x00, // root name
+    0x00, 0x02, // type NS
+    0x00, 0x01, // class IN
+];
+
+/// Whether a DNS query to [`PREFLIGHT_ADDR`] gets any response.
+async fn udp_reachable() -> io::Result<bool> {
+    let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)).await?;
+    socket.send_to(&DNS_QUERY, PREFLIGHT_ADDR).await?;
+
+    let mut buf = [0u8; 512];
+    Ok(timeout(PREFLIGHT_TIMEOUT, socket.recv_from(&mut buf))
+        .await
+        .is_ok_and(|res| res.is_ok()))
+}
+
+/// Check that UDP traffic reaches the internet before scanning.
+///
+/// Without a response the user is asked whether to continue, or just warned
+/// when stdin is not a terminal.
+pub(crate) async fn check() -> Result<()> {
+    match udp_reachable().await {
+        Ok(true) => {
+            debug!("Preflight check to {PREFLIGHT_ADDR} succeeded");
+            return Ok(());
+        }
+        Ok(false) => warn!("No response from {PREFLIGHT_ADDR}, UDP traffic may be blocked"),
+        Err(e) => warn!("Could not reach {PREFLIGHT_ADDR}, the network may be down: {e}"),
+    }
+    confirm()
+}
+
+/// Check that a known WARP endpoint answers the handshake from
+/// `source_port`, with the probe socket `options`, before scanning. The
+/// probe waits the same fixed `PROBE_TIMEOUT` as the scan.
+///
+/// Without a response the user is asked whether to continue, like [`check`].
+pub(crate) async fn check_endpoint(
+    source_port: Option<u16>,
+    options: &SocketOptions,
+) -> Result<()> {
+    match speedtest(&WARP_ENDPOINT, source_port.unwrap_or(0), options).await {
+        Ok(result) => {
+            debug!(
+                "{WARP_ENDPOINT} answered in {} {}",
+                result.latency,
+                options.latency_unit.label()
+            );
+            return Ok(());
+        }
+        Err(e) => {
+            debug!("Probe to {WARP_ENDPOINT} failed: {e}");
+            warn!("Pre-flight check failed: unable to reach {WARP_ENDPOINT}");
+        }
+    }
+    confirm()
+}
+
+/// Ask whether to continue after a failed check. Continues without asking
+/// when stdin is not a terminal.
+fn confirm() -> Result<()> {
+    if !io::stdin().is_terminal() {
+        return Ok(());
+    }
+
+    eprint!("Continue anyway? [y/N] ");
+    io::stderr().flush()?;
+    let mut answer = String::new();
+    io::stdin().lock().read_line(&mut answer)?;
+    if !matches!(answer.trim(), "y" | "Y" | "yes") {
+        bail!("Aborted after the preflight check failed");
+    }
+    Ok(())
+}
@@ -0,0 +1,44 @@
+use crate::TestResult;
+use futures::StreamExt;
+use log::debug;
+use std::net::IpAddr;
+use std::time::Duration;
+
+/// Number of reverse lookups in flight at once.
+const CONCURRENCY: usize = 8;
+
+/// Timeout of a single reverse lookup.
+const LOOKUP_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// The PTR name of `ip`, `None` if it has none or the lookup failed.
+async fn lookup(ip: IpAddr) -> Option<String> {
+    // getnameinfo blocks, and a timed out lookup keeps its thread until it returns
+    let lookup = tokio::task::spawn_blocking(move || dns_lookup::lookup_addr(&ip));
+    match tokio::time::timeout(LOOKUP_TIMEOUT, lookup).await {
+        Ok(Ok(Ok(hostname))) => Some(hostname),
+        Ok(Ok(Err(e))) => {
+            debug!("Reverse lookup of {ip} failed: {e}");
+            None
+        }
+        Ok(Err(e)) => {
+            debug!("Reverse lookup of {ip} failed: {e}");
+            None
+        }
+        Err(_) => {
+            debug!("Reverse lookup of {ip} timed out");
+            None
+        }
+    }
+}
+
+/// Fill in the hostname of every result.
+pub(crate) async fn annotate(results: &mut [TestResult]) {
+    let hostnames: Vec<Option<String>> = futures::stream::iter(results.iter())
+        .map(|result| lookup(result.addr.ip()))
+        .buffered(CONCURRENCY)
+        .collect()
+        .await;
+    for (result, hostname) in results.iter_mut().zip(hostnames) {
+        result.hostname = Some(hostname);
+    }
+}
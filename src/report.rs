@@ -0,0 +1,201 @@
+use crate::args::LatencyUnit;
+use crate::{Ranking, TestResult};
+use anyhow::{Context, Result};
+use std::fmt::Write as _;
+use std::fs;
+use std::path::Path;
+
+/// Height of the bar area of the SVG chart.
+const CHART_HEIGHT: usize = 240;
+
+/// Horizontal space taken by each bar, including the gap to the next one.
+const BAR_SPACING: usize = 64;
+
+/// Space left of the bars for the latency axis labels.
+const CHART_MARGIN: usize = 48;
+
+/// Space below the bars for the address labels.
+const LABEL_HEIGHT: usize = 96;
+
+/// Escape the characters that are special in HTML text and attributes.
+fn escape_html(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// An SVG bar chart with one bar per result, scaled to the slowest one.
+fn svg_chart(results: &[TestResult], unit: LatencyUnit) -> String {
+    let unit = unit.label();
+    let max = results.iter().map(|r| r.latency).max().unwrap_or(0).max(1);
+    let width = CHART_MARGIN + BAR_SPACING * results.len().max(1);
+    let height = CHART_HEIGHT + LABEL_HEIGHT;
+
+    let mut svg = format!(
+        r#"<svg xmlns="http://www.w3.org/2000/svg" width="{width}" height="{height}" font-family="sans-serif" font-size="12">"#
+    );
+    let _ = write!(
+        svg,
+        r#"<line x1="{CHART_MARGIN}" y1="0" x2="{CHART_MARGIN}" y2="{CHART_HEIGHT}" stroke="black"/><text x="{x}" y="12" text-anchor="end">{max} {unit}</text><text x="{x}" y="{CHART_HEIGHT}" text-anchor="end">0 {unit}</text>"#,
+        x = CHART_MARGIN - 4
+    );
+
+    for (i, result) in results.iter().enumerate() {
+        let bar_height = (result.latency as usize * CHART_HEIGHT) / max as usize;
+        let x = CHART_MARGIN + BAR_SPACING * i + 8;
+        let y = CHART_HEIGHT - bar_height;
+        let label_x = x + (BAR_SPACING - 16) / 2;
+        let addr = escape_html(&result.addr.to_string());
+        let _ = write!(
+            svg,
+            r##"<rect x="{x}" y="{y}" width="{bar_width}" height="{bar_height}" fill="#f38020"><title>{addr}: {latency} {unit}</title></rect><text x="{label_x}" y="{value_y}" text-anchor="middle">{latency}</text><text transform="translate({label_x},{label_y}) rotate(-45)" text-anchor="end">{addr}</text>"##,
+            bar_width = BAR_SPACING - 16,
+            latency = result.latency,
+            value_y = y.saturating_sub(4).max(12),
+            label_y = CHART_HEIGHT + 16,
+        );
+    }
+
+    svg.push_str("</svg>");
+    svg
+}
+
+/// Write a self-contained HTML report with a chart of the `top` results and a
+/// sortable table of every alive address, with latencies in `unit`.
+pub(crate) fn write_html(
+    path: &Path,
+    ranking: &Ranking,
+    top: &[TestResult],
+    unit: LatencyUnit,
+) -> Result<()> {
+    let title = format!(
+        "WARP speedtest: {} of {} endpoints alive ({})",
+        ranking.results.len(),
+        ranking.tested,
+        jiff::Timestamp::now()
+    );
+
+    let mut rows = String::new();
+    for (rank, result) in ranking.results.iter().enumerate() {
+        let _ = write!(
+            rows,
+            "<tr><td>{}</td><td>{}</td><td>{}</td></tr>",
+            rank + 1,
+            escape_html(&result.addr.to_string()),
+            result.latency
+        );
+    }
+
+    let html = format!(
+        r##"<!DOCTYPE html>
+<html lang="en">
+<head>
+<meta charset="utf-8">
+<title>{title}</title>
+<style>
+body {{ font-family: sans-serif; margin: 2em; }}
+table {{ border-collapse: collapse; }}
+th, td {{ border: 1px solid #ccc; padding: 4px 12px; text-align: right; }}
+th {{ cursor: pointer; background: #eee; }}
+</style>
+</head>
+<body>
+<h1>{title}</h1>
+<p>Scan took {duration:.1} s. Generated by {name} {version}.</p>
+<h2>Top {top_len} endpoints</h2>
+{chart}
+<h2>All alive endpoints</h2>
+<table id="results">
+<thead><tr><th data-type="number">Rank</th><th data-type="addr">Address</th><th data-type="number">Latency ({label})</th></tr></thead>
+<tbody>{rows}</tbody>
+</table>
+<script>
+document.querySelectorAll("#results th").forEach((th, column) => {{
+  let ascending = true;
+  th.addEventListener("click", () => {{
+    const body = document.querySelector("#results tbody");
+    const numeric = th.dataset.type === "number";
+    const rows = Array.from(body.rows).sort((a, b) => {{
+      const x = a.cells[column].textContent, y = b.cells[column].textContent;
+      const order = numeric ? x - y : x.localeCompare(y, undefined, {{ numeric: true }});
+      return ascending ? order : -order;
+    }});
+    ascending = !ascending;
+    body.append(...rows);
+  }});
+}});
+</script>
+</body>
+</html>
+"##,
+        title = escape_html(&title),
+        duration = ranking.duration.as_secs_f64(),
+        name = env!("CARGO_PKG_NAME"),
+        version = env!("CARGO_PKG_VERSION"),
+        top_len = top.len(),
+        chart = svg_chart(top, unit),
+        label = unit.label(),
+    );
+
+    fs::write(path, html).with_context(|| format!("Failed to write {}", path.display()))
+}
+
+/// Escape the characters that have a meaning in Markdown inline text.
+fn escape_markdown(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        if matches!(
+            c,
+            '\\' | '`' | '*' | '_' | '[' | ']' | '<' | '>' | '|' | '#'
+        ) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// Write a GitHub-flavored Markdown table of the `top` results, with
+/// latencies in `unit`.
+pub(crate) fn write_markdown(
+    path: &Path,
+    ranking: &Ranking,
+    top: &[TestResult],
+    unit: LatencyUnit,
+) -> Result<()> {
+    let unit = unit.label();
+    let mut md = String::from("# WARP speedtest results\n\n");
+    let _ = writeln!(md, "- Generated: {}", jiff::Timestamp::now());
+    let _ = writeln!(md, "- Tested: {}", ranking.tested);
+    let _ = writeln!(md, "- Alive: {}", ranking.results.len());
+    let _ = writeln!(
+        md,
+        "- Scan duration: {:.1} s\n",
+        ranking.duration.as_secs_f64()
+    );
+
+    let _ = writeln!(md, "| Address | Avg {unit} | Loss% | Jitter {unit} |");
+    md.push_str("| --- | ---: | ---: | ---: |\n");
+    for result in top {
+        let _ = writeln!(
+            md,
+            "| {} | {} | {:.1} | {:.1} |",
+            escape_markdown(&result.addr.to_string()),
+            result.latency,
+            result.loss_pct,
+            result.jitter_ms
+        );
+    }
+
+    md.push_str("\n```text\n");
+    match top.first() {
+        Some(best) => {
+            let _ = writeln!(md, "Best endpoint: {} - {} {unit}", best.addr, best.latency);
+        }
+        None => md.push_str("No working endpoints found\n"),
+    }
+    md.push_str("```\n");
+
+    fs::write(path, md).with_context(|| format!("Failed to write {}", path.display()))
+}
@@ -0,0 +1,59 @@
+use crate::socket::SocketOptions;
+use crate::{ProbeError, ScanResults, Status, TestResult, speedtest};
+use futures::{StreamExt, stream};
+use log::debug;
+
+/// Probe every dead address of `results` whose every probe failed to send
+/// once more, `concurrency` at a time from `source_port`, and return those
+/// that answered.
+///
+/// Answering addresses are taken out of `results.dead`, their loss only
+/// counts the retest probe. The others stay dead, as system errors if the
+/// retest failed to send too. The retest probes are added to the probe and
+/// error counts.
+pub(crate) async fn send_errors(
+    results: &mut ScanResults,
+    concurrency: usize,
+    source_port: u16,
+    options: &SocketOptions,
+) -> Vec<TestResult> {
+    let (failed, dead) = std::mem::take(&mut results.dead)
+        .into_iter()
+        .partition(|dead| dead.status == Status::SystemError);
+    results.dead = dead;
+    if failed.is_empty() {
+        return Vec::new();
+    }
+    debug!("Retesting {} addresses that failed to send", failed.len());
+
+    let probes: Vec<_> = stream::iter(failed)
+        .map(|dead| async move {
+            let probe = speedtest(&dead.addr, source_port, options).await;
+            (dead, probe)
+        })
+        .buffer_unordered(concurrency)
+        .collect()
+        .await;
+
+    let mut alive = Vec::new();
+    for (mut dead, probe) in probes {
+        results.probes += 1;
+        match probe {
+            Ok(probe) => {
+                debug!("{} answered the retest", dead.addr);
+                results.errors.system_errors -= 1;
+                alive.push(TestResult::from_samples(dead.addr, vec![probe.latency], 1));
+            }
+            Err(e) => {
+                debug!("{} failed the retest: {e}", dead.addr);
+                results.errors.record(&e);
+                if !matches!(e, ProbeError::SendError(_)) {
+                    results.errors.system_errors -= 1;
+                    dead.status = Status::from(&e);
+                }
+                results.dead.push(dead);
+            }
+        }
+    }
+    alive
+}
@@ -0,0 +1,155 @@
+use crate::TestResult;
+use crate::stats;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
+
+/// An address aggregated over every scan round it was seen in.
+#[derive(Debug, Serialize)]
+pub(crate) struct StableResult {
+    pub(crate) addr: SocketAddr,
+    /// Per-round average latencies smoothed with `--ewma-alpha`
+    pub(crate) latency_ms: f64,
+    /// Standard deviation of the per-round averages
+    pub(crate) std_dev_ms: f64,
+    /// Mean of the per-round losses
+    pub(crate) loss_pct: f64,
+    /// Mean of the per-round jitters
+    pub(crate) jitter_ms: f64,
+    /// Coefficient of variation of the per-round averages
+    pub(crate) cv: f64,
+    /// `1 / cv`, higher is more consistent across rounds. Infinite, `null` in
+    /// JSON, when every round measured the same latency
+    pub(crate) stability: f64,
+    pub(crate) rounds_present: usize,
+}
+
+impl From<&StableResult> for TestResult {
+    fn from(result: &StableResult) -> Self {
+        let latency = result.latency_ms.round() as u128;
+        TestResult {
+            loss_pct: result.loss_pct,
+            jitter_ms: result.jitter_ms,
+            ..TestResult::from_samples(result.addr, vec![latency], 1)
+        }
+    }
+}
+
+/// Combine the results of several rounds into per-address statistics.
+///
+/// With `by_ip` an IP is one address whatever the ports it was probed on,
+/// counting its fastest port in each round and reported with the port of its
+/// fastest round. The rounds are
+/// smoothed in order with an EWMA of weight `alpha`, the output is sorted by
+/// the smoothed latency, fastest first.
+pub(crate) fn aggregate_rounds(
+    rounds: &[Vec<TestResult>],
+    alpha: f64,
+    by_ip: bool,
+) -> Vec<StableResult> {
+    let mut by_addr: BTreeMap<SocketAddr, Vec<&TestResult>> = BTreeMap::new();
+    for round in rounds {
+        let mut fastest: BTreeMap<SocketAddr, &TestResult> = BTreeMap::new();
+        for result in round {
+            let key = if by_ip {
+                SocketAddr::new(result.addr.ip(), 0)
+            } else {
+                result.addr
+            };
+            fastest
+                .entry(key)
+                .and_modify(|best| {
+                    if result.latency < best.latency {
+                        *best = result;
+                    }
+                })
+                .or_insert(result);
+        }
+        for (key, result) in fastest {
+            by_addr.entry(key).or_default().push(result);
+        }
+    }
+
+    let mut stable: Vec<StableResult> = by_addr
+        .into_values()
+        .map(|results| {
+            let fastest = results.iter().min_by_key(|res| res.latency);
+            let addr = fastest.expect("a result per address").addr;
+            let means: Vec<f64> = results.iter().map(|res| res.latency as f64).collect();
+            let losses: Vec<f64> = results.iter().map(|res| res.loss_pct).collect();
+            let jitters: Vec<f64> = results.iter().map(|res| res.jitter_ms).collect();
+            let cv = stats::coefficient_of_variation(&means);
+            StableResult {
+                addr,
+                latency_ms: stats::ewma(&means, alpha),
+                std_dev_ms: stats::std_dev(&means),
+                loss_pct: stats::mean(&losses),
+                jitter_ms: stats::mean(&jitters),
+                cv,
+                stability: if cv == 0.0 { f64::INFINITY } else { 1.0 / cv },
+                rounds_present: means.len(),
+            }
+        })
+        .collect();
+
+    stable.sort_by(|a, b| a.latency_ms.total_cmp(&b.latency_ms));
+    stable
+}
+
+/// Sort `results` most stable first, breaking ties by latency.
+pub(crate) fn sort_by_stability(results: &mut [StableResult]) {
+    results.sort_by(|a, b| {
+        b.stability
+            .total_cmp(&a.stability)
+            .then(a.latency_ms.total_cmp(&b.latency_ms))
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn result(addr: &str, latency: u128, loss_pct: f64) -> TestResult {
+        TestResult {
+            loss_pct,
+            ..TestResult::from_samples(addr.parse().unwrap(), vec![latency], 1)
+        }
+    }
+
+    #[test]
+    fn stability_is_the_inverse_cv_and_loss_carries_over() {
+        let rounds = [
+            vec![
+                result("10.0.0.1:2408", 10, 0.0),
+                result("10.0.0.2:2408", 20, 0.0),
+            ],
+            vec![
+                result("10.0.0.1:2408", 30, 50.0),
+                result("10.0.0.2:2408", 20, 0.0),
+            ],
+        ];
+        let stable = aggregate_rounds(&rounds, 0.5, false);
+
+        // Latencies 10 and 30: mean 20, std dev 10
+        assert_eq!(stable[0].addr, "10.0.0.1:2408".parse().unwrap());
+        assert_eq!(stable[0].cv, 0.5);
+        assert_eq!(stable[0].stability, 2.0);
+        assert_eq!(TestResult::from(&stable[0]).loss_pct, 25.0);
+        assert_eq!(stable[1].stability, f64::INFINITY);
+    }
+
+    #[test]
+    fn by_ip_merges_the_ports_of_an_ip() {
+        let rounds = [
+            vec![result("10.0.0.1:2408", 30, 0.0)],
+            vec![
+                result("10.0.0.1:500", 10, 0.0),
+                result("10.0.0.1:2408", 40, 0.0),
+            ],
+        ];
+        let stable = aggregate_rounds(&rounds, 0.5, true);
+        assert_eq!(stable.len(), 1);
+        assert_eq!(stable[0].addr, "10.0.0.1:500".parse().unwrap());
+        assert_eq!(stable[0].rounds_present, 2);
+    }
+}
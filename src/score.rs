@@ -0,0 +1,152 @@
+use crate::TestResult;
+use crate::args::LatencyUnit;
+use evalexpr::{
+    ContextWithMutableVariables, DefaultNumericTypes, HashMapContext, Node, Value,
+    build_operator_tree,
+};
+
+/// A `--score-formula` over the `latency`, `loss` and `jitter` of a result,
+/// latency and jitter being in milliseconds whatever the `--latency-unit`.
+#[derive(Clone, Debug)]
+pub(crate) struct Formula(Node<DefaultNumericTypes>);
+
+impl Formula {
+    /// The score of `result` measured in `unit`, lower is better.
+    fn eval(&self, result: &TestResult, unit: LatencyUnit) -> Result<f64, String> {
+        let context = variables(
+            unit.to_ms(result.latency as f64),
+            result.loss_pct,
+            unit.to_ms(result.jitter_ms),
+        )?;
+        self.0
+            .eval_number_with_context(&context)
+            .map_err(|e| e.to_string())
+    }
+}
+
+fn variables(
+    latency: f64,
+    loss: f64,
+    jitter: f64,
+) -> Result<HashMapContext<DefaultNumericTypes>, String> {
+    let mut context = HashMapContext::new();
+    for (name, value) in [("latency", latency), ("loss", loss), ("jitter", jitter)] {
+        context
+            .set_value(name.to_string(), Value::Float(value))
+            .map_err(|e| e.to_string())?;
+    }
+    Ok(context)
+}
+
+/// Parse a `--score-formula`, checking that it evaluates to a number.
+pub(crate) fn parse_formula(value: &str) -> Result<Formula, String> {
+    let formula = Formula(build_operator_tree(value).map_err(|e| e.to_string())?);
+    formula
+        .0
+        .eval_number_with_context(&variables(0.0, 0.0, 0.0)?)
+        .map_err(|e| format!("{e} The variables are latency, loss and jitter."))?;
+    Ok(formula)
+}
+
+/// Weights of the `--score` sum.
+pub(crate) struct Weights {
+    pub(crate) latency: f64,
+    pub(crate) loss: f64,
+    pub(crate) jitter: f64,
+}
+
+/// Score every result measured in `unit` with `formula` and sort them by
+/// it, lowest first. Results the formula fails on are ranked last.
+pub(crate) fn rank(results: &mut [TestResult], formula: &Formula, unit: LatencyUnit) {
+    rank_by(results, |result| {
+        formula.eval(result, unit).unwrap_or(f64::INFINITY)
+    });
+}
+
+/// Score every result measured in `unit` with the weighted sum of its
+/// latency and jitter in milliseconds and its loss, and sort them by it,
+/// lowest first.
+pub(crate) fn rank_weighted(results: &mut [TestResult], weights: &Weights, unit: LatencyUnit) {
+    rank_by(results, |result| {
+        unit.to_ms(result.latency as f64) * weights.latency
+            + result.loss_pct * weights.loss
+            + unit.to_ms(result.jitter_ms) * weights.jitter
+    });
+}
+
+fn rank_by(results: &mut [TestResult], score: impl Fn(&TestResult) -> f64) {
+    for result in results.iter_mut() {
+        result.score = Some(score(result));
+    }
+    results.sort_by(|a, b| {
+        a.score
+            .unwrap_or_default()
+            .total_cmp(&b.score.unwrap_or_default())
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A result answering `received` of `attempts` probes in `latency` each.
+    fn result(addr: &str, latency: u128, received: usize, attempts: u32) -> TestResult {
+        TestResult::from_samples(addr.parse().unwrap(), vec![latency; received], attempts)
+    }
+
+    #[test]
+    fn formula_ranks_by_its_value() {
+        let formula = parse_formula("latency + loss * 2").unwrap();
+        let mut results = vec![
+            result("10.0.0.1:2408", 10, 1, 2),
+            result("10.0.0.2:2408", 80, 2, 2),
+        ];
+        rank(&mut results, &formula, LatencyUnit::Ms);
+
+        // 10 + 50% loss * 2 = 110 ranks behind 80 + 0
+        let ranked: Vec<_> = results.iter().map(|r| (r.addr, r.score)).collect();
+        assert_eq!(
+            ranked,
+            [
+                ("10.0.0.2:2408".parse().unwrap(), Some(80.0)),
+                ("10.0.0.1:2408".parse().unwrap(), Some(110.0)),
+            ]
+        );
+    }
+
+    #[test]
+    fn formula_latency_is_in_milliseconds() {
+        let formula = parse_formula("latency").unwrap();
+        let mut results = vec![result("10.0.0.1:2408", 1500, 1, 1)];
+        rank(&mut results, &formula, LatencyUnit::Us);
+        assert_eq!(results[0].score, Some(1.5));
+    }
+
+    #[test]
+    fn weighted_score_penalizes_loss() {
+        // The default --weight-* values
+        let weights = Weights {
+            latency: 1.0,
+            loss: 200.0,
+            jitter: 0.5,
+        };
+        let mut results = vec![
+            result("10.0.0.1:2408", 20, 9, 10),
+            result("10.0.0.2:2408", 90, 10, 10),
+        ];
+        rank_weighted(&mut results, &weights, LatencyUnit::Ms);
+
+        assert_eq!(results[0].addr, "10.0.0.2:2408".parse().unwrap());
+        assert_eq!(results[0].score, Some(90.0));
+        assert_eq!(results[1].score, Some(20.0 + 10.0 * 200.0));
+    }
+
+    #[test]
+    fn parse_formula_rejects_unknown_variables() {
+        let e = parse_formula("latency + rtt").unwrap_err();
+        assert!(
+            e.ends_with("The variables are latency, loss and jitter."),
+            "{e}"
+        );
+    }
+}
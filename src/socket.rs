@@ -0,0 +1,231 @@
+use crate::args::LatencyUnit;
+use log::debug;
+use socket2::{Domain, Protocol, SockRef, Socket, Type};
+use std::io::{self, Read};
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::Once;
+use tokio::io::Interest;
+use tokio::net::UdpSocket;
+
+/// Options applied to every probe socket.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct SocketOptions {
+    /// `SO_RCVBUF` in bytes, `0` keeps the OS default
+    pub(crate) recv_buf_size: usize,
+    /// `SO_SNDBUF` in bytes, `0` keeps the OS default
+    pub(crate) send_buf_size: usize,
+    /// `SO_MARK` for policy routing, only supported on Linux
+    pub(crate) mark: Option<u32>,
+    /// `SO_PRIORITY` for QoS, only supported on Linux
+    pub(crate) priority: Option<u32>,
+    /// `SO_BINDTODEVICE` interface name, only supported on Linux
+    pub(crate) bind_device: Option<String>,
+    /// Connect to the probed address, so the kernel drops datagrams from any
+    /// other source and reports ICMP errors
+    pub(crate) connect: bool,
+    /// Unit the round trip of a probe is measured in
+    pub(crate) latency_unit: LatencyUnit,
+    /// Accepted response sizes in bytes, only the exact handshake response
+    /// size without
+    pub(crate) response_size: Option<(usize, usize)>,
+}
+
+/// Logs the effective buffer sizes once, they are the same for every socket.
+static LOG_BUFFER_SIZES: Once = Once::new();
+
+/// Bind a UDP socket for probing `addr` from `source_port`, `0` lets the OS
+/// pick one.
+pub(crate) fn bind(
+    addr: &SocketAddr,
+    source_port: u16,
+    options: &SocketOptions,
+) -> io::Result<UdpSocket> {
+    let (domain, bind_addr) = match addr {
+        SocketAddr::V4(_) => (
+            Domain::IPV4,
+            SocketAddr::new(Ipv4Addr::UNSPECIFIED.into(), source_port),
+        ),
+        SocketAddr::V6(_) => (
+            Domain::IPV6,
+            SocketAddr::new(Ipv6Addr::UNSPECIFIED.into(), source_port),
+        ),
+    };
+
+    let socket = Socket::new(domain, Type::DGRAM, Some(Protocol::UDP))?;
+    #[cfg(target_os = "linux")]
+    if let Some(mark) = options.mark {
+        socket.set_mark(mark)?;
+    }
+    #[cfg(target_os = "linux")]
+    if let Some(priority) = options.priority {
+        socket.set_priority(priority)?;
+    }
+    #[cfg(target_os = "linux")]
+    if let Some(device) = &options.bind_device {
+        socket.bind_device(Some(device.as_bytes()))?;
+    }
+    if options.recv_buf_size > 0 {
+        socket.set_recv_buffer_size(options.recv_buf_size)?;
+    }
+    if options.send_buf_size > 0 {
+        socket.set_send_buffer_size(options.send_buf_size)?;
+    }
+    LOG_BUFFER_SIZES.call_once(|| {
+        // Linux doubles the requested sizes to leave room for bookkeeping
+        if options.recv_buf_size > 0 {
+            match socket.recv_buffer_size() {
+                Ok(actual) => debug!(
+                    "Requested a {} byte receive buffer, the OS set {actual} bytes",
+                    options.recv_buf_size
+                ),
+                Err(e) => debug!("Could not read back the receive buffer size: {e}"),
+            }
+        }
+        if options.send_buf_size > 0 {
+            match socket.send_buffer_size() {
+                Ok(actual) => debug!(
+                    "Requested a {} byte send buffer, the OS set {actual} bytes",
+                    options.send_buf_size
+                ),
+                Err(e) => debug!("Could not read back the send buffer size: {e}"),
+            }
+        }
+    });
+    socket.set_nonblocking(true)?;
+    socket.bind(&bind_addr.into())?;
+    if options.connect {
+        socket.connect(&(*addr).into())?;
+    }
+
+    UdpSocket::from_std(socket.into())
+}
+
+/// Fail early, with a clear error, if `options` cannot be applied.
+pub(crate) fn check(options: &SocketOptions) -> anyhow::Result<()> {
+    #[cfg(not(target_os = "linux"))]
+    if options.mark.is_some() {
+        debug!("--socket-mark is only supported on Linux, ignoring it");
+    }
+    #[cfg(not(target_os = "linux"))]
+    if options.priority.is_some() {
+        debug!("--socket-priority is only supported on Linux, ignoring it");
+    }
+    #[cfg(not(target_os = "linux"))]
+    if options.bind_device.is_some() {
+        debug!("--bind-device is only supported on Linux, ignoring it");
+    }
+
+    let probe = SocketAddr::new(Ipv4Addr::LOCALHOST.into(), 0);
+    bind(
+        &probe,
+        0,
+        &SocketOptions {
+            connect: false,
+            ..options.clone()
+        },
+    )
+    .map_err(|e| {
+        let hint = match e.kind() {
+            io::ErrorKind::PermissionDenied if options.mark.is_some() => {
+                ", --socket-mark needs CAP_NET_ADMIN"
+            }
+            io::ErrorKind::PermissionDenied if options.bind_device.is_some() => {
+                ", --bind-device needs CAP_NET_RAW"
+            }
+            _ => "",
+        };
+        anyhow::Error::new(e).context(format!("Failed to set up a probe socket{hint}"))
+    })?;
+    Ok(())
+}
+
+/// Set the Don't Fragment bit on everything `socket` sends, so datagrams
+/// larger than the path MTU fail instead of being fragmented.
+#[cfg(target_os = "linux")]
+pub(crate) fn set_dont_fragment(socket: &UdpSocket, ipv6: bool) -> io::Result<()> {
+    use std::os::fd::AsRawFd;
+
+    let (level, name, value) = if ipv6 {
+        (
+            libc::IPPROTO_IPV6,
+            libc::IPV6_MTU_DISCOVER,
+            libc::IPV6_PMTUDISC_DO,
+        )
+    } else {
+        (
+            libc::IPPROTO_IP,
+            libc::IP_MTU_DISCOVER,
+            libc::IP_PMTUDISC_DO,
+        )
+    };
+    // SAFETY: the descriptor is open for the lifetime of `socket` and
+    // `value` outlives the call
+    let ret = unsafe {
+        libc::setsockopt(
+            socket.as_raw_fd(),
+            level,
+            name,
+            (&raw const value).cast(),
+            size_of_val(&value) as libc::socklen_t,
+        )
+    };
+    if ret == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Index of the network interface called `name`, the scope ID of its
+/// link-local IPv6 addresses.
+#[cfg(target_os = "linux")]
+pub(crate) fn interface_index(name: &str) -> io::Result<u32> {
+    let name =
+        std::ffi::CString::new(name).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    // SAFETY: `name` is a valid NUL-terminated string for the whole call
+    match unsafe { libc::if_nametoindex(name.as_ptr()) } {
+        0 => Err(io::Error::last_os_error()),
+        index => Ok(index),
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+pub(crate) fn interface_index(_name: &str) -> io::Result<u32> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "interface names are only resolved on Linux",
+    ))
+}
+
+/// Receive on a connected socket, returning pending socket errors too.
+///
+/// An ICMP port unreachable only raises the error readiness of the socket,
+/// which `UdpSocket::recv` never waits for.
+pub(crate) async fn recv(socket: &UdpSocket, buf: &mut [u8]) -> io::Result<usize> {
+    socket
+        .async_io(Interest::READABLE | Interest::ERROR, || {
+            (&*SockRef::from(socket)).read(buf)
+        })
+        .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn bind_sets_the_receive_buffer() {
+        let options = SocketOptions {
+            recv_buf_size: 8 * 1024,
+            ..SocketOptions::default()
+        };
+        let addr = SocketAddr::new(Ipv4Addr::LOCALHOST.into(), 2408);
+        let socket = bind(&addr, 0, &options).unwrap();
+
+        let actual = SockRef::from(&socket).recv_buffer_size().unwrap();
+        // Linux doubles the requested size, and defaults to far more
+        #[cfg(target_os = "linux")]
+        assert_eq!(actual, options.recv_buf_size * 2);
+        #[cfg(not(target_os = "linux"))]
+        assert!(actual >= options.recv_buf_size, "{actual}");
+    }
+}
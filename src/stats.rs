@@ -0,0 +1,163 @@
+use rand::Rng;
+use rand::seq::IndexedRandom;
+
+/// Arithmetic mean of `samples`, `0.0` when empty.
+pub(crate) fn mean(samples: &[f64]) -> f64 {
+    if samples.is_empty() {
+        return 0.0;
+    }
+    samples.iter().sum::<f64>() / samples.len() as f64
+}
+
+/// Population standard deviation of `samples`, `0.0` when empty.
+pub(crate) fn std_dev(samples: &[f64]) -> f64 {
+    if samples.is_empty() {
+        return 0.0;
+    }
+    let mean = mean(samples);
+    let variance = samples.iter().map(|x| (x - mean).powi(2)).sum::<f64>() / samples.len() as f64;
+    variance.sqrt()
+}
+
+/// Coefficient of variation (standard deviation relative to the mean).
+///
+/// Returns `0.0` when the mean is zero to avoid dividing by it.
+pub(crate) fn coefficient_of_variation(samples: &[f64]) -> f64 {
+    let mean = mean(samples);
+    if mean == 0.0 {
+        return 0.0;
+    }
+    std_dev(samples) / mean
+}
+
+/// Mean absolute difference between consecutive `samples`, `0.0` with fewer
+/// than two.
+pub(crate) fn ping_variance(samples: &[f64]) -> f64 {
+    if samples.len() < 2 {
+        return 0.0;
+    }
+    let diffs: Vec<f64> = samples.windows(2).map(|w| (w[1] - w[0]).abs()).collect();
+    mean(&diffs)
+}
+
+/// Whether `samples` split at the median into a lower and an upper half
+/// whose means are more than `gap` apart. Never with fewer than 2 samples.
+pub(crate) fn is_bimodal(samples: &[f64], gap: f64) -> bool {
+    if samples.len() < 2 {
+        return false;
+    }
+    let mut sorted = samples.to_vec();
+    sorted.sort_by(f64::total_cmp);
+    let (lower, upper) = sorted.split_at(sorted.len() / 2);
+    mean(upper) - mean(lower) > gap
+}
+
+/// Exponentially weighted moving average of `samples`, oldest first, where
+/// each one has weight `alpha` against the average before it. `0.0` when
+/// empty.
+pub(crate) fn ewma(samples: &[f64], alpha: f64) -> f64 {
+    let mut samples = samples.iter();
+    let Some(&first) = samples.next() else {
+        return 0.0;
+    };
+    samples.fold(first, |prev, &new| alpha * new + (1.0 - alpha) * prev)
+}
+
+/// Median of `samples`, `0.0` when empty.
+pub(crate) fn median(samples: &[f64]) -> f64 {
+    if samples.is_empty() {
+        return 0.0;
+    }
+    let mut sorted = samples.to_vec();
+    sorted.sort_by(f64::total_cmp);
+    let mid = sorted.len() / 2;
+    if sorted.len().is_multiple_of(2) {
+        (sorted[mid - 1] + sorted[mid]) / 2.0
+    } else {
+        sorted[mid]
+    }
+}
+
+/// Drop the samples outside Tukey's fences, `1.5` IQR below the first or
+/// above the third quartile, leaving the rest sorted.
+///
+/// Does nothing with fewer than 4 samples.
+pub(crate) fn remove_outliers(samples: &mut Vec<u128>) {
+    if samples.len() < 4 {
+        return;
+    }
+    samples.sort_unstable();
+    let sorted: Vec<f64> = samples.iter().map(|&s| s as f64).collect();
+    let q1 = quantile(&sorted, 0.25);
+    let q3 = quantile(&sorted, 0.75);
+    let iqr = q3 - q1;
+    let (low, high) = (q1 - 1.5 * iqr, q3 + 1.5 * iqr);
+    samples.retain(|&s| (low..=high).contains(&(s as f64)));
+}
+
+/// The `q` quantile of non-empty `sorted` samples, interpolating linearly
+/// between the closest ranks.
+pub(crate) fn quantile(sorted: &[f64], q: f64) -> f64 {
+    let rank = q * (sorted.len() - 1) as f64;
+    let (lower, upper) = (rank.floor() as usize, rank.ceil() as usize);
+    let weight = rank - lower as f64;
+    sorted[lower] * (1.0 - weight) + sorted[upper] * weight
+}
+
+/// Percentile bootstrap confidence interval of the mean of `samples`, from
+/// `resamples` resamples with replacement. `confidence` is a fraction, e.g.
+/// `0.95`.
+///
+/// `samples` must not be empty.
+pub(crate) fn bootstrap_ci(
+    samples: &[u128],
+    confidence: f64,
+    resamples: usize,
+    rng: &mut impl Rng,
+) -> (f64, f64) {
+    let mut means: Vec<f64> = (0..resamples)
+        .map(|_| {
+            let sum: u128 = (0..samples.len())
+                .map(|_| *samples.choose(rng).expect("samples is not empty"))
+                .sum();
+            sum as f64 / samples.len() as f64
+        })
+        .collect();
+    means.sort_by(f64::total_cmp);
+
+    let tail = (1.0 - confidence) / 2.0;
+    (quantile(&means, tail), quantile(&means, 1.0 - tail))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn remove_outliers_drops_beyond_the_fences() {
+        let mut samples = vec![13, 100, 11, 10, 12];
+        remove_outliers(&mut samples);
+        assert_eq!(samples, [10, 11, 12, 13]);
+    }
+
+    #[test]
+    fn remove_outliers_keeps_few_samples() {
+        let mut samples = vec![100, 1, 2];
+        remove_outliers(&mut samples);
+        assert_eq!(samples, [100, 1, 2]);
+    }
+
+    #[test]
+    fn ping_variance_averages_consecutive_changes() {
+        assert_eq!(ping_variance(&[10.0, 100.0, 10.0, 100.0]), 90.0);
+        assert_eq!(ping_variance(&[10.0, 11.0, 12.0, 13.0]), 1.0);
+        assert_eq!(ping_variance(&[10.0]), 0.0);
+    }
+
+    #[test]
+    fn is_bimodal_needs_a_gap_between_halves() {
+        assert!(is_bimodal(&[10.0, 10.0, 80.0, 80.0, 10.0], 20.0));
+        assert!(!is_bimodal(&[10.0, 12.0, 11.0], 20.0));
+        assert!(!is_bimodal(&[10.0], 0.0));
+    }
+}
@@ -0,0 +1,252 @@
+use crate::TestResult;
+use crate::stats;
+use ipnetwork::IpNetwork;
+use schemars::JsonSchema;
+use serde::Serialize;
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, BTreeSet, HashMap, VecDeque};
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+/// Prefix length used to group IPv4 addresses.
+const V4_PREFIX: u8 = 24;
+
+/// Prefix length used to group IPv6 addresses.
+const V6_PREFIX: u8 = 48;
+
+#[derive(Debug, Serialize, JsonSchema)]
+pub(crate) struct SubnetStats {
+    #[schemars(with = "String")]
+    pub(crate) subnet: IpNetwork,
+    pub(crate) tested: usize,
+    pub(crate) alive: usize,
+    pub(crate) mean_ms: f64,
+    pub(crate) median_ms: f64,
+    pub(crate) std_dev_ms: f64,
+}
+
+#[derive(Clone, Copy, Debug, Serialize, JsonSchema)]
+pub(crate) struct SubnetCount {
+    #[schemars(with = "String")]
+    pub(crate) subnet: IpNetwork,
+    pub(crate) tested: usize,
+    pub(crate) alive: usize,
+}
+
+#[derive(Debug, Serialize, JsonSchema)]
+pub(crate) struct Productivity {
+    pub(crate) most: SubnetCount,
+    pub(crate) least: SubnetCount,
+}
+
+/// The /24 (IPv4) or /48 (IPv6) network containing `ip`.
+pub(crate) fn subnet_of(ip: IpAddr) -> IpNetwork {
+    let prefix = match ip {
+        IpAddr::V4(_) => V4_PREFIX,
+        IpAddr::V6(_) => V6_PREFIX,
+    };
+    let network = IpNetwork::new(ip, prefix).expect("prefix is within range");
+    IpNetwork::new(network.network(), prefix).expect("prefix is within range")
+}
+
+/// Latency statistics of the alive addresses per subnet, ordered by subnet.
+///
+/// Every subnet that had at least one address tested is included, even when
+/// none of them responded.
+pub(crate) fn subnet_stats(tested: &[SocketAddr], alive: &[TestResult]) -> Vec<SubnetStats> {
+    let mut subnets: BTreeMap<IpNetwork, (usize, Vec<f64>)> = BTreeMap::new();
+    for addr in tested {
+        subnets.entry(subnet_of(addr.ip())).or_default().0 += 1;
+    }
+    for result in alive {
+        subnets
+            .entry(subnet_of(result.addr.ip()))
+            .or_default()
+            .1
+            .push(result.latency as f64);
+    }
+
+    subnets
+        .into_iter()
+        .map(|(subnet, (tested, latencies))| SubnetStats {
+            subnet,
+            tested,
+            alive: latencies.len(),
+            mean_ms: stats::mean(&latencies),
+            median_ms: stats::median(&latencies),
+            std_dev_ms: stats::std_dev(&latencies),
+        })
+        .collect()
+}
+
+/// The subnets with the highest and the lowest share of alive addresses,
+/// `None` unless at least two subnets were tested and they differ.
+pub(crate) fn productivity(tested: &[SocketAddr], alive: &[TestResult]) -> Option<Productivity> {
+    let counts: Vec<SubnetCount> = subnet_stats(tested, alive)
+        .into_iter()
+        .map(|stats| SubnetCount {
+            subnet: stats.subnet,
+            tested: stats.tested,
+            alive: stats.alive,
+        })
+        .collect();
+    if counts.len() < 2 {
+        return None;
+    }
+
+    // Compare alive / tested without dividing, then by the number alive
+    let by_share = |a: &&SubnetCount, b: &&SubnetCount| {
+        (a.alive * b.tested)
+            .cmp(&(b.alive * a.tested))
+            .then(a.alive.cmp(&b.alive))
+    };
+    let most = counts.iter().max_by(by_share)?;
+    let least = counts.iter().min_by(by_share)?;
+    // Nothing stands out when every subnet did equally well
+    (by_share(&most, &least) != Ordering::Equal).then_some(Productivity {
+        most: *most,
+        least: *least,
+    })
+}
+
+/// Reorder `addrs` to take one address from every subnet in turn, keeping
+/// the order within a subnet and of the subnets' first addresses.
+pub(crate) fn interleave(addrs: Vec<SocketAddr>) -> Vec<SocketAddr> {
+    let total = addrs.len();
+    let mut index: HashMap<IpNetwork, usize> = HashMap::new();
+    let mut queues: Vec<VecDeque<SocketAddr>> = Vec::new();
+    for addr in addrs {
+        let i = *index.entry(subnet_of(addr.ip())).or_insert_with(|| {
+            queues.push(VecDeque::new());
+            queues.len() - 1
+        });
+        queues[i].push_back(addr);
+    }
+
+    let mut interleaved = Vec::with_capacity(total);
+    while interleaved.len() < total {
+        for queue in &mut queues {
+            interleaved.extend(queue.pop_front());
+        }
+    }
+    interleaved
+}
+
+/// Keep only the first `limit` results of every subnet, `results` being
+/// sorted fastest first.
+pub(crate) fn limit_per_subnet(results: &mut Vec<TestResult>, limit: u64) {
+    let mut kept: HashMap<IpNetwork, u64> = HashMap::new();
+    results.retain(|result| {
+        let count = kept.entry(subnet_of(result.addr.ip())).or_default();
+        *count += 1;
+        *count <= limit
+    });
+}
+
+/// Subnets where none of the tested addresses responded, ordered by subnet.
+pub(crate) fn unreachable<'a>(
+    tested: &[SocketAddr],
+    alive: impl IntoIterator<Item = &'a TestResult>,
+) -> Vec<IpNetwork> {
+    let reachable: BTreeSet<IpNetwork> = alive
+        .into_iter()
+        .map(|result| subnet_of(result.addr.ip()))
+        .collect();
+    let tested: BTreeSet<IpNetwork> = tested.iter().map(|addr| subnet_of(addr.ip())).collect();
+    tested.difference(&reachable).copied().collect()
+}
+
+/// A subnet being probed, holding one of the [`SubnetLimiter`] permits while
+/// any of its addresses are.
+struct ActiveSubnet {
+    /// One permit, so only one address of the subnet waits for a global one
+    gate: Semaphore,
+    state: Mutex<(usize, Option<OwnedSemaphorePermit>)>,
+}
+
+/// Lets the addresses of at most a fixed number of subnets be probed at
+/// once, for `--concurrent-subnets`.
+pub(crate) struct SubnetLimiter {
+    permits: Arc<Semaphore>,
+    subnets: Mutex<HashMap<IpNetwork, Arc<ActiveSubnet>>>,
+}
+
+/// Keeps the subnet of an address active until dropped.
+pub(crate) struct SubnetGuard(Arc<ActiveSubnet>);
+
+impl SubnetLimiter {
+    pub(crate) fn new(subnets: usize) -> Self {
+        Self {
+            permits: Arc::new(Semaphore::new(subnets)),
+            subnets: Mutex::default(),
+        }
+    }
+
+    /// Wait until `subnet` is active or there is room to make it so.
+    pub(crate) async fn enter(&self, subnet: IpNetwork) -> SubnetGuard {
+        let active = Arc::clone(
+            self.subnets
+                .lock()
+                .unwrap()
+                .entry(subnet)
+                .or_insert_with(|| {
+                    Arc::new(ActiveSubnet {
+                        gate: Semaphore::new(1),
+                        state: Mutex::default(),
+                    })
+                }),
+        );
+        let _gate = active.gate.acquire().await.expect("gate is never closed");
+        {
+            let mut state = active.state.lock().unwrap();
+            if state.0 > 0 {
+                state.0 += 1;
+                return SubnetGuard(Arc::clone(&active));
+            }
+        }
+        // Nothing else can enter or leave the subnet while it has no addresses
+        // in flight and the gate is held
+        let permit = Arc::clone(&self.permits)
+            .acquire_owned()
+            .await
+            .expect("permits are never closed");
+        *active.state.lock().unwrap() = (1, Some(permit));
+        SubnetGuard(Arc::clone(&active))
+    }
+}
+
+impl Drop for SubnetGuard {
+    fn drop(&mut self) {
+        let mut state = self.0.state.lock().unwrap();
+        state.0 -= 1;
+        if state.0 == 0 {
+            state.1 = None;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn result(addr: &str, latency: u128) -> TestResult {
+        TestResult::from_samples(addr.parse().unwrap(), vec![latency], 1)
+    }
+
+    #[test]
+    fn limit_per_subnet_keeps_the_fastest_of_each() {
+        let mut results = vec![
+            result("10.0.0.1:2408", 10),
+            result("10.0.0.2:2408", 20),
+            result("10.0.1.1:2408", 30),
+            result("10.0.0.3:2408", 40),
+            result("10.0.1.2:2408", 50),
+            result("10.0.1.3:2408", 60),
+        ];
+        limit_per_subnet(&mut results, 2);
+
+        let kept: Vec<u128> = results.iter().map(|result| result.latency).collect();
+        assert_eq!(kept, [10, 20, 30, 50]);
+    }
+}
@@ -0,0 +1,272 @@
+use crate::Status;
+use anyhow::{Context, Result, bail};
+use ipnetwork::IpNetwork;
+use log::warn;
+use serde_json::Value;
+use std::fs;
+use std::iter;
+use std::net::{IpAddr, SocketAddr};
+use std::path::Path;
+use std::str::FromStr;
+
+/// Non-empty lines of `path` with `#` comments stripped, with their line number.
+fn entries(path: &Path) -> Result<Vec<(usize, String)>> {
+    let contents =
+        fs::read_to_string(path).with_context(|| format!("Failed to read {}", path.display()))?;
+
+    Ok(contents
+        .lines()
+        .enumerate()
+        .filter_map(|(i, line)| {
+            let entry = line.split('#').next().unwrap_or_default().trim();
+            (!entry.is_empty()).then(|| (i + 1, entry.to_string()))
+        })
+        .collect())
+}
+
+/// Read a file of IPs or CIDRs, one per line. Invalid entries are skipped with a warning.
+pub(crate) fn read_networks(path: &Path) -> Result<Vec<IpNetwork>> {
+    Ok(entries(path)?
+        .into_iter()
+        .filter_map(|(line, entry)| {
+            entry
+                .parse()
+                .inspect_err(|e| warn!("Ignoring {}:{line} {entry:?}: {e}", path.display()))
+                .ok()
+        })
+        .collect())
+}
+
+/// Read a file of CIDR ranges to scan, one per line. Unlike
+/// [`read_networks`] an invalid entry or an empty file is an error.
+pub(crate) fn read_ranges<N>(path: &Path) -> Result<Vec<N>>
+where
+    N: FromStr,
+    N::Err: std::fmt::Display,
+{
+    let ranges = entries(path)?
+        .into_iter()
+        .map(|(line, entry)| {
+            entry.parse().map_err(|e| {
+                anyhow::anyhow!("Invalid CIDR {entry:?} at {}:{line}: {e}", path.display())
+            })
+        })
+        .collect::<Result<Vec<N>>>()?;
+    if ranges.is_empty() {
+        bail!("No CIDR ranges to test in {}", path.display());
+    }
+    Ok(ranges)
+}
+
+/// One entry of a whitelist file.
+pub(crate) enum Target {
+    /// An exact `ip:port`
+    Addr(SocketAddr),
+    /// A bare IP or CIDR, tested on random ports
+    Network(IpNetwork),
+}
+
+impl FromStr for Target {
+    type Err = String;
+
+    fn from_str(entry: &str) -> Result<Self, Self::Err> {
+        if let Ok(addr) = entry.parse() {
+            return Ok(Target::Addr(addr));
+        }
+        entry
+            .parse()
+            .map(Target::Network)
+            .map_err(|e| e.to_string())
+    }
+}
+
+/// Read a file of IPs, `ip:port` pairs or CIDRs, one per line. Invalid entries
+/// are skipped with a warning.
+pub(crate) fn read_targets(path: &Path) -> Result<Vec<Target>> {
+    Ok(entries(path)?
+        .into_iter()
+        .filter_map(|(line, entry)| {
+            entry
+                .parse()
+                .inspect_err(|e| warn!("Ignoring {}:{line} {entry:?}: {e}", path.display()))
+                .ok()
+        })
+        .collect())
+}
+
+/// The first `amount` addresses of `targets`, in file order.
+///
+/// Addresses without a port get one from `random_port`. Addresses for which
+/// `skip` returns true are left out.
+pub(crate) fn expand(
+    targets: &[Target],
+    amount: usize,
+    mut random_port: impl FnMut() -> u16,
+    skip: impl Fn(IpAddr) -> bool,
+) -> Vec<SocketAddr> {
+    targets
+        .iter()
+        .flat_map(
+            |target| -> Box<dyn Iterator<Item = (IpAddr, Option<u16>)>> {
+                match target {
+                    Target::Addr(addr) => Box::new(iter::once((addr.ip(), Some(addr.port())))),
+                    Target::Network(network) => Box::new(network.iter().map(|ip| (ip, None))),
+                }
+            },
+        )
+        .filter(|(ip, _)| !skip(*ip))
+        .take(amount)
+        .map(|(ip, port)| {
+            let port = port.unwrap_or_else(&mut random_port);
+            SocketAddr::new(ip, port)
+        })
+        .collect()
+}
+
+/// Read the working addresses saved by `--output-file`.
+///
+/// The format is picked by extension: `.json` reads the `results` of a JSON
+/// report, `.csv` reads the `addr` column of fields separated by `csv_sep`,
+/// `.tsv` the same separated by tabs, and anything else reads the first word
+/// of every line.
+pub(crate) fn read_saved(path: &Path, csv_sep: char) -> Result<Vec<Target>> {
+    let extension = path.extension().and_then(|ext| ext.to_str());
+    let addrs: Vec<String> = match extension {
+        Some("json") => {
+            let contents = fs::read_to_string(path)
+                .with_context(|| format!("Failed to read {}", path.display()))?;
+            let report: Value = serde_json::from_str(&contents)
+                .with_context(|| format!("Failed to parse {}", path.display()))?;
+            report["results"]
+                .as_array()
+                .with_context(|| format!("{} has no results", path.display()))?
+                .iter()
+                .filter_map(|result| result["addr"].as_str().map(str::to_string))
+                .collect()
+        }
+        Some(extension @ ("csv" | "tsv")) => {
+            let csv_sep = if extension == "tsv" { '\t' } else { csv_sep };
+            let mut rows = entries(path)?.into_iter();
+            let header = rows.next().map(|(_, header)| header).unwrap_or_default();
+            let columns = split_csv_row(&header, csv_sep);
+            let addr = columns
+                .iter()
+                .position(|name| name == "addr")
+                .with_context(|| format!("{} has no addr column", path.display()))?;
+            let status = columns.iter().position(|name| name == "status");
+            // Only the working addresses are re-tested, like with JSON reports
+            rows.map(|(_, row)| split_csv_row(&row, csv_sep))
+                .filter(|fields| {
+                    status.is_none_or(|i| fields.get(i).is_none_or(|s| s == Status::Alive.as_str()))
+                })
+                .filter_map(|mut fields| (addr < fields.len()).then(|| fields.swap_remove(addr)))
+                .collect()
+        }
+        _ => entries(path)?
+            .into_iter()
+            .filter_map(|(_, entry)| entry.split_whitespace().next().map(str::to_string))
+            .collect(),
+    };
+
+    Ok(addrs
+        .into_iter()
+        .filter_map(|addr| {
+            addr.parse()
+                .map(Target::Addr)
+                .inspect_err(|e| warn!("Ignoring {addr:?} in {}: {e}", path.display()))
+                .ok()
+        })
+        .collect())
+}
+
+/// The fields of a CSV `row` separated by `sep`, with quoted fields unquoted.
+fn split_csv_row(row: &str, sep: char) -> Vec<String> {
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = row.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            }
+            '"' => quoted = !quoted,
+            c if c == sep && !quoted => fields.push(std::mem::take(&mut field)),
+            c => field.push(c),
+        }
+    }
+    fields.push(field);
+    fields
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::args::LatencyUnit;
+    use crate::output;
+    use ipnetwork::Ipv4Network;
+    use std::path::PathBuf;
+
+    /// Write `contents` to a file named `name` in a fresh temporary directory.
+    fn write_temp(name: &str, contents: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("warp-targets-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(name);
+        fs::write(&path, contents).unwrap();
+        path
+    }
+
+    fn addrs(targets: &[Target]) -> Vec<SocketAddr> {
+        targets
+            .iter()
+            .map(|target| match target {
+                Target::Addr(addr) => *addr,
+                Target::Network(network) => panic!("unexpected range {network}"),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn read_ranges_skips_comments() {
+        let path = write_temp(
+            "ranges.txt",
+            "# Cloudflare\n162.159.192.0/24\n\n162.159.193.0/24 # WARP\n",
+        );
+        let ranges: Vec<Ipv4Network> = read_ranges(&path).unwrap();
+        assert_eq!(
+            ranges,
+            [
+                "162.159.192.0/24".parse().unwrap(),
+                "162.159.193.0/24".parse().unwrap()
+            ]
+        );
+    }
+
+    #[test]
+    fn read_ranges_rejects_invalid_lines() {
+        let path = write_temp("invalid.txt", "162.159.192.0/24\n162.159.300.0/24\n");
+        let e = read_ranges::<Ipv4Network>(&path).unwrap_err().to_string();
+        assert!(e.contains("\"162.159.300.0/24\""), "{e}");
+        assert!(e.contains("invalid.txt:2"), "{e}");
+    }
+
+    #[test]
+    fn read_ranges_rejects_empty_files() {
+        let path = write_temp("empty.txt", "# nothing yet\n");
+        let e = read_ranges::<Ipv4Network>(&path).unwrap_err().to_string();
+        assert!(e.starts_with("No CIDR ranges to test"), "{e}");
+    }
+
+    #[test]
+    fn read_saved_reads_tsv_results() {
+        let mut tsv = output::csv_header('\t', LatencyUnit::Ms, []);
+        tsv.push_str("162.159.192.1:2408\t12\t0.0\t1.5\t2.0\talive\n");
+        tsv.push_str("162.159.192.2:2408\t\t\t\t\ttimeout\n");
+        let path = write_temp("results.tsv", &tsv);
+
+        // The separator of CSV files does not apply to TSV ones
+        let targets = read_saved(&path, ';').unwrap();
+        assert_eq!(addrs(&targets), ["162.159.192.1:2408".parse().unwrap()]);
+    }
+}
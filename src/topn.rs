@@ -0,0 +1,81 @@
+use std::collections::BinaryHeap;
+
+/// Keeps the `capacity` smallest items pushed into it.
+///
+/// Backed by a max-heap, so the largest kept item is evicted in `O(log n)`
+/// when a smaller one arrives, without ever sorting everything seen.
+pub(crate) struct BoundedHeap<T: Ord> {
+    heap: BinaryHeap<T>,
+    capacity: usize,
+}
+
+impl<T: Ord> BoundedHeap<T> {
+    pub(crate) fn new(capacity: usize) -> Self {
+        BoundedHeap {
+            heap: BinaryHeap::with_capacity(capacity + 1),
+            capacity,
+        }
+    }
+
+    /// A heap that keeps everything pushed into it, sorted on the way out.
+    pub(crate) fn unbounded() -> Self {
+        BoundedHeap {
+            heap: BinaryHeap::new(),
+            capacity: usize::MAX,
+        }
+    }
+
+    /// Add `item` if it is among the `capacity` smallest so far, returning
+    /// whichever item no longer is.
+    pub(crate) fn push(&mut self, item: T) -> Option<T> {
+        if self.heap.len() < self.capacity {
+            self.heap.push(item);
+            None
+        } else if self.heap.peek().is_some_and(|largest| item < *largest) {
+            let evicted = self.heap.pop();
+            self.heap.push(item);
+            evicted
+        } else {
+            Some(item)
+        }
+    }
+
+    /// The kept items, smallest first.
+    pub(crate) fn into_sorted(self) -> Vec<T> {
+        self.heap.into_sorted_vec()
+    }
+
+    /// The kept items, smallest first.
+    #[cfg(feature = "tui")]
+    pub(crate) fn sorted(&self) -> Vec<&T> {
+        let mut items: Vec<&T> = self.heap.iter().collect();
+        items.sort();
+        items
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_the_smallest() {
+        let mut heap = BoundedHeap::new(3);
+        let evicted: Vec<Option<u32>> = [50, 10, 40, 30, 60, 20]
+            .into_iter()
+            .map(|item| heap.push(item))
+            .collect();
+
+        assert_eq!(evicted, [None, None, None, Some(50), Some(60), Some(40)]);
+        assert_eq!(heap.into_sorted(), [10, 20, 30]);
+    }
+
+    #[test]
+    fn unbounded_keeps_everything() {
+        let mut heap = BoundedHeap::unbounded();
+        for item in [3, 1, 2] {
+            assert_eq!(heap.push(item), None);
+        }
+        assert_eq!(heap.into_sorted(), [1, 2, 3]);
+    }
+}
@@ -0,0 +1,211 @@
+use crate::args::LatencyUnit;
+use crate::topn::BoundedHeap;
+use ratatui::Frame;
+use ratatui::crossterm::event::{self, Event as TermEvent, KeyCode, KeyEventKind};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Color, Style};
+use ratatui::widgets::{Bar, BarChart, Block, Gauge, List, ListItem};
+use std::collections::VecDeque;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender, TryRecvError};
+use std::time::{Duration, Instant};
+use tokio::task::JoinHandle;
+
+/// How often the dashboard redraws and checks for key presses.
+const TICK: Duration = Duration::from_millis(100);
+
+/// Number of probe log lines kept for the top panel.
+const LOG_LINES: usize = 500;
+
+/// Number of addresses shown on the leaderboard.
+const LEADERBOARD_SIZE: usize = 10;
+
+/// Progress reported by the scan workers to the dashboard.
+pub(crate) enum Event {
+    /// A single probe finished, with its latency if it got a valid response
+    Probe {
+        addr: SocketAddr,
+        latency: Option<u128>,
+    },
+    /// Every attempt to an address finished, with its average latency if alive
+    Done {
+        addr: SocketAddr,
+        latency: Option<u128>,
+    },
+}
+
+/// Start the dashboard on a blocking thread, showing latencies in `unit`.
+///
+/// Pressing `q` sets `cancel`. The dashboard closes and restores the terminal
+/// once every sender of the returned channel is dropped.
+pub(crate) fn spawn(
+    total_probes: u64,
+    unit: LatencyUnit,
+    cancel: Arc<AtomicBool>,
+) -> (Sender<Event>, JoinHandle<io::Result<()>>) {
+    let (sender, receiver) = mpsc::channel();
+    let handle = tokio::task::spawn_blocking(move || {
+        // Log lines would tear through the alternate screen
+        let max_level = log::max_level();
+        log::set_max_level(log::LevelFilter::Off);
+
+        let mut terminal = ratatui::init();
+        let result = run(
+            &mut terminal,
+            receiver,
+            State::new(total_probes, unit),
+            &cancel,
+        );
+        ratatui::restore();
+
+        log::set_max_level(max_level);
+        result
+    });
+    (sender, handle)
+}
+
+fn run(
+    terminal: &mut ratatui::DefaultTerminal,
+    receiver: Receiver<Event>,
+    mut state: State,
+    cancel: &AtomicBool,
+) -> io::Result<()> {
+    loop {
+        loop {
+            match receiver.try_recv() {
+                Ok(event) => state.apply(event),
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => return Ok(()),
+            }
+        }
+
+        // Resizes need no special handling, every draw uses the current size
+        terminal.draw(|frame| state.draw(frame))?;
+
+        if event::poll(TICK)?
+            && let TermEvent::Key(key) = event::read()?
+            && key.kind == KeyEventKind::Press
+            && key.code == KeyCode::Char('q')
+            && !cancel.swap(true, Ordering::Relaxed)
+        {
+            state.log("Stopping, waiting for in-flight probes...".to_string());
+        }
+    }
+}
+
+struct State {
+    log: VecDeque<String>,
+    /// Fastest addresses so far, as `(latency, addr)`
+    leaderboard: BoundedHeap<(u128, SocketAddr)>,
+    probed: u64,
+    total_probes: u64,
+    start: Instant,
+    unit: &'static str,
+}
+
+impl State {
+    fn new(total_probes: u64, unit: LatencyUnit) -> Self {
+        State {
+            log: VecDeque::with_capacity(LOG_LINES),
+            leaderboard: BoundedHeap::new(LEADERBOARD_SIZE),
+            probed: 0,
+            total_probes,
+            start: Instant::now(),
+            unit: unit.label(),
+        }
+    }
+
+    fn log(&mut self, line: String) {
+        if self.log.len() == LOG_LINES {
+            self.log.pop_front();
+        }
+        self.log.push_back(line);
+    }
+
+    fn apply(&mut self, event: Event) {
+        match event {
+            Event::Probe { addr, latency } => {
+                self.probed += 1;
+                match latency {
+                    Some(latency) => self.log(format!("{addr} - {latency} {}", self.unit)),
+                    None => self.log(format!("{addr} - no response")),
+                }
+            }
+            Event::Done {
+                addr,
+                latency: Some(latency),
+            } => {
+                self.leaderboard.push((latency, addr));
+            }
+            Event::Done { latency: None, .. } => {}
+        }
+    }
+
+    /// Estimated time left, extrapolated from the rate so far.
+    fn eta(&self) -> Option<Duration> {
+        if self.probed == 0 {
+            return None;
+        }
+        let remaining = self.total_probes.saturating_sub(self.probed);
+        Some(
+            self.start
+                .elapsed()
+                .mul_f64(remaining as f64 / self.probed as f64),
+        )
+    }
+
+    fn draw(&self, frame: &mut Frame) {
+        let [log_area, chart_area, progress_area] = Layout::vertical([
+            Constraint::Min(3),
+            Constraint::Length(LEADERBOARD_SIZE as u16 + 2),
+            Constraint::Length(3),
+        ])
+        .areas(frame.area());
+
+        let visible = usize::from(log_area.height.saturating_sub(2));
+        let lines: Vec<ListItem> = self
+            .log
+            .iter()
+            .skip(self.log.len().saturating_sub(visible))
+            .map(|line| ListItem::new(line.as_str()))
+            .collect();
+        frame.render_widget(
+            List::new(lines).block(Block::bordered().title("Probes")),
+            log_area,
+        );
+
+        let bars: Vec<Bar> = self
+            .leaderboard
+            .sorted()
+            .into_iter()
+            .map(|(latency, addr)| {
+                Bar::with_label(addr.to_string(), *latency as u64)
+                    .text_value(format!("{latency} {}", self.unit))
+            })
+            .collect();
+        frame.render_widget(
+            BarChart::horizontal(bars)
+                .block(Block::bordered().title(format!("Top {LEADERBOARD_SIZE}")))
+                .bar_width(1)
+                .bar_gap(0)
+                .bar_style(Style::new().fg(Color::Cyan)),
+            chart_area,
+        );
+
+        let eta = match self.eta() {
+            Some(eta) => format!("ETA {}s", eta.as_secs()),
+            None => "ETA --".to_string(),
+        };
+        frame.render_widget(
+            Gauge::default()
+                .block(Block::bordered().title("Progress (q to stop)"))
+                .gauge_style(Style::new().fg(Color::Green))
+                .ratio((self.probed as f64 / self.total_probes.max(1) as f64).min(1.0))
+                .label(format!("{}/{} {eta}", self.probed, self.total_probes)),
+            progress_area,
+        );
+    }
+}
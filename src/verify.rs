@@ -0,0 +1,70 @@
+use crate::socket::SocketOptions;
+use crate::{TestResult, speedtest};
+use futures::{StreamExt, stream};
+use log::{info, warn};
+use std::time::Duration;
+use tokio::time::sleep;
+
+/// Least number of verification probes per result.
+const MIN_PROBES: u32 = 10;
+
+/// Probe every result again `max(attempts * 3, 10)` times and replace its
+/// latency with the average of the new probes. Results more than
+/// `threshold_pct` percent slower than in the scan are marked unstable.
+/// `results` keeps its order, ranking them again is up to the caller.
+///
+/// With a `source_port` the results are verified one at a time from it.
+pub(crate) async fn verify(
+    results: &mut [TestResult],
+    attempts: u32,
+    delay: Duration,
+    threshold_pct: f64,
+    source_port: Option<u16>,
+    options: &SocketOptions,
+) {
+    let concurrency = if source_port.is_some() {
+        1
+    } else {
+        results.len().max(1)
+    };
+    let probes = (attempts * 3).max(MIN_PROBES);
+    info!(
+        "Verifying the top {} IPs with {probes} probes each",
+        results.len()
+    );
+
+    let averages: Vec<_> = stream::iter(results.iter().map(|result| async move {
+        let mut latencies = Vec::new();
+        for sent in 0..probes {
+            if sent > 0 && !delay.is_zero() {
+                sleep(delay).await;
+            }
+            if let Ok(probe) = speedtest(&result.addr, source_port.unwrap_or(0), options).await {
+                latencies.push(probe.latency);
+            }
+        }
+        (!latencies.is_empty()).then(|| latencies.iter().sum::<u128>() / latencies.len() as u128)
+    }))
+    .buffered(concurrency)
+    .collect()
+    .await;
+
+    for (result, average) in results.iter_mut().zip(averages) {
+        let Some(average) = average else {
+            warn!("{} did not respond during verification", result.addr);
+            result.unstable = Some(true);
+            continue;
+        };
+        let unstable = average as f64 > result.latency as f64 * (1.0 + threshold_pct / 100.0);
+        if unstable {
+            warn!(
+                "{} degraded from {} {unit} to {average} {unit} during verification",
+                result.addr,
+                result.latency,
+                unit = options.latency_unit.label()
+            );
+        }
+        result.latency = average;
+        result.unstable = Some(unstable);
+    }
+}
@@ -0,0 +1,38 @@
+use anyhow::Result;
+use log::{info, warn};
+use reqwest::header::CONTENT_TYPE;
+use std::time::Duration;
+
+/// Timeout of the whole HTTP request.
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// POST the JSON `body` to `url`, with the `(name, value)` header if given.
+///
+/// Failures are only logged, a broken webhook does not fail the scan.
+pub(crate) async fn post(url: &str, header: Option<&(String, String)>, body: String) {
+    match send(url, header, body).await {
+        Ok(()) => info!("Posted results to {url}"),
+        Err(e) => warn!("Failed to post results: {e:#}"),
+    }
+}
+
+async fn send(url: &str, header: Option<&(String, String)>, body: String) -> Result<()> {
+    let client = reqwest::Client::builder()
+        .timeout(WEBHOOK_TIMEOUT)
+        .user_agent(concat!(
+            env!("CARGO_PKG_NAME"),
+            "/",
+            env!("CARGO_PKG_VERSION")
+        ))
+        .build()?;
+
+    let mut request = client
+        .post(url)
+        .header(CONTENT_TYPE, "application/json")
+        .body(body);
+    if let Some((name, value)) = header {
+        request = request.header(name, value);
+    }
+    request.send().await?.error_for_status()?;
+    Ok(())
+}